@@ -1,8 +1,25 @@
 SRPC_HOME=/home/gaowh/onlive/secrpc
 
+# two nodes on the same host: node 2 serves, node 1 connects to it 
+cargo build --bin srpc || exit 1
+
 cargo run --bin srpc \
     -- \
-    --peer http://127.0.0.1:9000 \
+    --id 2 \
+    --listen 0.0.0.0:9000 \
     --mrsize 256 \
-    >srpc.log 2>&1 
+    >srpc-2.log 2>&1 &
+SERVER_PID=$!
+trap "kill $SERVER_PID" EXIT
 
+# let the server come up 
+sleep 1
+
+cargo run --bin srpc \
+    -- \
+    --id 1 \
+    --listen 0.0.0.0:9001 \
+    --peer http://127.0.0.1:9000 \
+    --peer-id 2 \
+    --mrsize 256 \
+    >srpc-1.log 2>&1 
@@ -3,6 +3,16 @@ use tracing::{info};
 #[allow(unused_imports)]
 use argparse::{ArgumentParser, Store, StoreTrue, List};
 
+use crate::core::srpc_session_pool::RpcSessionSelect;
+use crate::core::network::srpc_mr_pool::RpcMrClass;
+use crate::core::network::srpc_poll::RpcPollMode;
+use crate::core::network::srpc_device::RpcRail;
+use crate::core::network::srpc_peer_meta::SRPC_PROTO_VERSIONS;
+use crate::core::network::srpc_core_network::IBVERBS_GRH_LEN;
+use crate::core::srpc_frag::RpcLargeMsgMode;
+use crate::core::srpc_atomic::RpcAtomicMode;
+use crate::core::srpc_credit::SRPC_RECV_RESERVE;
+
 use once_cell::sync::OnceCell;
 pub static RPC_CONF: once_cell::sync::OnceCell<RpcConf> = 
     OnceCell::new();
@@ -10,16 +20,157 @@ pub static RPC_CONF: once_cell::sync::OnceCell<RpcConf> =
 #[derive(Debug, Clone)]
 pub struct RpcConf {
     pub rmt_grpc_uri: Vec<String>, 
+    pub rmt_ids: Vec<u32>, 
+    pub loc_grpc_addr: String, 
     pub loc_mr_size: u32, 
+    pub loc_id: u32, 
+    pub proto_versions: Vec<u32>, 
+    pub qp_per_peer: u32, 
+    pub qp_select: RpcSessionSelect, 
+    pub max_sessions: u32, 
+    pub mr_classes: Vec<RpcMrClass>, 
+    pub max_inflight: u32, 
+    pub max_inflight_session: u32, 
+    pub recv_depth: u32, 
+    pub srq: bool, 
+    pub srq_depth: u32, 
+    pub poll_mode: RpcPollMode, 
+    pub poll_spin_us: u64, 
+    pub poll_thread: bool, 
+    pub poll_cores: Vec<usize>, 
+    pub poll_batch: usize, 
+    pub cq_depth: u32, 
+    pub cq_per_session: bool, 
+    pub batch_max: usize, 
+    pub batch_flush_us: u64, 
+    pub large_msg: RpcLargeMsgMode, 
+    pub landing_size: u32, 
+    pub atomic_mode: RpcAtomicMode, 
+    pub ud: bool, 
+    pub ud_req_types: Vec<u8>, 
+    pub ud_msg_size: u32, 
+    pub ud_depth: u32, 
+    pub device: String, 
+    pub ib_port: u8, 
+    pub gid_index: u32, 
+    pub list_devices: bool, 
+    pub rails: Vec<RpcRail>, 
+    pub rail_check_ms: u64, 
+    pub path_mtu: u32, 
+    pub qp_timeout: u8, 
+    pub retry_cnt: u8, 
+    pub rnr_retry: u8, 
+    pub min_rnr_timer: u8, 
+    pub max_inline: u32, 
+    pub inline_threshold: u32, 
+    pub sq_depth: u32, 
+    pub max_session_cq: u32, 
+    pub max_frag_msg_size: u32, 
+    pub max_frag_msgs: u32, 
+    pub frag_timeout_ms: u64, 
 }
 
 impl RpcConf {
     fn new_singleton() -> Self {
         let rmt_grpc_uri = Vec::new();
+        let rmt_ids = Vec::new();
+        let loc_grpc_addr = "0.0.0.0:9000".to_string();
         let loc_mr_size = 0;
+        let loc_id = 0;
+        let proto_versions = Vec::new();
+        let qp_per_peer = 1;
+        let qp_select = RpcSessionSelect::RoundRobin;
+        let max_sessions = 0;
+        let mr_classes = Vec::new();
+        let max_inflight = 0;
+        let max_inflight_session = 0;
+        let recv_depth = 32;
+        let srq = false;
+        let srq_depth = 1024;
+        let poll_mode = RpcPollMode::Busy;
+        let poll_spin_us = 50;
+        let poll_thread = false;
+        let poll_cores = Vec::new();
+        let poll_batch = 32;
+        let cq_depth = 64;
+        let cq_per_session = false;
+        let batch_max = 1;
+        let batch_flush_us = 0;
+        let large_msg = RpcLargeMsgMode::Fragment;
+        let landing_size = 4 << 20;
+        let atomic_mode = RpcAtomicMode::Rdma;
+        let ud = false;
+        let ud_req_types = Vec::new();
+        let ud_msg_size = 1024 - IBVERBS_GRH_LEN as u32;
+        let ud_depth = 256;
+        let device = "".to_string();
+        let ib_port = 1;
+        let gid_index = 0;
+        let list_devices = false;
+        let rails = Vec::new();
+        let rail_check_ms = 1000;
+        let path_mtu = 0;
+        let qp_timeout = 14;
+        let retry_cnt = 6;
+        let rnr_retry = 6;
+        let min_rnr_timer = 16;
+        let max_inline = 0;
+        let inline_threshold = 256;
+        let sq_depth = 128;
+        let max_session_cq = 64;
+        let max_frag_msg_size = 64 << 20;
+        let max_frag_msgs = 4;
+        let frag_timeout_ms = 10000;
         let conf = Self {
             rmt_grpc_uri, 
+            rmt_ids, 
+            loc_grpc_addr, 
             loc_mr_size,
+            loc_id, 
+            proto_versions, 
+            qp_per_peer, 
+            qp_select, 
+            max_sessions, 
+            mr_classes, 
+            max_inflight, 
+            max_inflight_session, 
+            recv_depth, 
+            srq, 
+            srq_depth, 
+            poll_mode, 
+            poll_spin_us, 
+            poll_thread, 
+            poll_cores, 
+            poll_batch, 
+            cq_depth, 
+            cq_per_session, 
+            batch_max, 
+            batch_flush_us, 
+            large_msg, 
+            landing_size, 
+            atomic_mode, 
+            ud, 
+            ud_req_types, 
+            ud_msg_size, 
+            ud_depth, 
+            device, 
+            ib_port, 
+            gid_index, 
+            list_devices, 
+            rails, 
+            rail_check_ms, 
+            path_mtu, 
+            qp_timeout, 
+            retry_cnt, 
+            rnr_retry, 
+            min_rnr_timer, 
+            max_inline, 
+            inline_threshold, 
+            sq_depth, 
+            max_session_cq, 
+            max_frag_msg_size, 
+            max_frag_msgs, 
+            frag_timeout_ms, 
         };
         conf
     }
@@ -41,21 +192,379 @@ impl RpcConf {
                     List, 
                     "Remote gRPC URI. "
                 );
+            ap.refer(&mut conf.rmt_ids)
+                .add_option(
+                    &["--peer-id"], 
+                    List, 
+                    "Node ID of each remote, in --peer order. "
+                );
+            ap.refer(&mut conf.loc_grpc_addr)
+                .add_option(
+                    &["--listen"], 
+                    Store, 
+                    "Address the local gRPC server listens on. "
+                );
             ap.refer(&mut conf.loc_mr_size)
                 .add_option(
                     &["--mrsize"], 
                     Store, 
                     "Local memory region size. "
                 );
+            ap.refer(&mut conf.loc_id)
+                .add_option(
+                    &["--id"], 
+                    Store, 
+                    "Local RPC node ID, required, unique and nonzero. "
+                );
+            ap.refer(&mut conf.proto_versions)
+                .add_option(
+                    &["--proto-version"], 
+                    List, 
+                    "Advertised protocol versions (default: all supported). "
+                );
+            ap.refer(&mut conf.qp_per_peer)
+                .add_option(
+                    &["--qp-per-peer"], 
+                    Store, 
+                    "Number of queue pairs connected to each peer. "
+                );
+            ap.refer(&mut conf.qp_select)
+                .add_option(
+                    &["--qp-select"], 
+                    Store, 
+                    "Queue pair selection policy, rr or ll. "
+                );
+            ap.refer(&mut conf.max_sessions)
+                .add_option(
+                    &["--max-sessions"], 
+                    Store, 
+                    "Maximum number of sessions, 0 for unlimited. "
+                );
+            ap.refer(&mut conf.mr_classes)
+                .add_option(
+                    &["--mr-class"], 
+                    List, 
+                    "Memory region size classes as size[:count] (default: --mrsize). "
+                );
+            ap.refer(&mut conf.max_inflight)
+                .add_option(
+                    &["--max-inflight"], 
+                    Store, 
+                    "Maximum number of in-flight sends, 0 for unlimited. "
+                );
+            ap.refer(&mut conf.max_inflight_session)
+                .add_option(
+                    &["--max-inflight-session"], 
+                    Store, 
+                    "Maximum number of in-flight sends per session, 0 for --sq-depth. "
+                );
+            ap.refer(&mut conf.recv_depth)
+                .add_option(
+                    &["--recv-depth"], 
+                    Store, 
+                    "Number of receive buffers kept posted on each session. "
+                );
+            ap.refer(&mut conf.srq)
+                .add_option(
+                    &["--srq"], 
+                    StoreTrue, 
+                    "Share one receive queue per size class among all sessions, requires --max-sessions. "
+                );
+            ap.refer(&mut conf.srq_depth)
+                .add_option(
+                    &["--srq-depth"], 
+                    Store, 
+                    "Number of receive buffers kept posted on each shared receive queue. "
+                );
+            ap.refer(&mut conf.poll_mode)
+                .add_option(
+                    &["--poll-mode"], 
+                    Store, 
+                    "Completion polling mode, busy, event or hybrid. "
+                );
+            ap.refer(&mut conf.poll_spin_us)
+                .add_option(
+                    &["--poll-spin-us"], 
+                    Store, 
+                    "Microseconds to spin after the last completion in hybrid mode. "
+                );
+            ap.refer(&mut conf.poll_thread)
+                .add_option(
+                    &["--poll-thread"], 
+                    StoreTrue, 
+                    "Poll completion queues on dedicated threads. "
+                );
+            ap.refer(&mut conf.poll_cores)
+                .add_option(
+                    &["--poll-core"], 
+                    List, 
+                    "Cores to pin polling threads to, in turn. "
+                );
+            ap.refer(&mut conf.poll_batch)
+                .add_option(
+                    &["--poll-batch"], 
+                    Store, 
+                    "Maximum number of completions handled per poll. "
+                );
+            ap.refer(&mut conf.cq_depth)
+                .add_option(
+                    &["--cq-depth"], 
+                    Store, 
+                    "Number of entries of each completion queue. "
+                );
+            ap.refer(&mut conf.cq_per_session)
+                .add_option(
+                    &["--cq-per-session"], 
+                    StoreTrue, 
+                    "Give each session completion queues of its own. "
+                );
+            ap.refer(&mut conf.batch_max)
+                .add_option(
+                    &["--batch-max"], 
+                    Store, 
+                    "Maximum number of messages coalesced into one frame, 1 disables batching. "
+                );
+            ap.refer(&mut conf.batch_flush_us)
+                .add_option(
+                    &["--batch-flush-us"], 
+                    Store, 
+                    "Microseconds a partial batch may wait for more messages. "
+                );
+            ap.refer(&mut conf.large_msg)
+                .add_option(
+                    &["--large-msg"], 
+                    Store, 
+                    "Protocol for messages too large for one frame: fragment or write-imm. "
+                );
+            ap.refer(&mut conf.landing_size)
+                .add_option(
+                    &["--landing-size"], 
+                    Store, 
+                    "Bytes of the region each session exposes to write-imm senders. "
+                );
+            ap.refer(&mut conf.atomic_mode)
+                .add_option(
+                    &["--atomic-mode"], 
+                    Store, 
+                    "Who performs remote atomic operations: rdma or emulated. "
+                );
+            ap.refer(&mut conf.ud)
+                .add_option(
+                    &["--ud"], 
+                    StoreTrue, 
+                    "Open an unreliable datagram queue pair for small messages. "
+                );
+            ap.refer(&mut conf.ud_req_types)
+                .add_option(
+                    &["--ud-req-type"], 
+                    List, 
+                    "Request type sent as datagrams when it fits, may be repeated. "
+                );
+            ap.refer(&mut conf.ud_msg_size)
+                .add_option(
+                    &["--ud-msg-size"], 
+                    Store, 
+                    "Largest datagram frame in bytes, the 40 bytes routing header added must fit the path MTU. "
+                );
+            ap.refer(&mut conf.ud_depth)
+                .add_option(
+                    &["--ud-depth"], 
+                    Store, 
+                    "Receive buffers posted to the datagram queue pair. "
+                );
+            ap.refer(&mut conf.device)
+                .add_option(
+                    &["--device"], 
+                    Store, 
+                    "RDMA device name, the first listed if empty. "
+                );
+            ap.refer(&mut conf.ib_port)
+                .add_option(
+                    &["--ib-port"], 
+                    Store, 
+                    "Port number of the RDMA device. "
+                );
+            ap.refer(&mut conf.gid_index)
+                .add_option(
+                    &["--gid-index"], 
+                    Store, 
+                    "GID index on the port, RoCE needs the one of its address. "
+                );
+            ap.refer(&mut conf.list_devices)
+                .add_option(
+                    &["--list-devices"], 
+                    StoreTrue, 
+                    "List RDMA devices and their ports, then exit. "
+                );
+            ap.refer(&mut conf.rails)
+                .add_option(
+                    &["--rail"], 
+                    List, 
+                    "Port to stripe the sends of every session across, as [device/]port[:gid_index], repeatable, the device being --device when left out. Needs --srq with more than one rail. "
+                );
+            ap.refer(&mut conf.rail_check_ms)
+                .add_option(
+                    &["--rail-check-ms"], 
+                    Store, 
+                    "Interval in milliseconds between checks of rail ports for failover. "
+                );
+            ap.refer(&mut conf.path_mtu)
+                .add_option(
+                    &["--path-mtu"], 
+                    Store, 
+                    "Path MTU of queue pairs in bytes, the smallest active MTU of the rail ports if 0. "
+                );
+            ap.refer(&mut conf.qp_timeout)
+                .add_option(
+                    &["--qp-timeout"], 
+                    Store, 
+                    "Local ACK timeout of queue pairs, 4.096us * 2^n, 0 waits forever. "
+                );
+            ap.refer(&mut conf.retry_cnt)
+                .add_option(
+                    &["--retry-cnt"], 
+                    Store, 
+                    "Retransmissions before a send fails, up to 7. "
+                );
+            ap.refer(&mut conf.rnr_retry)
+                .add_option(
+                    &["--rnr-retry"], 
+                    Store, 
+                    "Retries on receiver-not-ready, 7 retries forever. "
+                );
+            ap.refer(&mut conf.min_rnr_timer)
+                .add_option(
+                    &["--min-rnr-timer"], 
+                    Store, 
+                    "Receiver-not-ready delay asked of the peer, encoded as in the verbs spec. "
+                );
+            ap.refer(&mut conf.max_inline)
+                .add_option(
+                    &["--max-inline"], 
+                    Store, 
+                    "Bytes a send may carry inline in the work request, 0 disables inline sends. "
+                );
+            ap.refer(&mut conf.inline_threshold)
+                .add_option(
+                    &["--inline-threshold"], 
+                    Store, 
+                    "Frames of at most this many bytes are sent inline, within --max-inline, 0 disables. "
+                );
+            ap.refer(&mut conf.sq_depth)
+                .add_option(
+                    &["--sq-depth"], 
+                    Store, 
+                    "Send work requests each queue pair holds, also bounds the in-flight sends of a session. "
+                );
+            ap.refer(&mut conf.max_session_cq)
+                .add_option(
+                    &["--max-session-cq"], 
+                    Store, 
+                    "Maximum number of sessions with completion queues of their own, later sessions share the common ones. "
+                );
+            ap.refer(&mut conf.max_frag_msg_size)
+                .add_option(
+                    &["--max-frag-msg-size"], 
+                    Store, 
+                    "Largest message in bytes accepted in fragments, larger ones are refused. "
+                );
+            ap.refer(&mut conf.max_frag_msgs)
+                .add_option(
+                    &["--max-frag-msgs"], 
+                    Store, 
+                    "Messages a session may have partly received in fragments at once, further ones are refused, 0 for no limit. "
+                );
+            ap.refer(&mut conf.frag_timeout_ms)
+                .add_option(
+                    &["--frag-timeout-ms"], 
+                    Store, 
+                    "Milliseconds a message partly received in fragments is kept waiting for the rest, 0 to keep it until the session ends. "
+                );
             
             ap.parse_args_or_exit(); 
         }
 
+        if let Err(e) = conf.check() {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::USAGE);
+        }
+
         RPC_CONF.get_or_init(|| {
             conf
         });
     }
 
+    // Rejects settings the node cannot run with. 
+    fn check(&self) -> Result<(), String> {
+        // peers are told apart by node ID, so each node needs its own 
+        if self.loc_id == 0 {
+            return Err("--id is required and must not be 0".to_string());
+        }
+        // sessions are bound to the node a remote says it is 
+        if self.rmt_ids.len() != self.rmt_grpc_uri.len() {
+            return Err("--peer-id must be given once per --peer".to_string());
+        }
+        // control frames land in buffers kept out of the credits 
+        if self.recv_depth <= SRPC_RECV_RESERVE {
+            return Err(format!("--recv-depth must exceed the {} buffers kept for control frames", 
+                SRPC_RECV_RESERVE));
+        }
+        if self.poll_batch == 0 {
+            return Err("--poll-batch must be at least 1".to_string());
+        }
+        // sends of a session go out on one queue pair at a time 
+        if self.sq_depth == 0 {
+            return Err("--sq-depth must be at least 1".to_string());
+        }
+        if self.max_inflight_session > self.sq_depth {
+            return Err(format!(
+                "--max-inflight-session {} exceeds --sq-depth {}",
+                self.max_inflight_session, self.sq_depth));
+        }
+        // a datagram is one packet, routing header included, the active 
+        // MTU of the port is checked once the device is open 
+        if self.ud && self.path_mtu != 0 
+            && IBVERBS_GRH_LEN + self.ud_msg_size as usize > self.path_mtu as usize 
+        {
+            return Err(format!(
+                "--ud-msg-size {} plus the {} bytes routing header exceeds --path-mtu {}", 
+                self.ud_msg_size, IBVERBS_GRH_LEN, self.path_mtu));
+        }
+        // frames of a session land on any of its rails, each queue pair 
+        // of its own would need the whole receive depth the peer is 
+        // granted credits for 
+        if self.rails.len() > 1 && !self.srq {
+            return Err("--rail given more than once requires --srq".to_string());
+        }
+        // sessions share the receive buffers of a shared receive queue, 
+        // each is granted its share as credits, reserve included 
+        if self.srq {
+            if self.max_sessions == 0 {
+                return Err("--srq requires --max-sessions".to_string());
+            }
+            let min_depth = RpcMrClass::normalize(&self.mr_classes, self.loc_mr_size)
+                .iter()
+                .map(|class| std::cmp::min(self.srq_depth, class.count))
+                .min()
+                .unwrap_or(0);
+            if min_depth / self.max_sessions <= SRPC_RECV_RESERVE {
+                return Err(format!(
+                    "--srq-depth of {} buffers per class too small for {} sessions", 
+                    min_depth, self.max_sessions));
+            }
+        }
+        let unsupported: Vec<u32> = self.proto_versions.iter()
+            .filter(|v| !SRPC_PROTO_VERSIONS.contains(v))
+            .cloned()
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(format!(
+                "--proto-version {:?} not supported, supported are {:?}", 
+                unsupported, SRPC_PROTO_VERSIONS));
+        }
+        Ok(())
+    }
+
     pub fn get_conf() -> &'static Self {
         RPC_CONF.get().unwrap()
     }
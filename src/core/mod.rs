@@ -1,4 +1,9 @@
 pub mod srpc_core; 
 pub mod network;
 pub mod srpc_session; 
+pub mod srpc_session_pool;
+pub mod srpc_credit;
+pub mod srpc_batch;
+pub mod srpc_frag;
+pub mod srpc_atomic;
 pub mod srpc_dispatcher;
@@ -1,2 +1,11 @@
 pub mod srpc_core_network;
+pub mod srpc_device;
 pub mod srpc_grpc; 
+pub mod srpc_peer_meta;
+pub mod srpc_mr_pool;
+pub mod srpc_net_error;
+pub mod srpc_poll;
+pub mod srpc_qp_attr;
+pub mod srpc_send_buf;
+pub mod srpc_session_qps;
+pub mod srpc_remote_mr;
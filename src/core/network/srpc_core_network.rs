@@ -7,57 +7,283 @@ pub struct RpcNetworkCore
             u32, (Vec<Vec<u8>>, Vec<Vec<u8>>)
         >
     >>, 
-    wr_cnt: std::sync::Arc<std::sync::atomic::AtomicU64>,
 }
 
 unsafe impl Send for RpcNetworkCore {}
 unsafe impl Sync for RpcNetworkCore {}
 
-use ibverbs::{MemoryRegion};
 use once_cell::sync::OnceCell;
 
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 use tracing::error;
 #[allow(unused_imports)]
 use tracing::{info, trace};
 
-use crate::{core::{srpc_core::{RPC_DISPATCHER, get_mut_from_immut}, network::srpc_grpc::SrpcGrpcPreComm}, conf::conf::RPC_CONF};
+use crate::{core::{srpc_core::RPC_DISPATCHER, network::srpc_grpc::SrpcGrpcPreComm}, conf::conf::RPC_CONF};
+use crate::core::srpc_credit::RpcCredit;
+use crate::core::srpc_frag::RpcLargeMsgMode;
+use crate::msg::srpc_frame::{RpcFrameHeader, SRPC_FRAME_HEADER_LEN};
+use super::srpc_device::{RpcRail, get_active_mtu, get_rail_device, get_rail_devices, get_rails, is_port_active, open_devices};
+use super::srpc_peer_meta::RpcPeerMeta;
+use super::srpc_mr_pool::{RpcMrClass, RpcMrPool, RpcMrSlab};
+use super::srpc_net_error::{RpcNetworkError, RpcWcError};
+use super::srpc_poll::{RpcPollMode, RpcPollStop};
+use super::srpc_remote_mr::{RpcMemRegion, RpcRemoteKey, RpcRemoteOp, RpcRemoteOpKind, RpcRemoteOpResult};
+use super::srpc_session_qps::{RpcRailQp, RpcSessionQps};
+use crate::msg::srpc_atomic::RpcAtomicOp;
 pub static IBVERBS_QP_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
-    std::collections::BTreeMap<u32, ibverbs::QueuePair>
-    >>> = OnceCell::new(); // session_id -> queue pair 
-pub static IBVERBS_PD: OnceCell<std::sync::Arc<
-    ibverbs::ProtectionDomain>> = OnceCell::new();
-pub static IBVERBS_CTX: OnceCell<std::sync::Arc<
-    ibverbs::Context>> = OnceCell::new(); 
-// pub static IBVERBS_PQP: OnceCell<std::sync::Arc<
-//     ibverbs::PreparedQueuePair>> = OnceCell::new();
-pub static IBVERBS_SQ: OnceCell<std::sync::Arc<
-    ibverbs::CompletionQueue>> = OnceCell::new();
-pub static IBVERBS_RQ: OnceCell<std::sync::Arc<
-    ibverbs::CompletionQueue>> = OnceCell::new();
+    std::collections::BTreeMap<u32, RpcSessionQps<ibverbs::QueuePair>>
+    >>> = OnceCell::new(); // session_id -> queue pairs on every rail 
+// devices the rails are on, in the order of get_rail_devices 
+pub static IBVERBS_DEVICES: OnceCell<Vec<RpcVerbsDevice>> = OnceCell::new();
+// completion queues of each session on each device, when 
+// --cq-per-session is set, (session_id, device) -> 
+// (send queue, receive queue, stop of their pollers) 
+pub static IBVERBS_SESSION_CQ_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<(u32, u8), (
+        std::sync::Arc<ibverbs::CompletionQueue>, 
+        std::sync::Arc<ibverbs::CompletionQueue>, 
+        std::sync::Arc<RpcPollStop>
+    )>
+    >>> = OnceCell::new();
+// polling threads started so far, to spread them across cores 
+static IBVERBS_POLLER_CNT: std::sync::atomic::AtomicUsize = 
+    std::sync::atomic::AtomicUsize::new(0);
 pub static IBVERBS_WRID_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
     std::collections::BTreeMap<u64, u32>
     >>> = OnceCell::new(); // wr_id -> session_id
-// send memory region 
-pub static IBVERBS_SMR_VEC: OnceCell<std::vec::Vec<
-    ibverbs::MemoryRegion<u8>
-    >> = OnceCell::new(); // mr 
-pub static IBVERBS_SMR_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
-    std::collections::BTreeMap<u64, u32>
-    >>> = OnceCell::new(); // wr_id -> mr_index 
-pub static IBVERBS_SMR_MAP_INV: OnceCell<std::sync::Arc<std::sync::Mutex<
-    std::collections::BTreeMap<u32, u64>
-    >>> = OnceCell::new(); // mr_index -> wr_id 
-// receive memory region 
-pub static IBVERBS_RMR_VEC: OnceCell<std::vec::Vec<
-    ibverbs::MemoryRegion<u8>
-    >> = OnceCell::new(); // mr 
-pub static IBVERBS_RMR_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
-    std::collections::BTreeMap<u64, u32>
-    >>> = OnceCell::new(); // wr_id -> mr_index 
-pub static IBVERBS_RMR_MAP_INV: OnceCell<std::sync::Arc<std::sync::Mutex<
-    std::collections::BTreeMap<u32, u64>
-    >>> = OnceCell::new(); // mr_index -> wr_id 
+// work request sequence 
+static IBVERBS_WR_CNT: std::sync::atomic::AtomicU64 = 
+    std::sync::atomic::AtomicU64::new(1);
+// receives a session failed to repost, session_id -> count 
+pub static IBVERBS_RECV_DEFICIT: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u32, u32>
+    >>> = OnceCell::new();
+// queue pair number -> session_id, completions of shared 
+// receive queues only carry the queue pair they arrived on 
+pub static IBVERBS_QPN_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u32, u32>
+    >>> = OnceCell::new();
+// receives a shared receive queue failed to repost, 
+// (device, class_id) -> count 
+pub static IBVERBS_SRQ_DEFICIT: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<(u8, u8), u32>
+    >>> = OnceCell::new();
+// ports sessions open queue pairs on, in the order peers pair them 
+static IBVERBS_RAILS: OnceCell<Vec<RpcRail>> = OnceCell::new();
+// device of each rail, as a position in IBVERBS_DEVICES 
+static IBVERBS_RAIL_DEVICES: OnceCell<Vec<u8>> = OnceCell::new();
+// whether the port of each rail was up when last checked 
+static IBVERBS_RAIL_UP: OnceCell<Vec<std::sync::atomic::AtomicBool>> = OnceCell::new();
+// queue pairs a work request failed on, in the error state for good 
+pub static IBVERBS_FAILED_QPN_SET: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeSet<u32>
+    >>> = OnceCell::new();
+// first error a work request of a session failed with, flushes 
+// aside, session_id -> error 
+pub static IBVERBS_SESSION_ERROR_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u32, RpcWcError>
+    >>> = OnceCell::new();
+// sends posted and not completed yet, wr_id -> send, kept to post 
+// them again on another rail when their queue pair fails 
+pub static IBVERBS_SEND_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u64, RpcPostedSend>
+    >>> = OnceCell::new();
+// sends posted inline so far 
+pub static IBVERBS_INLINE_CNT: std::sync::atomic::AtomicU64 = 
+    std::sync::atomic::AtomicU64::new(0);
+// sends posted but not completed yet 
+pub static IBVERBS_INFLIGHT: std::sync::atomic::AtomicU64 = 
+    std::sync::atomic::AtomicU64::new(0);
+// woken up whenever a send completes 
+pub static IBVERBS_SEND_NOTIFY: OnceCell<
+    tokio::sync::Notify> = OnceCell::new();
+// send memory regions, one pool per size class, registered with 
+// the first device 
+pub static IBVERBS_SMR_SLAB: OnceCell<
+    RpcMrSlab<ibverbs::MemoryRegion<u8>>
+    > = OnceCell::new(); 
+// application regions registered and not dropped yet, region_id -> key 
+pub static IBVERBS_REGION_MAP: OnceCell<std::sync::Arc<std::sync::RwLock<
+    std::collections::BTreeMap<u32, RpcRemoteKey>
+    >>> = OnceCell::new();
+// 8-byte regions remote atomic operations land their result in 
+static IBVERBS_ATOMIC_BUFS: OnceCell<std::sync::Mutex<
+    Vec<RpcMemRegion>
+    >> = OnceCell::new();
+// application regions exposed to peers, region_id -> key 
+pub static IBVERBS_EXPOSED_MAP: OnceCell<std::sync::Arc<std::sync::RwLock<
+    std::collections::BTreeMap<u32, RpcRemoteKey>
+    >>> = OnceCell::new();
+// application regions registered so far 
+static IBVERBS_REGION_CNT: std::sync::atomic::AtomicU32 = 
+    std::sync::atomic::AtomicU32::new(1);
+// one-sided operations posted and not completed yet, wr_id -> operation 
+pub static IBVERBS_REMOTE_OP_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u64, RpcRemoteOp>
+    >>> = OnceCell::new();
+// size class carried by work request ids of one-sided operations 
+const IBVERBS_REMOTE_OP_CLASS: u8 = u8::MAX;
+// regions peers write large messages into, session_id -> region 
+pub static IBVERBS_LANDING_MAP: OnceCell<std::sync::Arc<std::sync::RwLock<
+    std::collections::BTreeMap<u32, RpcMemRegion>
+    >>> = OnceCell::new();
+// regions large messages are staged in before being written 
+// into the landing region of the peer, session_id -> region 
+pub static IBVERBS_STAGING_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u32, RpcMemRegion>
+    >>> = OnceCell::new();
+// size class carried by work request ids of writes into landing regions 
+const IBVERBS_LANDING_CLASS: u8 = u8::MAX - 1;
+// bits of a work request id below its sequence number, see make_wr_id 
+const IBVERBS_WR_SEQ_SHIFT: u32 = 45;
+// devices work request ids tell apart 
+const IBVERBS_WR_DEVICE_MASK: u8 = 0xf;
+// the datagram queue pair shared by all peers, when --ud is set 
+pub static IBVERBS_UD_QP: OnceCell<std::sync::Mutex<
+    ibverbs::QueuePair
+    >> = OnceCell::new();
+// endpoint of the datagram queue pair, serialized for the handshake 
+static IBVERBS_UD_ENDPOINT: OnceCell<Vec<u8>> = OnceCell::new();
+// datagram send and receive buffers 
+static IBVERBS_UD_SMR_POOL: OnceCell<
+    RpcMrPool<ibverbs::MemoryRegion<u8>>
+    > = OnceCell::new();
+static IBVERBS_UD_RMR_POOL: OnceCell<
+    RpcMrPool<ibverbs::MemoryRegion<u8>>
+    > = OnceCell::new();
+// datagram endpoints of peers, peer_id -> endpoint 
+pub static IBVERBS_UD_PEER_MAP: OnceCell<std::sync::Arc<std::sync::RwLock<
+    std::collections::BTreeMap<u32, ibverbs::QueuePairEndpoint>
+    >>> = OnceCell::new();
+// datagram senders of peers, source -> peer_id 
+pub static IBVERBS_UD_SRC_MAP: OnceCell<std::sync::Arc<std::sync::RwLock<
+    std::collections::BTreeMap<RpcUdSource, u32>
+    >>> = OnceCell::new();
+// address handles created so far, peer_id -> address handle 
+pub static IBVERBS_AH_MAP: OnceCell<std::sync::Arc<std::sync::Mutex<
+    std::collections::BTreeMap<u32, ibverbs::AddressHandle>
+    >>> = OnceCell::new();
+// size class carried by work request ids of datagrams 
+const IBVERBS_UD_CLASS: u8 = u8::MAX - 2;
+// size class carried by work request ids of sends posted inline, 
+// which hold no memory region 
+const IBVERBS_INLINE_CLASS: u8 = u8::MAX - 3;
+// queue key shared by the datagram queue pairs of all nodes 
+const IBVERBS_UD_QKEY: u32 = 0x11111111;
+// bytes of the global routing header in front of a received datagram 
+pub const IBVERBS_GRH_LEN: usize = 40;
+// work completion flag of a datagram received with its routing header 
+const IBVERBS_WC_GRH: u32 = 1 << 0;
+
+// Sender of a datagram. Queue pair numbers are only unique within a 
+// node, they go with the global identifier of the port of the sender 
+// when routed, with its local one otherwise. 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RpcUdSource
+{
+    // (subnet prefix, interface id, qp_num) 
+    Global(u64, u64, u32),
+    // (lid, qp_num) 
+    Local(u16, u32),
+}
+
+impl RpcUdSource
+{
+    // Source the datagrams of the endpoint come from. 
+    fn of_endpoint(endpoint: &ibverbs::QueuePairEndpoint) -> RpcUdSource
+    {
+        match endpoint.gid {
+            Some(gid) => RpcUdSource::Global(
+                gid.subnet_prefix(), gid.interface_id(), endpoint.num),
+            None => RpcUdSource::Local(endpoint.lid, endpoint.num),
+        }
+    }
+
+    // Source of a received datagram, the routing header being the 
+    // first bytes of its buffer, the source GID at bytes 8 to 24. 
+    fn of_wc(wc: &ibverbs::ibv_wc, grh: &[u8]) -> RpcUdSource
+    {
+        if wc.wc_flags & IBVERBS_WC_GRH != 0 && grh.len() >= IBVERBS_GRH_LEN {
+            RpcUdSource::Global(
+                u64::from_be_bytes(grh[8..16].try_into().unwrap()), 
+                u64::from_be_bytes(grh[16..24].try_into().unwrap()), 
+                wc.src_qp)
+        } else {
+            RpcUdSource::Local(wc.slid, wc.src_qp)
+        }
+    }
+}
+
+// Verbs resources of a device rails are on. Memory regions only serve 
+// queue pairs of the device they are registered with: each device has 
+// receive buffers and shared receive queues of its own, and the devices 
+// after the first keep a copy of every send memory region, which frames 
+// sent on their rails are copied into before posting. 
+pub struct RpcVerbsDevice
+{
+    pub name: std::string::String,
+    pub ctx: std::sync::Arc<ibverbs::Context>,
+    pub pd: std::sync::Arc<ibverbs::ProtectionDomain>,
+    // completion queues shared by the sessions 
+    pub sq: std::sync::Arc<ibverbs::CompletionQueue>,
+    pub rq: std::sync::Arc<ibverbs::CompletionQueue>,
+    // receive memory regions, one pool per size class 
+    pub rmr_slab: RpcMrSlab<ibverbs::MemoryRegion<u8>>,
+    // copies of the send memory regions, none on the first device, 
+    // each held along with the region it copies 
+    pub smr_copy: Option<RpcMrSlab<ibverbs::MemoryRegion<u8>>>,
+    // shared receive queues, one per size class, when --srq is set 
+    pub srq_vec: Vec<ibverbs::SharedReceiveQueue>,
+}
+
+// A send posted and not completed yet. 
+#[derive(Debug)]
+pub struct RpcPostedSend
+{
+    // bytes of the frame posted 
+    pub len: usize,
+    // the frame when sent inline, kept in place of a memory region 
+    pub inline: Option<Vec<u8>>,
+    // whether the frame took a credit of the peer 
+    pub credited: bool,
+    // credits the frame returns to the peer 
+    pub returned: u32,
+}
+
+// Queue pairs receive buffers are posted to, whether connected yet or not. 
+trait RpcRecvQueue
+{
+    unsafe fn post_recv_mr(
+        &mut self, 
+        mr: &mut ibverbs::MemoryRegion<u8>, 
+        wr_id: u64
+    ) -> std::io::Result<()>;
+}
+
+impl RpcRecvQueue for ibverbs::QueuePair
+{
+    unsafe fn post_recv_mr(
+        &mut self, 
+        mr: &mut ibverbs::MemoryRegion<u8>, 
+        wr_id: u64
+    ) -> std::io::Result<()>
+    {
+        self.post_receive(mr, .., wr_id)
+    }
+}
+
+impl RpcRecvQueue for ibverbs::PreparedQueuePair
+{
+    unsafe fn post_recv_mr(
+        &mut self, 
+        mr: &mut ibverbs::MemoryRegion<u8>, 
+        wr_id: u64
+    ) -> std::io::Result<()>
+    {
+        self.post_receive(mr, .., wr_id)
+    }
+}
 
 #[allow(unused_variables)]
 impl RpcNetworkCore
@@ -70,7 +296,6 @@ impl RpcNetworkCore
                 std::sync::RwLock::new(
                     std::collections::BTreeMap::new()
                 )),
-            wr_cnt: std::sync::Arc::new(1.into()),
         };
 
         net_core.init();
@@ -78,9 +303,9 @@ impl RpcNetworkCore
         net_core
     }
 
-    fn get_wr_id(&self) -> u64
+    fn get_wr_id() -> u64
     {
-        let wr_cnt = self.wr_cnt.fetch_add(
+        let wr_cnt = IBVERBS_WR_CNT.fetch_add(
             1, 
             std::sync::atomic::Ordering::SeqCst
         );
@@ -101,22 +326,83 @@ impl RpcNetworkCore
             std::sync::Mutex::new(
                 std::collections::BTreeMap::new()
             )));
-        let _result = IBVERBS_SMR_MAP.set(
+        let _result = IBVERBS_RECV_DEFICIT.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_QPN_MAP.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_SEND_MAP.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_SESSION_ERROR_MAP.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_FAILED_QPN_SET.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeSet::new()
+            )));
+        let _result = IBVERBS_SRQ_DEFICIT.set(
+            std::sync::Arc::new(
+            std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_SESSION_CQ_MAP.set(
             std::sync::Arc::new(
             std::sync::Mutex::new(
                 std::collections::BTreeMap::new()
             )));
-        let _result = IBVERBS_SMR_MAP_INV.set(
+        let _result = IBVERBS_SEND_NOTIFY.set(
+            tokio::sync::Notify::new()
+        );
+        let _result = IBVERBS_REGION_MAP.set(
+            std::sync::Arc::new(
+            std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_ATOMIC_BUFS.set(
+            std::sync::Mutex::new(Vec::new())
+        );
+        let _result = IBVERBS_EXPOSED_MAP.set(
+            std::sync::Arc::new(
+            std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_REMOTE_OP_MAP.set(
             std::sync::Arc::new(
             std::sync::Mutex::new(
                 std::collections::BTreeMap::new()
             )));
-        let _result = IBVERBS_RMR_MAP.set(
+        let _result = IBVERBS_LANDING_MAP.set(
+            std::sync::Arc::new(
+            std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_STAGING_MAP.set(
             std::sync::Arc::new(
             std::sync::Mutex::new(
                 std::collections::BTreeMap::new()
             )));
-        let _result = IBVERBS_RMR_MAP_INV.set(
+        let _result = IBVERBS_UD_PEER_MAP.set(
+            std::sync::Arc::new(
+            std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_UD_SRC_MAP.set(
+            std::sync::Arc::new(
+            std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )));
+        let _result = IBVERBS_AH_MAP.set(
             std::sync::Arc::new(
             std::sync::Mutex::new(
                 std::collections::BTreeMap::new()
@@ -130,10 +416,10 @@ impl RpcNetworkCore
     {
         info!("init_grpc");
 
-        let listen_addr = "0.0.0.0:9000";
+        let listen_addr = RPC_CONF.get().unwrap().loc_grpc_addr.clone();
         let _handle = tokio::spawn(async move {
             let result = 
-                SrpcGrpcPreComm::serve(listen_addr).await;
+                SrpcGrpcPreComm::serve(&listen_addr).await;
             match result {
                 Ok(_) => {
                     info!("grpc server stopped");
@@ -149,338 +435,2324 @@ impl RpcNetworkCore
     {
         let conf = RPC_CONF.get().unwrap();
 
-        let ctx = ibverbs::devices().unwrap()
-            .iter().next().expect("no rdma device available")
-            .open().unwrap();
-        let _result = IBVERBS_CTX.set(
-            std::sync::Arc::new(ctx)
-        );
+        let rails = match get_rails(conf) {
+            Ok(rails) => rails,
+            Err(e) => panic!("failed to resolve rails: {}", e),
+        };
+        let ctxs = match open_devices(&rails) {
+            Ok(ctxs) => ctxs,
+            Err(e) => panic!("failed to open rdma devices: {}", e),
+        };
+        let names = get_rail_devices(&rails);
+        info!("rdma devices: {:?}, rails: {:?}", names, rails);
+        // work request ids have room for so many devices 
+        if names.len() > IBVERBS_WR_DEVICE_MASK as usize + 1 {
+            panic!("rails span {} devices, at most {} are supported", 
+                names.len(), IBVERBS_WR_DEVICE_MASK as usize + 1);
+        }
 
-        let ctx = IBVERBS_CTX.get().unwrap();
-        let sq = ctx.create_cq(64, 0).unwrap();
-        let rq = ctx.create_cq(64, 1).unwrap();
-        let pd = ctx.alloc_pd().unwrap();
+        let rail_devices = rails.iter()
+            .map(|rail| get_rail_device(&rails, rail) as u8)
+            .collect();
+        let rail_up = rails.iter()
+            .map(|rail| std::sync::atomic::AtomicBool::new(
+                is_port_active(&ctxs[get_rail_device(&rails, rail)], rail.port)))
+            .collect();
+        let _result = IBVERBS_RAILS.set(rails);
+        let _result = IBVERBS_RAIL_DEVICES.set(rail_devices);
+        let _result = IBVERBS_RAIL_UP.set(rail_up);
 
-        let _result = IBVERBS_SQ.set(
-            std::sync::Arc::new(sq)
-        );
-        let _result = IBVERBS_RQ.set(
-            std::sync::Arc::new(rq)
-        );
-        let _result = IBVERBS_PD.set(
-            std::sync::Arc::new(pd)
+        // create memory regions 
+        let classes = RpcMrClass::normalize(
+            &conf.mr_classes, 
+            conf.loc_mr_size
         );
+        info!("memory region classes: {:?}", classes);
 
-        // create memory regions 
-        let pd = IBVERBS_PD.get().unwrap();
-        let mut smr_vec = Vec::new();
-        let smr_size = conf.loc_mr_size as usize;
-        for _i in 0..1024
-        {
-            let smr = pd.allocate::<u8>(smr_size).unwrap();
-            smr_vec.push(smr);
+        let mut devices = Vec::new();
+        let mut channels = Vec::new();
+        for (device, (name, ctx)) in names.into_iter().zip(ctxs.into_iter()).enumerate() {
+            let ctx = std::sync::Arc::new(ctx);
+            let (sq, sq_channel) = Self::create_cq(&ctx, 0);
+            let (rq, rq_channel) = Self::create_cq(&ctx, 1);
+            let pd = ctx.alloc_pd().unwrap();
+
+            if device == 0 {
+                let smr_slab = RpcMrSlab::new(&classes, |size| {
+                    pd.allocate::<u8>(size).unwrap()
+                });
+                let _result = IBVERBS_SMR_SLAB.set(smr_slab);
+            }
+            let smr_copy = if device == 0 {
+                None
+            } else {
+                Some(RpcMrSlab::new(&classes, |size| {
+                    pd.allocate::<u8>(size).unwrap()
+                }))
+            };
+            let rmr_slab = RpcMrSlab::new(&classes, |size| {
+                pd.allocate::<u8>(size).unwrap()
+            });
+            // shared receive queues are filled once, 
+            // sessions never post receives of their own 
+            let srq_vec = if conf.srq {
+                classes.iter().map(|_| {
+                    pd.create_srq(conf.srq_depth, 1).unwrap()
+                }).collect()
+            } else {
+                Vec::new()
+            };
+
+            channels.push((sq_channel, rq_channel));
+            devices.push(RpcVerbsDevice {
+                name: name,
+                ctx: ctx,
+                pd: std::sync::Arc::new(pd),
+                sq: sq,
+                rq: rq,
+                rmr_slab: rmr_slab,
+                smr_copy: smr_copy,
+                srq_vec: srq_vec,
+            });
         }
-        let _result = IBVERBS_SMR_VEC.set(smr_vec);
+        let _result = IBVERBS_DEVICES.set(devices);
 
-        let mut rmr_vec = Vec::new();
-        let rmr_size = conf.loc_mr_size as usize;
-        for _i in 0..1024
-        {
-            let rmr = pd.allocate::<u8>(rmr_size).unwrap();
-            rmr_vec.push(rmr);
+        if conf.srq {
+            for device in 0..Self::get_device_cnt() as u8 {
+                for (class_id, class) in classes.iter().enumerate() {
+                    let depth = std::cmp::min(conf.srq_depth, class.count);
+                    if let Err(e) = Self::post_srq_buffers(device, class_id as u8, depth) {
+                        error!("failed to fill shared receive queue of {} bytes on {}: {}", 
+                            class.size, Self::get_device(device).name, e);
+                    }
+                }
+            }
+        }
+
+        if conf.ud {
+            Self::init_ud();
         }
-        let _result = IBVERBS_RMR_VEC.set(rmr_vec); 
 
         // start polling work request queues 
-        let sq = IBVERBS_SQ.get().unwrap();
-        let rq = IBVERBS_RQ.get().unwrap();
-        let _pollsq_handle = tokio::spawn(async move {
-            Self::poll_cq(sq);
-        });
-        let _pollrq_handle = tokio::spawn(async move {
-            Self::poll_cq(rq);
-        }); 
+        // polled for as long as the process lives 
+        let stop = std::sync::Arc::new(RpcPollStop::new());
+        for (device, (sq_channel, rq_channel)) in channels.into_iter().enumerate() {
+            let verbs_device = Self::get_device(device as u8);
+            // the first device keeps the names pollers always had 
+            let suffix = if device == 0 {
+                std::string::String::new()
+            } else {
+                format!("-{}", verbs_device.name)
+            };
+            Self::spawn_poller(format!("srpc-poll-sq{}", suffix), 
+                verbs_device.sq.clone(), false, device as u8, sq_channel, stop.clone());
+            Self::spawn_poller(format!("srpc-poll-rq{}", suffix), 
+                verbs_device.rq.clone(), true, device as u8, rq_channel, stop.clone());
+        }
+
+        if Self::get_rail_cnt() > 1 {
+            Self::spawn_rail_monitor();
+        }
     }
 
-    fn poll_cq(cq: &std::sync::Arc<ibverbs::CompletionQueue>)
+    pub fn get_rail_cnt() -> usize
     {
-        let mut completions = [ibverbs::ibv_wc::default(); 32];
+        IBVERBS_RAILS.get().map(|rails| rails.len()).unwrap_or(1)
+    }
 
-        loop {
-            let completed = cq.poll(&mut completions[..]).unwrap();
-            if completed.is_empty() {
-                continue;
-            }
-            for wc in completed {
-                match wc.opcode() {
-                    ibverbs::ibv_wc_opcode::IBV_WC_SEND => {
-                        let wr_id = wc.wr_id();
-                        trace!("IBV_WC_SEND wr_id={}", wr_id);
-                        Self::release_occupied_smr(wr_id);
-                    }
-                    ibverbs::ibv_wc_opcode::IBV_WC_RECV => {
-                        let wr_id = wc.wr_id();
-                        trace!("IBV_WC_RECV wr_id={}", wr_id);
-                        Self::on_recv(wr_id);
-                        Self::release_occupied_rmr(wr_id);
-                    }
-                    _ => {
-                        error!("unexpected completion: {:?}", wc.error());
-                        // panic!("unexpected completion");
-                    }
-                }
-            }
+    fn get_device_cnt() -> usize
+    {
+        IBVERBS_DEVICES.get().map(|devices| devices.len()).unwrap_or(0)
+    }
+
+    // Verbs resources of a device, 0 being the device of the first rail. 
+    fn get_device(device: u8) -> &'static RpcVerbsDevice
+    {
+        &IBVERBS_DEVICES.get().unwrap()[device as usize]
+    }
+
+    fn get_rail_device_id(rail: u8) -> u8
+    {
+        IBVERBS_RAIL_DEVICES.get().unwrap()
+            .get(rail as usize).cloned()
+            .unwrap_or(0)
+    }
+
+    fn is_rail_up(rail: u8) -> bool
+    {
+        IBVERBS_RAIL_UP.get().unwrap()
+            .get(rail as usize)
+            .map(|up| up.load(std::sync::atomic::Ordering::Relaxed))
+            .unwrap_or(false)
+    }
+
+    // Rails the session still sends on. 
+    pub fn get_session_rails(session_id: u32) -> Vec<RpcRail>
+    {
+        let rails = IBVERBS_RAILS.get().unwrap();
+        match IBVERBS_QP_MAP.get().unwrap().lock().unwrap().get(&session_id) {
+            Some(qps) => qps.iter()
+                .filter(|rail_qp| Self::is_qp_usable(rail_qp))
+                .filter_map(|rail_qp| rails.get(rail_qp.rail as usize).cloned())
+                .collect(),
+            None => Vec::new(),
         }
     }
 
-    fn get_vacant_smr(&self, wr_id: u64) -> Option<&mut ibverbs::MemoryRegion<u8>>
+    // Checks the rail ports periodically. Sessions stop sending on a 
+    // rail that went down and resume once it is up again; what was in 
+    // flight on it is posted again on the others as it gets flushed. 
+    fn spawn_rail_monitor()
     {
-        let mut mr_map = IBVERBS_SMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        let mut mr_map_inv = IBVERBS_SMR_MAP_INV.get().unwrap()
-            .lock().unwrap(); // mr_index -> wr_id
-        let mr_vec_len = IBVERBS_SMR_VEC.get().unwrap().len();
-        for i in 0..mr_vec_len // mr_index 
-        {
-            let mr_index = i as u32;
-            if !mr_map_inv.contains_key(&mr_index)
-            {
-                mr_map.insert(wr_id, mr_index);
-                mr_map_inv.insert(mr_index, wr_id);
-
-                let vacant_mr = IBVERBS_SMR_VEC.get().unwrap()
-                    .get(i).unwrap();
-                let vacant_mr_mut: &mut MemoryRegion<u8>;
-                unsafe {
-                    vacant_mr_mut = get_mut_from_immut(vacant_mr);
+        let conf = RPC_CONF.get().unwrap();
+        let interval = std::time::Duration::from_millis(
+            std::cmp::max(conf.rail_check_ms, 1));
+
+        std::thread::Builder::new()
+            .name("srpc-rail-monitor".to_string())
+            .spawn(move || {
+                let rails = IBVERBS_RAILS.get().unwrap();
+                let rail_up = IBVERBS_RAIL_UP.get().unwrap();
+                loop {
+                    std::thread::sleep(interval);
+                    for (rail, up) in rail_up.iter().enumerate() {
+                        let device = Self::get_device(Self::get_rail_device_id(rail as u8));
+                        let active = is_port_active(&device.ctx, rails[rail].port);
+                        let was_active = up.swap(
+                            active, 
+                            std::sync::atomic::Ordering::Relaxed
+                        );
+                        if was_active && !active {
+                            error!("rail {} ({} port {}) went down", 
+                                rail, device.name, rails[rail].port);
+                        } else if !was_active && active {
+                            info!("rail {} ({} port {}) is up", 
+                                rail, device.name, rails[rail].port);
+                        }
+                    }
                 }
-                trace!("get_vacant_mr: wr_id = {}, mr_index = {}", wr_id, mr_index);
-                return Some(vacant_mr_mut);
+            })
+            .unwrap();
+    }
+
+    // Whether sends may go on the queue pair: on a rail up, 
+    // and not failed already. 
+    fn is_qp_usable(rail_qp: &RpcRailQp<ibverbs::QueuePair>) -> bool
+    {
+        Self::is_rail_up(rail_qp.rail) 
+            && !IBVERBS_FAILED_QPN_SET.get().unwrap().lock().unwrap()
+                .contains(&rail_qp.qp.qp_num())
+    }
+
+    // Whether one-sided operations may go on the queue pair. The regions 
+    // they read and write are registered with the first device, so they 
+    // take its rails only, which are the first device of the peer too 
+    // when both ends list their rails alike. 
+    fn is_qp_usable_remote(rail_qp: &RpcRailQp<ibverbs::QueuePair>) -> bool
+    {
+        rail_qp.device == 0 && Self::is_qp_usable(rail_qp)
+    }
+
+    // Records that a work request failed on the queue pair of the 
+    // session, which leaves it in the error state, along with the error 
+    // unless the request was only flushed by an earlier failure. 
+    // Returns whether the session still has a queue pair to send on. 
+    fn on_qp_failed(session_id: u32, qp_num: u32, error: RpcWcError) -> bool
+    {
+        if error != RpcWcError::Flushed {
+            IBVERBS_SESSION_ERROR_MAP.get().unwrap().lock().unwrap()
+                .entry(session_id)
+                .or_insert(error);
+        }
+        let newly_failed = IBVERBS_FAILED_QPN_SET.get().unwrap().lock().unwrap()
+            .insert(qp_num);
+
+        let qp_map = IBVERBS_QP_MAP.get().unwrap().lock().unwrap();
+        let qps = match qp_map.get(&session_id) {
+            Some(qps) => qps,
+            None => return false,
+        };
+        if newly_failed {
+            if let Some(rail_qp) = qps.iter().find(|rail_qp| rail_qp.qp.qp_num() == qp_num) {
+                info!("on_qp_failed: session {} stops sending on rail {}", 
+                    session_id, rail_qp.rail);
             }
         }
-        None
+        qps.any_usable(Self::is_qp_usable)
     }
 
-    fn get_vacant_rmr(&self, wr_id: u64) -> Option<&mut ibverbs::MemoryRegion<u8>>
+    // Opens the datagram queue pair on the first rail, on the shared 
+    // completion queues of its device, and fills its receive queue. 
+    fn init_ud()
     {
-        let mut mr_map = IBVERBS_RMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        let mut mr_map_inv = IBVERBS_RMR_MAP_INV.get().unwrap()
-            .lock().unwrap(); // mr_index -> wr_id
-        let mr_vec_len = IBVERBS_RMR_VEC.get().unwrap().len();
-        for i in 0..mr_vec_len // mr_index 
-        {
-            let mr_index = i as u32;
-            if !mr_map_inv.contains_key(&mr_index)
-            {
-                mr_map.insert(wr_id, mr_index);
-                mr_map_inv.insert(mr_index, wr_id);
-
-                let vacant_mr = IBVERBS_RMR_VEC.get().unwrap()
-                    .get(i).unwrap();
-                let vacant_mr_mut: &mut MemoryRegion<u8>;
-                unsafe {
-                    vacant_mr_mut = get_mut_from_immut(vacant_mr);
-                }
-                trace!("get_vacant_mr: wr_id = {}, mr_index = {}", wr_id, mr_index);
-                return Some(vacant_mr_mut);
+        let conf = RPC_CONF.get().unwrap();
+        let device = Self::get_device(0);
+        let pd = &device.pd;
+
+        // a datagram is one packet, routing header included 
+        let port = IBVERBS_RAILS.get().unwrap()[0].port;
+        let path_mtu = match conf.path_mtu {
+            0 => get_active_mtu(&device.ctx, port),
+            path_mtu => Some(path_mtu),
+        };
+        if let Some(path_mtu) = path_mtu {
+            if IBVERBS_GRH_LEN + conf.ud_msg_size as usize > path_mtu as usize {
+                error!("datagrams of {} bytes exceed the path mtu {} of port {}, not opening the datagram queue pair", 
+                    conf.ud_msg_size, path_mtu, port);
+                return;
+            }
+        }
+
+        let qp = pd.create_qp(
+            &device.sq, 
+            64, 
+            &device.rq, 
+            std::cmp::max(conf.ud_depth, 1), 
+            ibverbs::ibv_qp_type::IBV_QPT_UD
+        )
+            .set_port_num(IBVERBS_RAILS.get().unwrap()[0].port)
+            .set_gid_index(IBVERBS_RAILS.get().unwrap()[0].gid_index)
+            .build().unwrap();
+        let endpoint = qp.endpoint();
+        let qp = match qp.activate_ud(IBVERBS_UD_QKEY) {
+            Ok(qp) => qp,
+            Err(e) => {
+                error!("failed to open datagram queue pair: {}", e);
+                return;
+            }
+        };
+        info!("datagram endpoint: {:?}", endpoint);
+
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new();
+        if let Err(e) = endpoint.serialize(&mut serializer) {
+            error!("failed to serialize datagram endpoint: {}", e);
+            return;
+        }
+        let _result = IBVERBS_UD_ENDPOINT.set(serializer.view().to_vec());
+
+        // received datagrams are preceded by the routing header 
+        let depth = std::cmp::max(conf.ud_depth, 1);
+        let smr_pool = RpcMrPool::new((0..depth).map(|_| {
+            pd.allocate::<u8>(conf.ud_msg_size as usize).unwrap()
+        }).collect());
+        let rmr_pool = RpcMrPool::new((0..depth).map(|_| {
+            pd.allocate::<u8>(IBVERBS_GRH_LEN + conf.ud_msg_size as usize).unwrap()
+        }).collect());
+        let _result = IBVERBS_UD_SMR_POOL.set(smr_pool);
+        let _result = IBVERBS_UD_RMR_POOL.set(rmr_pool);
+        let _result = IBVERBS_UD_QP.set(std::sync::Mutex::new(qp));
+
+        for _ in 0..depth {
+            if let Err(e) = Self::post_ud_recv() {
+                error!("failed to post datagram receives: {}", e);
+                break;
             }
         }
-        None
     }
 
-    fn release_occupied_smr(wr_id: u64)
+    pub fn is_ud_enabled() -> bool
     {
-        let mut mr_map = IBVERBS_SMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        let mut mr_map_inv = IBVERBS_SMR_MAP_INV.get().unwrap()
-            .lock().unwrap(); // mr_index -> wr_id
-        if mr_map.contains_key(&wr_id)
-        {
-            let mr_index = mr_map.remove(&wr_id).unwrap();
-            mr_map_inv.remove(&mr_index);
-        }
+        IBVERBS_UD_QP.get().is_some()
     }
 
-    fn release_occupied_rmr(wr_id: u64)
+    // Endpoint of the datagram queue pair to advertise, 
+    // empty without one. 
+    pub fn get_ud_endpoint() -> Vec<u8>
     {
-        let mut mr_map = IBVERBS_RMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        let mut mr_map_inv = IBVERBS_RMR_MAP_INV.get().unwrap()
-            .lock().unwrap(); // mr_index -> wr_id
-        if mr_map.contains_key(&wr_id)
-        {
-            let mr_index = mr_map.remove(&wr_id).unwrap();
-            mr_map_inv.remove(&mr_index);
-        }
+        IBVERBS_UD_ENDPOINT.get().cloned().unwrap_or_default()
     }
 
-    pub fn send_to(&self, session_id: u32, bin: &[u8])
+    // Records the datagram endpoint a peer advertised, 
+    // dropping the address handle cached for its previous one. 
+    pub fn add_ud_peer(peer_id: u32, endpoint_bin: &[u8])
     {
-        let conf = RPC_CONF.get().unwrap();
-        let mr_size = conf.loc_mr_size as usize;
+        let endpoint = flexbuffers::Reader::get_root(endpoint_bin)
+            .map_err(|e| e.to_string())
+            .and_then(|reader| ibverbs::QueuePairEndpoint::deserialize(reader)
+                .map_err(|e| e.to_string()));
+        let endpoint = match endpoint {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                error!("bad datagram endpoint of peer {}: {}", peer_id, e);
+                return;
+            }
+        };
 
-        let data_len = bin.len();
-        trace!("send_to: session_id = {}, data_len = {}", session_id, data_len);
-        trace!("send_to: raw_msg_len = {:?}", u16::from_be_bytes([
-            bin[mr_size - 2], 
-            bin[mr_size - 1]]
-        ));
+        let prev = IBVERBS_UD_PEER_MAP.get().unwrap().write().unwrap()
+            .insert(peer_id, endpoint);
+        let mut src_map = IBVERBS_UD_SRC_MAP.get().unwrap().write().unwrap();
+        if let Some(prev) = prev {
+            src_map.remove(&RpcUdSource::of_endpoint(&prev));
+        }
+        src_map.insert(RpcUdSource::of_endpoint(&endpoint), peer_id);
+        IBVERBS_AH_MAP.get().unwrap().lock().unwrap()
+            .remove(&peer_id);
+    }
 
-        // invoke network to send request
-        let qp_map = IBVERBS_QP_MAP.get().unwrap();
-        let mut qp_map = qp_map.lock().unwrap();
-        let qp = qp_map.get_mut(&session_id).unwrap();
+    pub fn has_ud_peer(peer_id: u32) -> bool
+    {
+        Self::is_ud_enabled() 
+            && IBVERBS_UD_PEER_MAP.get().unwrap().read().unwrap()
+                .contains_key(&peer_id)
+    }
+
+    // Largest frame a datagram carries. 
+    pub fn get_ud_msg_size() -> usize
+    {
+        RPC_CONF.get().unwrap().ud_msg_size as usize
+    }
 
-        let wr_id = self.get_wr_id();
-        trace!("post_receive trying to get_vacant_mr: wr_id = {}", wr_id);
-        let mut mr_recv = self.get_vacant_rmr(wr_id).unwrap(); 
+    fn post_ud_recv() -> Result<(), RpcNetworkError>
+    {
+        let rmr_pool = IBVERBS_UD_RMR_POOL.get().unwrap();
+        let rmr_index = rmr_pool.acquire()
+            .ok_or(RpcNetworkError::MrExhausted)?;
+        let wr_id = Self::make_wr_id(Self::get_wr_id(), true, 0, IBVERBS_UD_CLASS, rmr_index);
+        let mut mr_recv = unsafe { rmr_pool.get_buf_mut(rmr_index) }.unwrap();
 
-        IBVERBS_WRID_MAP.get().unwrap()
-            .lock().unwrap()
-            .insert(wr_id, session_id);
-        unsafe { 
+        let mut qp = IBVERBS_UD_QP.get().unwrap().lock().unwrap();
+        let result = unsafe {
             qp.post_receive(
                 &mut mr_recv, 
                 .., 
                 wr_id
-            ) 
-        }.unwrap();
-        trace!("post_receive: wr_id = {}", wr_id);
-
-        let wr_id = self.get_wr_id();
-        trace!("post_send trying to get_vacant_mr: wr_id = {}", wr_id);
-        let mut mr_send = self.get_vacant_smr(wr_id).unwrap(); 
-        mr_send.clone_from_slice(bin);
-        IBVERBS_WRID_MAP.get().unwrap()
-            .lock().unwrap()
-            .insert(wr_id, session_id);
-        unsafe { 
-            qp.post_send(
+            )
+        };
+        if let Err(e) = result {
+            rmr_pool.release(rmr_index);
+            return Err(RpcNetworkError::Verbs(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Sends a frame to the peer as one datagram, through an address 
+    // handle created on the first datagram to the peer and reused after. 
+    // Datagrams may be lost, they take no credit. 
+    pub fn send_ud(peer_id: u32, header: &RpcFrameHeader, body: &[u8]) -> Result<(), RpcNetworkError>
+    {
+        let frame_len = header.frame_len();
+        if frame_len > Self::get_ud_msg_size() {
+            return Err(RpcNetworkError::MsgTooLarge(frame_len));
+        }
+        let endpoint = IBVERBS_UD_PEER_MAP.get().unwrap().read().unwrap()
+            .get(&peer_id).cloned()
+            .ok_or(RpcNetworkError::NoPeer(peer_id))?;
+
+        let mut ah_map = IBVERBS_AH_MAP.get().unwrap().lock().unwrap();
+        if !ah_map.contains_key(&peer_id) {
+            let ah = Self::get_device(0).pd.create_ah(&endpoint)
+                .map_err(|e| RpcNetworkError::Verbs(e.to_string()))?;
+            ah_map.insert(peer_id, ah);
+        }
+        let ah = ah_map.get(&peer_id).unwrap();
+
+        let smr_pool = IBVERBS_UD_SMR_POOL.get().unwrap();
+        let smr_index = smr_pool.acquire()
+            .ok_or(RpcNetworkError::MrExhausted)?;
+        if let Err(e) = Self::reserve_inflight() {
+            smr_pool.release(smr_index);
+            return Err(e);
+        }
+        let mut mr_send = unsafe { smr_pool.get_buf_mut(smr_index) }.unwrap();
+        header.write_to(&mut mr_send[..]);
+        mr_send[SRPC_FRAME_HEADER_LEN as usize..frame_len].copy_from_slice(body);
+
+        let wr_id = Self::make_wr_id(Self::get_wr_id(), false, 0, IBVERBS_UD_CLASS, smr_index);
+        let mut qp = IBVERBS_UD_QP.get().unwrap().lock().unwrap();
+        let result = unsafe {
+            qp.post_send_ud(
                 &mut mr_send, 
-                .., 
+                ..frame_len, 
+                ah, 
+                endpoint.num, 
+                IBVERBS_UD_QKEY, 
                 wr_id
             )
-        }.unwrap();
-        trace!("post_send: wr_id = {}", wr_id);
+        };
+        if let Err(e) = result {
+            Self::release_inflight();
+            smr_pool.release(smr_index);
+            return Err(RpcNetworkError::Verbs(e.to_string()));
+        }
+        trace!("send_ud: wr_id = {}, peer_id = {}, len = {}", wr_id, peer_id, frame_len);
 
+        Ok(())
     }
 
-    pub fn on_recv(wr_id: u64)
+    fn on_ud_send(wr_id: u64)
     {
-        let conf = RPC_CONF.get().unwrap();
-        let mr_size = conf.loc_mr_size as usize;
+        let smr_index = Self::get_mr_index_by_wr_id(wr_id);
+        IBVERBS_UD_SMR_POOL.get().unwrap().release(smr_index);
+        Self::on_send_vacancy();
+    }
+
+    // Hands a received datagram to the dispatcher, then reposts 
+    // its buffer. Datagrams from unknown queue pairs are dropped. 
+    fn on_ud_recv(wc: &ibverbs::ibv_wc)
+    {
+        let wr_id = wc.wr_id();
+        let rmr_pool = IBVERBS_UD_RMR_POOL.get().unwrap();
+        let rmr_index = Self::get_mr_index_by_wr_id(wr_id);
 
-        let session_id = Self::get_session_id_by_wr_id(wr_id);
-        let mr_index = Self::get_rmr_index_by_wr_id(wr_id).unwrap();
+        let mr_recv = rmr_pool.get_buf(rmr_index).unwrap();
+        let source = RpcUdSource::of_wc(wc, &mr_recv[..]);
+        let peer_id = IBVERBS_UD_SRC_MAP.get().unwrap().read().unwrap()
+            .get(&source).cloned();
+        match peer_id {
+            Some(peer_id) => {
+                let len = std::cmp::min(wc.len(), mr_recv.len());
+                if len > IBVERBS_GRH_LEN {
+                    RPC_DISPATCHER.get().unwrap().on_recv_datagram(
+                        peer_id, &mr_recv[IBVERBS_GRH_LEN..len]);
+                }
+            },
+            None => {
+                trace!("on_ud_recv: datagram from unknown source {:?}", source);
+            },
+        }
 
-        trace!("on_recv: wr_id = {}, mr_index = {}", wr_id, mr_index);
+        rmr_pool.release(rmr_index);
+        if let Err(e) = Self::post_ud_recv() {
+            error!("on_ud_recv: failed to repost: {}", e);
+        }
+    }
 
-        let mr = IBVERBS_RMR_VEC.get().unwrap()
-            .get(mr_index as usize).unwrap();
-        let mr_mut = unsafe { get_mut_from_immut(mr) };
-        let raw_msg_len = u16::from_be_bytes([
-            mr_mut[mr_size - 2], 
-            mr_mut[mr_size - 1]
-            ]);
-        trace!("on_recv: raw_msg_len = {:?}", raw_msg_len);
-        
-        let mut mr_vec = vec![0; mr_size];
-        let mr_slice = mr_vec.as_mut_slice();
-        trace!("mr_slice.len() = {}", mr_slice.len());
-        mr_mut.swap_with_slice(mr_slice);
+    // Creates a completion queue of the configured depth on the device, 
+    // along with the channel signalling its completions unless polling 
+    // busily. 
+    fn create_cq(ctx: &ibverbs::Context, cq_id: isize) -> (
+        std::sync::Arc<ibverbs::CompletionQueue>, 
+        Option<std::sync::Arc<ibverbs::CompletionChannel>>
+    )
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let cq_depth = conf.cq_depth as i32;
 
-        RPC_DISPATCHER.get().unwrap().on_recv_msg(session_id, mr_slice);
+        if conf.poll_mode == RpcPollMode::Busy {
+            let cq = ctx.create_cq(cq_depth, cq_id).unwrap();
+            (std::sync::Arc::new(cq), None)
+        } else {
+            let channel = ctx.create_comp_channel().unwrap();
+            let cq = ctx.create_cq_with_channel(cq_depth, cq_id, &channel).unwrap();
+            (std::sync::Arc::new(cq), Some(std::sync::Arc::new(channel)))
+        }
     }
 
-    fn get_smr_index_by_wr_id(wr_id: u64) -> Option<u32>
+    // Creates the completion queues of a session on the device and 
+    // starts polling them, unless an earlier queue pair of the session 
+    // on the device did. Each session polls on pollers of its own, so 
+    // only the first --max-session-cq sessions get queues, None tells 
+    // the others to use the shared ones. 
+    fn create_session_cq(session_id: u32, device: u8) -> Option<(
+        std::sync::Arc<ibverbs::CompletionQueue>, 
+        std::sync::Arc<ibverbs::CompletionQueue>
+    )>
     {
-        let mr_map = IBVERBS_SMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        if mr_map.contains_key(&wr_id)
-        {
-            Some(mr_map.get(&wr_id).unwrap().clone())
+        let conf = RPC_CONF.get().unwrap();
+        let mut cq_map = IBVERBS_SESSION_CQ_MAP.get().unwrap().lock().unwrap();
+        if let Some((sq, rq, _)) = cq_map.get(&(session_id, device)) {
+            return Some((sq.clone(), rq.clone()));
         }
-        else
+        let sessions: std::collections::BTreeSet<u32> = cq_map.keys()
+            .map(|(cq_session_id, _)| *cq_session_id)
+            .collect();
+        if !sessions.contains(&session_id) 
+            && sessions.len() >= conf.max_session_cq as usize 
         {
-            None
+            trace!("create_session_cq: session {} polled on the shared queues", 
+                session_id);
+            return None;
         }
+
+        // ids 0 and 1 belong to the shared queues 
+        let ctx = &Self::get_device(device).ctx;
+        let (sq, sq_channel) = Self::create_cq(ctx, 2 + 2 * session_id as isize);
+        let (rq, rq_channel) = Self::create_cq(ctx, 3 + 2 * session_id as isize);
+        let stop = std::sync::Arc::new(RpcPollStop::new());
+        Self::spawn_poller(format!("srpc-poll-sq-{}-{}", session_id, device), 
+            sq.clone(), false, device, sq_channel, stop.clone());
+        Self::spawn_poller(format!("srpc-poll-rq-{}-{}", session_id, device), 
+            rq.clone(), true, device, rq_channel, stop.clone());
+
+        cq_map.insert((session_id, device), (sq.clone(), rq.clone(), stop));
+        Some((sq, rq))
     }
 
-    fn get_rmr_index_by_wr_id(wr_id: u64) -> Option<u32>
+    // Stops polling the completion queues of the session, which are 
+    // destroyed once their pollers let go of them. 
+    fn drop_session_cq(session_id: u32)
     {
-        let mr_map = IBVERBS_RMR_MAP.get().unwrap()
-            .lock().unwrap(); // wr_id -> mr_index
-        if mr_map.contains_key(&wr_id)
-        {
-            Some(mr_map.get(&wr_id).unwrap().clone())
+        let mut cq_map = IBVERBS_SESSION_CQ_MAP.get().unwrap().lock().unwrap();
+        let keys: Vec<(u32, u8)> = cq_map.keys()
+            .filter(|(cq_session_id, _)| *cq_session_id == session_id)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some((_, _, stop)) = cq_map.remove(&key) {
+                stop.stop();
+            }
         }
-        else
-        {
-            None
+    }
+
+    // Next core a polling thread is pinned to, if any are configured. 
+    fn next_poll_core() -> Option<core_affinity::CoreId>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        if conf.poll_cores.is_empty() {
+            return None;
         }
+
+        let poller = IBVERBS_POLLER_CNT.fetch_add(
+            1, 
+            std::sync::atomic::Ordering::Relaxed
+        );
+        let core = conf.poll_cores[poller % conf.poll_cores.len()];
+        core_affinity::get_core_ids()?
+            .into_iter()
+            .find(|core_id| core_id.id == core)
     }
 
-    pub fn on_send(wr_id: u64)
+    // Polls the queue according to the configured mode, either on 
+    // a dedicated thread or on the runtime, until `stop` is set. 
+    // The queue is watched through its channel when it has one. 
+    // `recv` tells whether the queue takes completions of receives 
+    // or of sends, `device` which device it is on. 
+    fn spawn_poller(
+        name: std::string::String, 
+        cq: std::sync::Arc<ibverbs::CompletionQueue>, 
+        recv: bool, 
+        device: u8, 
+        channel: Option<std::sync::Arc<ibverbs::CompletionChannel>>, 
+        stop: std::sync::Arc<RpcPollStop>
+    )
     {
-        let smr_index = Self::get_smr_index_by_wr_id(wr_id).unwrap();
+        let conf = RPC_CONF.get().unwrap();
+
+        if conf.poll_thread {
+            let core_id = Self::next_poll_core();
+            let result = std::thread::Builder::new()
+                .name(name.clone())
+                .spawn(move || {
+                    if let Some(core_id) = core_id {
+                        core_affinity::set_for_current(core_id);
+                    }
+                    match channel {
+                        Some(channel) => {
+                            // a reactor of its own watches the channel 
+                            let runtime = tokio::runtime::Builder::new_current_thread()
+                                .enable_all()
+                                .build();
+                            match runtime {
+                                Ok(runtime) => runtime.block_on(
+                                    Self::poll_cq_event(&cq, recv, device, &channel, &stop)),
+                                Err(e) => {
+                                    error!("{}: no runtime to watch the channel: {}, polling busily", 
+                                        std::thread::current().name().unwrap_or(""), e);
+                                    Self::poll_cq(&cq, recv, device, &stop);
+                                },
+                            }
+                        },
+                        None => Self::poll_cq(&cq, recv, device, &stop),
+                    }
+                });
+            if let Err(e) = result {
+                error!("failed to start polling thread {}: {}", name, e);
+            }
+            return;
+        }
 
+        match channel {
+            Some(channel) => {
+                let _poll_handle = tokio::spawn(async move {
+                    Self::poll_cq_event(&cq, recv, device, &channel, &stop).await;
+                });
+            },
+            None => {
+                // keep the spinning off the async workers 
+                let _poll_handle = tokio::task::spawn_blocking(move || {
+                    Self::poll_cq(&cq, recv, device, &stop);
+                });
+            },
+        }
     }
 
-    // FIXME: change return value to Result<> type 
-    pub async fn connect_to(&self, session_id: u32, peer_uri: &str) -> bool
+    fn poll_cq(
+        cq: &std::sync::Arc<ibverbs::CompletionQueue>, 
+        recv: bool, 
+        device: u8, 
+        stop: &RpcPollStop
+    )
     {
-        trace!("connect_to: session_id = {}, peer_uri = {}", session_id, peer_uri);
-        self.conn_map.write().unwrap()
-            .insert(session_id, (Vec::new(), Vec::new()));
-        
-        // connect 
-        let pd = IBVERBS_PD.get().unwrap();
-        let sq = IBVERBS_SQ.get().unwrap();
-        let rq = IBVERBS_RQ.get().unwrap();
-        let qp_builder = pd.create_qp(
-            &sq, 
-            64, 
-            &rq, 
-            64, 
-            ibverbs::ibv_qp_type::IBV_QPT_RC
-        ).build().unwrap();
-        let loc_endpoint = 
-            qp_builder.endpoint(); // local endpoint 
+        let conf = RPC_CONF.get().unwrap();
+        let mut completions = vec![ibverbs::ibv_wc::default(); conf.poll_batch];
 
-        let mut serializer = 
-            flexbuffers::FlexbufferSerializer::new(); 
-        loc_endpoint.serialize(&mut serializer).unwrap();
-        let endpoint_bin = serializer.view();
-        let endpoint_bin_vec = endpoint_bin.to_vec();
-
-        let rmt_endpoint = 
-            SrpcGrpcPreComm::get_endpoint(
-                &loc_endpoint, 
-                peer_uri
-            ).await.unwrap(); 
-
-        let qp = qp_builder.handshake(rmt_endpoint).unwrap(); 
-        info!("qp_map insert session_id: {}", session_id);
-        IBVERBS_QP_MAP.get().unwrap().lock().unwrap()
-            .insert(session_id, qp);
-        
-        true
+        while !stop.is_stopped() {
+            Self::poll_cq_once(cq, recv, device, &mut completions);
+        }
     }
 
-    pub fn disconnect(&mut self, conn_id: u32)
+    // Polls the queue busily for `spin` after the last completion, 
+    // then sleeps until the completion channel signals. Should the 
+    // channel fail, the queue is polled busily from then on, so that 
+    // its sessions keep completing. 
+    async fn poll_cq_event(
+        cq: &std::sync::Arc<ibverbs::CompletionQueue>, 
+        recv: bool, 
+        device: u8, 
+        channel: &std::sync::Arc<ibverbs::CompletionChannel>, 
+        stop: &RpcPollStop
+    )
     {
-        // invoke network module to disconnect
+        let conf = RPC_CONF.get().unwrap();
+        let mut completions = vec![ibverbs::ibv_wc::default(); conf.poll_batch];
+
+        let result = Self::poll_cq_notified(cq, recv, device, channel, stop, 
+            &mut completions).await;
+        if let Err(e) = result {
+            error!("poll_cq_event: completion channel failed: {}, polling busily", e);
+            while !stop.is_stopped() {
+                Self::poll_cq_once(cq, recv, device, &mut completions);
+                // other tasks of the worker get their turn 
+                tokio::task::yield_now().await;
+            }
+        }
     }
-}
 
-impl RpcNetworkCore
-{
-    fn get_session_id_by_wr_id(wr_id: u64) -> u32
+    // Polls the queue as poll_cq_event tells, until `stop` is set 
+    // or the completion channel fails. 
+    async fn poll_cq_notified(
+        cq: &std::sync::Arc<ibverbs::CompletionQueue>, 
+        recv: bool, 
+        device: u8, 
+        channel: &std::sync::Arc<ibverbs::CompletionChannel>, 
+        stop: &RpcPollStop, 
+        completions: &mut [ibverbs::ibv_wc]
+    ) -> Result<(), std::string::String>
     {
-        let wrid_map = IBVERBS_WRID_MAP.get().unwrap();
-        let wrid_map_lock = wrid_map.lock().unwrap();
-        let session_id = wrid_map_lock.get(&wr_id).unwrap();
-        *session_id
+        use std::os::unix::io::AsRawFd;
+
+        let conf = RPC_CONF.get().unwrap();
+        let spin = if conf.poll_mode == RpcPollMode::Hybrid {
+            std::time::Duration::from_micros(conf.poll_spin_us)
+        } else {
+            std::time::Duration::ZERO
+        };
+
+        // drained until it would block, which must not block the worker 
+        let fd = channel.as_raw_fd();
+        Self::set_nonblocking(fd)
+            .map_err(|e| format!("channel {}: {}", fd, e))?;
+        let channel_fd = tokio::io::unix::AsyncFd::new(fd)
+            .map_err(|e| format!("channel {}: {}", fd, e))?;
+
+        while !stop.is_stopped() {
+            let mut last_completion = std::time::Instant::now();
+            loop {
+                if Self::poll_cq_once(cq, recv, device, completions) > 0 {
+                    last_completion = std::time::Instant::now();
+                } else if last_completion.elapsed() >= spin {
+                    break;
+                }
+                // other tasks of the worker get their turn while spinning 
+                tokio::task::yield_now().await;
+            }
+
+            // arm the queue, then catch completions that 
+            // arrived before it was armed 
+            cq.req_notify(false)
+                .map_err(|e| format!("arming the queue: {}", e))?;
+            if Self::poll_cq_once(cq, recv, device, completions) > 0 {
+                continue;
+            }
+
+            let mut guard = tokio::select! {
+                guard = channel_fd.readable() => guard
+                    .map_err(|e| format!("channel {}: {}", fd, e))?,
+                _ = stop.stopped() => break,
+            };
+            let mut events = 0;
+            while channel.get_cq_event().is_ok() {
+                events += 1;
+            }
+            // every event taken must be acknowledged, or destroying 
+            // the queue waits for it forever 
+            if events > 0 {
+                cq.ack_events(events);
+            }
+            guard.clear_ready();
+        }
+
+        Ok(())
+    }
+
+    fn set_nonblocking(fd: std::os::unix::io::RawFd) -> std::io::Result<()>
+    {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Reclaims what a failed work request held, then fails its session 
+    // unless it has another queue pair to go on with. Sends flushed 
+    // from the failed queue pair never left, they are posted again on 
+    // another one; those that cannot be, as well as the one that failed, 
+    // give back what they took. 
+    fn on_wc_error(
+        wc: &ibverbs::ibv_wc, 
+        recv: bool, 
+        device: u8, 
+        status: ibverbs::ibv_wc_status::Type, 
+        vendor_err: u32
+    )
+    {
+        let wr_id = wc.wr_id();
+        let class_id = Self::get_mr_class_by_wr_id(wr_id);
+        let error = RpcWcError::from_status(status);
+        if error == RpcWcError::Flushed {
+            trace!("on_wc_error: wr_id = {} flushed, qp_num = {}", wr_id, wc.qp_num);
+        } else {
+            error!("work request {} failed on qp_num {}: {} (vendor error {:#x})", 
+                wr_id, wc.qp_num, error, vendor_err);
+        }
+
+        let session_id = match class_id {
+            IBVERBS_UD_CLASS => {
+                // datagrams belong to no session 
+                let index = Self::get_mr_index_by_wr_id(wr_id);
+                if !recv {
+                    Self::on_ud_send(wr_id);
+                } else {
+                    IBVERBS_UD_RMR_POOL.get().unwrap().release(index);
+                    if error != RpcWcError::Flushed {
+                        let _ = Self::post_ud_recv();
+                    }
+                }
+                return;
+            },
+            IBVERBS_REMOTE_OP_CLASS if !recv => {
+                // the caller gets the region back with the error 
+                Self::on_remote_complete(wr_id, Some(error))
+            },
+            IBVERBS_LANDING_CLASS if !recv => {
+                // the peer got no frame, so sends no release of its 
+                // landing region either 
+                let session_id = Self::find_session_id_by_wr_id(wr_id);
+                let send = IBVERBS_SEND_MAP.get().unwrap().lock().unwrap()
+                    .get(&wr_id)
+                    .map(|send| (send.credited, send.returned));
+                Self::release_wr_id(wr_id);
+                Self::on_send_vacancy();
+                if let (Some(session_id), Some((credited, returned))) = (session_id, send) {
+                    RPC_DISPATCHER.get().unwrap().on_landing_dropped(
+                        session_id, credited, returned);
+                }
+                session_id
+            },
+            _ if recv => {
+                let session_id = if Self::is_srq_enabled() {
+                    IBVERBS_QPN_MAP.get().unwrap().lock().unwrap()
+                        .get(&wc.qp_num).cloned()
+                } else {
+                    Self::find_session_id_by_wr_id(wr_id)
+                };
+                Self::release_recv(device, wr_id);
+                // shared receive queues outlive the failed queue pair 
+                if Self::is_srq_enabled() {
+                    Self::replenish_srq(device, class_id);
+                }
+                // receives flushed after their session was torn down 
+                if session_id.is_none() && error == RpcWcError::Flushed {
+                    trace!("on_wc_error: wr_id = {} of a dropped session", wr_id);
+                    return;
+                }
+                session_id
+            },
+            _ => {
+                Self::on_send_error(wc, wr_id, error);
+                return;
+            },
+        };
+
+        let session_id = match session_id {
+            Some(session_id) => session_id,
+            None => {
+                error!("on_wc_error: no session for wr_id {}", wr_id);
+                return;
+            }
+        };
+        let dispatcher = RPC_DISPATCHER.get().unwrap();
+        if !recv && class_id != IBVERBS_REMOTE_OP_CLASS 
+            && class_id != IBVERBS_LANDING_CLASS 
+        {
+            dispatcher.on_send_complete(session_id);
+        }
+        if !Self::on_qp_failed(session_id, wc.qp_num, error) {
+            dispatcher.on_session_failed(session_id, error);
+        }
+    }
+
+    // A send of a frame failed, or was flushed from its queue pair. 
+    fn on_send_error(wc: &ibverbs::ibv_wc, wr_id: u64, error: RpcWcError)
+    {
+        let session_id = Self::find_session_id_by_wr_id(wr_id);
+        let usable = match session_id {
+            Some(session_id) => Self::on_qp_failed(session_id, wc.qp_num, error),
+            None => false,
+        };
+        // the send failing may have reached the peer, only those 
+        // flushed after it are certain not to have 
+        if let Some(session_id) = session_id.filter(|_| usable && error == RpcWcError::Flushed) {
+            if Self::repost_send(session_id, wr_id) {
+                return;
+            }
+        }
+
+        let send = IBVERBS_SEND_MAP.get().unwrap().lock().unwrap()
+            .get(&wr_id)
+            .map(|send| (send.credited, send.returned));
+        Self::release_send(wr_id);
+        Self::on_send_vacancy();
+
+        let session_id = match session_id {
+            Some(session_id) => session_id,
+            None => {
+                error!("on_wc_error: no session for wr_id {}", wr_id);
+                return;
+            }
+        };
+        let dispatcher = RPC_DISPATCHER.get().unwrap();
+        match send {
+            Some((credited, returned)) => dispatcher.on_send_dropped(
+                session_id, credited, returned),
+            None => dispatcher.on_send_complete(session_id),
+        }
+        if !usable {
+            dispatcher.on_session_failed(session_id, error);
+        }
+    }
+
+    // Posts a send flushed from a failed queue pair again on another 
+    // queue pair of the session, under the same work request, which 
+    // keeps its in-flight slot. Returns whether it was posted. 
+    fn repost_send(session_id: u32, wr_id: u64) -> bool
+    {
+        let mut qp_map = IBVERBS_QP_MAP.get().unwrap().lock().unwrap();
+        let rail_qp = match qp_map.get_mut(&session_id)
+            .and_then(|qps| qps.next_usable(Self::is_qp_usable)) 
+        {
+            Some(rail_qp) => rail_qp,
+            None => return false,
+        };
+        // posted from where it is kept, the map is held meanwhile 
+        let send_map = IBVERBS_SEND_MAP.get().unwrap().lock().unwrap();
+        let send = match send_map.get(&wr_id) {
+            Some(send) => send,
+            None => return false,
+        };
+        let class_id = Self::get_mr_class_by_wr_id(wr_id);
+        let mr_index = Self::get_mr_index_by_wr_id(wr_id);
+        match Self::post_frame(rail_qp, class_id, mr_index, send, wr_id) {
+            Ok(_) => {
+                trace!("repost_send: wr_id = {} on rail {}", wr_id, rail_qp.rail);
+                true
+            },
+            Err(e) => {
+                error!("repost_send: session {}: wr_id {}: {}", session_id, wr_id, e);
+                false
+            },
+        }
+    }
+
+    // Handles the completions available on the queue, 
+    // returns how many there were. 
+    fn poll_cq_once(
+        cq: &std::sync::Arc<ibverbs::CompletionQueue>, 
+        recv: bool, 
+        device: u8, 
+        completions: &mut [ibverbs::ibv_wc]
+    ) -> usize
+    {
+        let completed = cq.poll(completions).unwrap();
+        let count = completed.len();
+        for wc in completed {
+            // the opcode of a failed completion is undefined, 
+            // the queue it came from tells sends and receives apart 
+            if let Some((status, vendor_err)) = wc.error() {
+                Self::on_wc_error(wc, recv, device, status, vendor_err);
+                continue;
+            }
+            if Self::get_mr_class_by_wr_id(wc.wr_id()) == IBVERBS_UD_CLASS {
+                match wc.opcode() {
+                    ibverbs::ibv_wc_opcode::IBV_WC_SEND => Self::on_ud_send(wc.wr_id()),
+                    ibverbs::ibv_wc_opcode::IBV_WC_RECV => Self::on_ud_recv(wc),
+                    opcode => error!("unexpected datagram completion: {:?}", opcode),
+                }
+                continue;
+            }
+            match wc.opcode() {
+                ibverbs::ibv_wc_opcode::IBV_WC_SEND => {
+                    let wr_id = wc.wr_id();
+                    trace!("IBV_WC_SEND wr_id={}", wr_id);
+                    Self::on_send(wr_id);
+                    Self::release_send(wr_id);
+                    Self::on_send_vacancy();
+                }
+                ibverbs::ibv_wc_opcode::IBV_WC_RECV => {
+                    let wr_id = wc.wr_id();
+                    trace!("IBV_WC_RECV wr_id={} qp_num={}", wr_id, wc.qp_num);
+                    let session_id = Self::get_session_id_by_wc(&wc);
+                    let class_id = Self::get_mr_class_by_wr_id(wr_id);
+                    if let Some(session_id) = session_id {
+                        Self::on_recv(wr_id, device, session_id, wc.len());
+                    }
+                    Self::release_recv(device, wr_id);
+                    Self::replenish(device, session_id, wc.qp_num, class_id);
+                }
+                ibverbs::ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM => {
+                    let wr_id = wc.wr_id();
+                    trace!("IBV_WC_RECV_RDMA_WITH_IMM wr_id={} qp_num={}", wr_id, wc.qp_num);
+                    let session_id = Self::get_session_id_by_wc(&wc);
+                    let class_id = Self::get_mr_class_by_wr_id(wr_id);
+                    // the receive buffer stays empty, 
+                    // the data is in the landing region 
+                    if let Some(session_id) = session_id {
+                        Self::on_recv_landing(session_id, wc.imm_data(), wc.len());
+                    }
+                    Self::release_recv(device, wr_id);
+                    Self::replenish(device, session_id, wc.qp_num, class_id);
+                }
+                ibverbs::ibv_wc_opcode::IBV_WC_RDMA_WRITE 
+                    if Self::get_mr_class_by_wr_id(wc.wr_id()) == IBVERBS_LANDING_CLASS => {
+                    let wr_id = wc.wr_id();
+                    trace!("IBV_WC_RDMA_WRITE landing wr_id={}", wr_id);
+                    Self::on_send(wr_id);
+                    Self::release_wr_id(wr_id);
+                    Self::on_send_vacancy();
+                }
+                ibverbs::ibv_wc_opcode::IBV_WC_RDMA_READ 
+                | ibverbs::ibv_wc_opcode::IBV_WC_RDMA_WRITE 
+                | ibverbs::ibv_wc_opcode::IBV_WC_FETCH_ADD 
+                | ibverbs::ibv_wc_opcode::IBV_WC_COMP_SWAP => {
+                    let wr_id = wc.wr_id();
+                    trace!("IBV_WC_RDMA wr_id={}", wr_id);
+                    Self::on_remote_complete(wr_id, None);
+                }
+                _ => {
+                    error!("unexpected completion: opcode {:?}, wr_id {}", 
+                        wc.opcode(), wc.wr_id());
+                    // panic!("unexpected completion");
+                }
+            }
+        }
+
+        count
+    }
+
+    // Memory region sizes a session may be bound to. 
+    pub fn get_mr_sizes() -> Vec<u32>
+    {
+        IBVERBS_SMR_SLAB.get().unwrap().get_sizes()
+    }
+
+    // Work request ids carry the size class and the index of the 
+    // memory region they use in their low bits, so that completions 
+    // find it without any lookup. Above them go whether the region is 
+    // one of receives and its device: a region is posted once at a 
+    // time, so no two work requests posted at once share an id, 
+    // however far the sequence number in the high bits wrapped. 
+    // Sends posted inline hold no region, the low bits of their 
+    // sequence number stand in for its index. 
+    fn make_wr_id(seq: u64, recv: bool, device: u8, class_id: u8, mr_index: u32) -> u64
+    {
+        (seq << IBVERBS_WR_SEQ_SHIFT) 
+            | (((device & IBVERBS_WR_DEVICE_MASK) as u64) << 41) 
+            | ((recv as u64) << 40) 
+            | ((class_id as u64) << 32) 
+            | (mr_index as u64)
+    }
+
+    fn get_mr_class_by_wr_id(wr_id: u64) -> u8
+    {
+        (wr_id >> 32) as u8
+    }
+
+    fn get_device_by_wr_id(wr_id: u64) -> u8
+    {
+        (wr_id >> 41) as u8 & IBVERBS_WR_DEVICE_MASK
+    }
+
+    fn get_mr_index_by_wr_id(wr_id: u64) -> u32
+    {
+        wr_id as u32
+    }
+
+    // Gives the memory region of a send back to the pool, unless the 
+    // send was inline and held none. 
+    fn release_occupied_smr(wr_id: u64)
+    {
+        let class_id = Self::get_mr_class_by_wr_id(wr_id);
+        if class_id == IBVERBS_INLINE_CLASS {
+            return;
+        }
+        let mr_index = Self::get_mr_index_by_wr_id(wr_id);
+        if let Some(pool) = IBVERBS_SMR_SLAB.get().unwrap().get_pool(class_id)
+        {
+            pool.release(mr_index);
+        }
+    }
+
+    // Gives back the memory region and the work request of a send that 
+    // completed, unless its session was dropped and took them back. 
+    fn release_send(wr_id: u64)
+    {
+        if Self::release_wr_id(wr_id) {
+            Self::release_occupied_smr(wr_id);
+        }
+    }
+
+    // Gives back the memory region and the work request of a receive 
+    // that completed, unless its session was dropped and took them back 
+    // already: a receive posted for a session is released by whoever 
+    // takes its work request. Buffers of shared receive queues belong 
+    // to no session and have no work request recorded. 
+    fn release_recv(device: u8, wr_id: u64)
+    {
+        if Self::release_wr_id(wr_id) || Self::is_srq_enabled() {
+            Self::release_occupied_rmr(device, wr_id);
+        }
+    }
+
+    fn release_occupied_rmr(device: u8, wr_id: u64)
+    {
+        let class_id = Self::get_mr_class_by_wr_id(wr_id);
+        let mr_index = Self::get_mr_index_by_wr_id(wr_id);
+        if let Some(pool) = Self::get_device(device).rmr_slab.get_pool(class_id)
+        {
+            pool.release(mr_index);
+        }
+    }
+
+    // Sends currently posted and not completed yet. 
+    pub fn get_inflight() -> u64
+    {
+        IBVERBS_INFLIGHT.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    // Largest frame the session sends inline, bounded by what its 
+    // queue pairs carry inline. Devices may grant some inline room 
+    // even when asked for none, --max-inline 0 still disables it. 
+    fn get_inline_limit(qps: &RpcSessionQps<ibverbs::QueuePair>) -> usize
+    {
+        let conf = RPC_CONF.get().unwrap();
+        if conf.max_inline == 0 {
+            return 0;
+        }
+        std::cmp::min(conf.inline_threshold, qps.max_inline()) as usize
+    }
+
+    // Largest frame the session sends inline, none if it is gone. 
+    pub fn get_session_inline_limit(session_id: u32) -> usize
+    {
+        IBVERBS_QP_MAP.get().unwrap().lock().unwrap()
+            .get(&session_id)
+            .map_or(0, |qps| Self::get_inline_limit(qps))
+    }
+
+    // Sends posted inline since the start. 
+    pub fn get_inline_cnt() -> u64
+    {
+        IBVERBS_INLINE_CNT.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    // Takes an in-flight slot within the configured limit before 
+    // a work request is posted, to be given back if posting fails. 
+    fn reserve_inflight() -> Result<(), RpcNetworkError>
+    {
+        let max_inflight = RPC_CONF.get().unwrap().max_inflight as u64;
+        IBVERBS_INFLIGHT.fetch_update(
+            std::sync::atomic::Ordering::AcqRel, 
+            std::sync::atomic::Ordering::Acquire, 
+            |n| {
+                if max_inflight != 0 && n >= max_inflight {
+                    None
+                } else {
+                    Some(n + 1)
+                }
+            }
+        )
+        .map(|_| ())
+        .map_err(|_| RpcNetworkError::InflightLimit)
+    }
+
+    fn release_inflight()
+    {
+        let _ = IBVERBS_INFLIGHT.fetch_update(
+            std::sync::atomic::Ordering::AcqRel, 
+            std::sync::atomic::Ordering::Acquire, 
+            |n| n.checked_sub(1)
+        );
+    }
+
+    fn on_send_vacancy()
+    {
+        Self::release_inflight();
+        IBVERBS_SEND_NOTIFY.get().unwrap().notify_waiters();
+    }
+
+    // Wakes up senders waiting for a vacancy, e.g. when 
+    // the peer returns credits. 
+    pub fn notify_send_vacancy()
+    {
+        IBVERBS_SEND_NOTIFY.get().unwrap().notify_waiters();
+    }
+
+    // Runs `attempt` until it succeeds or fails for good, waiting for 
+    // an in-flight send to complete after each retryable failure. 
+    // The waiter is registered before every attempt, so that a vacancy 
+    // coming in between the attempt and the wait is not missed. 
+    pub async fn retry_on_vacancy<T, F>(mut attempt: F) -> Result<T, RpcNetworkError>
+    where F: FnMut() -> Result<T, RpcNetworkError>
+    {
+        let notify = IBVERBS_SEND_NOTIFY.get().unwrap();
+        loop {
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            match attempt() {
+                Err(e) if e.is_retryable() => notified.await,
+                result => return result,
+            }
+        }
+    }
+
+    // Send work requests a queue pair holds, sessions keep 
+    // their in-flight sends within it. 
+    pub fn get_send_wr_cap() -> u32
+    {
+        RPC_CONF.get().unwrap().sq_depth
+    }
+
+    // Receive work requests a queue pair must hold. 
+    pub fn get_recv_wr_cap() -> u32
+    {
+        let conf = RPC_CONF.get().unwrap();
+        std::cmp::max(conf.recv_depth, 1)
+    }
+
+    // Credits the local end grants the peer of a session bound to memory 
+    // regions of `mr_size` bytes: one per receive buffer posted for it, 
+    // or its share of the shared receive queue of the class, split across 
+    // the sessions the node may hold. Frames striped across rails may 
+    // all land on one device, the share is that of a single device. 
+    pub fn get_recv_credits(mr_size: u32) -> u32
+    {
+        let conf = RPC_CONF.get().unwrap();
+        if !Self::is_srq_enabled() {
+            return RpcCredit::initial_credits(conf.recv_depth);
+        }
+        let rmr_slab = &Self::get_device(0).rmr_slab;
+        let count = rmr_slab.class_by_size(mr_size)
+            .and_then(|class_id| rmr_slab.get_class(class_id))
+            .map(|class| class.count)
+            .unwrap_or(0);
+        RpcCredit::shared_credits(
+            std::cmp::min(conf.srq_depth, count), conf.max_sessions)
+    }
+
+    // Path MTU the rails carry, the smallest active MTU of their ports, 
+    // 0 when none could be queried. 
+    pub fn get_active_path_mtu() -> u32
+    {
+        IBVERBS_RAILS.get().unwrap().iter()
+            .enumerate()
+            .filter_map(|(rail, rail_conf)| get_active_mtu(
+                &Self::get_device(Self::get_rail_device_id(rail as u8)).ctx, 
+                rail_conf.port))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn is_srq_enabled() -> bool
+    {
+        RPC_CONF.get().unwrap().srq
+    }
+
+    // Shared receive queue of the device holding the receives of 
+    // sessions bound to memory regions of `mr_size` bytes. 
+    fn get_srq_by_mr_size(device: u8, mr_size: u32) -> Option<&'static ibverbs::SharedReceiveQueue>
+    {
+        let verbs_device = Self::get_device(device);
+        let class_id = verbs_device.rmr_slab.class_by_size(mr_size)?;
+        verbs_device.srq_vec.get(class_id as usize)
+    }
+
+    // Creates the queue pairs of a session on every rail, in rail order. 
+    pub fn create_rail_qps(
+        session_id: u32, 
+        mr_size: u32
+    ) -> Result<Vec<ibverbs::PreparedQueuePair>, RpcNetworkError>
+    {
+        (0..Self::get_rail_cnt())
+            .map(|rail| Self::create_qp(session_id, mr_size, rail as u8))
+            .collect()
+    }
+
+    // Creates a queue pair of a session on the port of the rail, bound 
+    // to memory regions of `mr_size` bytes, attached to the shared 
+    // receive queue of the class on the device of the rail if any, on 
+    // completion queues of its own if configured so. 
+    pub fn create_qp(
+        session_id: u32, 
+        mr_size: u32, 
+        rail_id: u8
+    ) -> Result<ibverbs::PreparedQueuePair, RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let rail = &IBVERBS_RAILS.get().unwrap()[rail_id as usize];
+        let device = Self::get_rail_device_id(rail_id);
+        let verbs_device = Self::get_device(device);
+        let pd = &verbs_device.pd;
+        let session_cq = if conf.cq_per_session {
+            Self::create_session_cq(session_id, device)
+        } else {
+            None
+        };
+        let (sq, rq) = session_cq.unwrap_or_else(|| 
+            (verbs_device.sq.clone(), verbs_device.rq.clone()));
+        let mut qp_init = pd.create_qp(
+            &sq, 
+            Self::get_send_wr_cap(), 
+            &rq, 
+            Self::get_recv_wr_cap(), 
+            ibverbs::ibv_qp_type::IBV_QPT_RC
+        );
+        qp_init.set_port_num(rail.port);
+        qp_init.set_gid_index(rail.gid_index);
+        qp_init.set_max_inline_data(conf.max_inline);
+        qp_init.set_access(Self::get_remote_access());
+        if let Some(srq) = Self::get_srq_by_mr_size(device, mr_size) {
+            qp_init.set_srq(srq);
+        }
+        let qp = qp_init.build()
+            .map_err(|e| RpcNetworkError::Verbs(e.to_string()))?;
+        // known before anything may arrive on it 
+        IBVERBS_QPN_MAP.get().unwrap().lock().unwrap()
+            .insert(qp.endpoint().num, session_id);
+        Ok(qp)
+    }
+
+    // Makes the connected queue pairs of a session, one per rail in rail 
+    // order, reachable by it. Sends of the session are striped across 
+    // them in turn, each session starting on a rail of its own. 
+    pub fn bind_rail_qps(
+        session_id: u32, 
+        qps: Vec<ibverbs::QueuePair>
+    ) -> Result<(), RpcNetworkError>
+    {
+        if qps.is_empty() {
+            return Err(RpcNetworkError::NoSession(session_id));
+        }
+
+        let qps: Vec<RpcRailQp<ibverbs::QueuePair>> = qps.into_iter()
+            .enumerate()
+            .map(|(rail, qp)| RpcRailQp {
+                rail: rail as u8,
+                device: Self::get_rail_device_id(rail as u8),
+                // the device may grant more than asked, or less 
+                max_inline: qp.max_inline_data(),
+                qp: qp,
+            })
+            .collect();
+        info!("qp_map insert session_id: {}, qp_nums: {:?}", session_id, 
+            qps.iter().map(|rail_qp| rail_qp.qp.qp_num()).collect::<Vec<u32>>());
+        let first = session_id as usize;
+        IBVERBS_QP_MAP.get().unwrap().lock().unwrap()
+            .insert(session_id, RpcSessionQps::new(qps, first));
+
+        Ok(())
+    }
+
+    // Posts `count` receive buffers of the size class 
+    // to its shared receive queue on the device. 
+    fn post_srq_buffers(device: u8, class_id: u8, count: u32) -> Result<(), RpcNetworkError>
+    {
+        let verbs_device = Self::get_device(device);
+        let srq = &verbs_device.srq_vec[class_id as usize];
+        let rmr_pool = verbs_device.rmr_slab
+            .get_pool(class_id).unwrap();
+
+        for _ in 0..count
+        {
+            let rmr_index = rmr_pool.acquire()
+                .ok_or(RpcNetworkError::MrExhausted)?;
+            let wr_id = Self::make_wr_id(Self::get_wr_id(), true, device, class_id, rmr_index);
+            let mut mr_recv = unsafe { rmr_pool.get_buf_mut(rmr_index) }.unwrap(); 
+
+            let result = unsafe { 
+                srq.post_receive(
+                    &mut mr_recv, 
+                    .., 
+                    wr_id
+                ) 
+            };
+            if let Err(e) = result {
+                rmr_pool.release(rmr_index);
+                return Err(RpcNetworkError::Verbs(e.to_string()));
+            }
+            trace!("post_srq_receive: wr_id = {}, mr_index = {}", wr_id, rmr_index);
+        }
+
+        Ok(())
+    }
+
+    // Reposts a buffer to the shared receive queue of the class on the 
+    // device, together with any it failed to repost before. 
+    fn replenish_srq(device: u8, class_id: u8)
+    {
+        let mut deficit_map = IBVERBS_SRQ_DEFICIT.get().unwrap()
+            .lock().unwrap();
+        let count = 1 + deficit_map.remove(&(device, class_id)).unwrap_or(0);
+
+        let mut posted = 0;
+        for _ in 0..count
+        {
+            match Self::post_srq_buffers(device, class_id, 1) {
+                Ok(_) => posted += 1,
+                Err(e) => {
+                    error!("replenish_srq: device {}: class {}: {}", device, class_id, e);
+                    break;
+                }
+            }
+        }
+        if posted < count {
+            deficit_map.insert((device, class_id), count - posted);
+        }
+    }
+
+    // Posts a receive buffer of the size class of the device to the 
+    // queue pair, returns the work request id. 
+    fn post_recv_buffer<Q: RpcRecvQueue>(
+        qp: &mut Q, 
+        device: u8, 
+        session_id: u32, 
+        class_id: u8
+    ) -> Result<u64, RpcNetworkError>
+    {
+        let rmr_pool = Self::get_device(device).rmr_slab
+            .get_pool(class_id).unwrap();
+
+        let rmr_index = rmr_pool.acquire()
+            .ok_or(RpcNetworkError::MrExhausted)?;
+        let wr_id = Self::make_wr_id(Self::get_wr_id(), true, device, class_id, rmr_index);
+        let mr_recv = unsafe { rmr_pool.get_buf_mut(rmr_index) }.unwrap(); 
+
+        IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .insert(wr_id, session_id);
+        let result = unsafe { qp.post_recv_mr(mr_recv, wr_id) };
+        if let Err(e) = result {
+            Self::release_wr_id(wr_id);
+            rmr_pool.release(rmr_index);
+            return Err(RpcNetworkError::Verbs(e.to_string()));
+        }
+        trace!("post_receive: wr_id = {}, mr_index = {}", wr_id, rmr_index);
+
+        Ok(wr_id)
+    }
+
+    // Fills the receive queue of a queue pair of the session up to the 
+    // configured depth before it connects, so that the peer never finds 
+    // it empty. Nothing to do when the queue pair uses a shared receive 
+    // queue, which sessions on several rails always do; a session with 
+    // a receive queue of its own thus has one rail, on the first device. 
+    // On failure the buffers posted so far are taken back, which is safe 
+    // as nothing arrives before the queue pair connects; the queue pair 
+    // is to be dropped then. 
+    pub fn post_recv_depth(
+        qp: &mut ibverbs::PreparedQueuePair, 
+        session_id: u32, 
+        mr_size: u32
+    ) -> Result<(), RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let class_id = Self::get_device(0).rmr_slab
+            .class_by_size(mr_size)
+            .ok_or(RpcNetworkError::MsgTooLarge(mr_size as usize))?;
+        if Self::is_srq_enabled() {
+            return Ok(());
+        }
+
+        let mut posted = Vec::new();
+        for _ in 0..conf.recv_depth
+        {
+            match Self::post_recv_buffer(qp, 0, session_id, class_id) {
+                Ok(wr_id) => posted.push(wr_id),
+                Err(e) => {
+                    for wr_id in posted {
+                        Self::release_occupied_rmr(0, wr_id);
+                        Self::release_wr_id(wr_id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reposts a receive buffer for the one just consumed, to the shared 
+    // receive queue of the class on the device or to the queue pair of 
+    // the session. Buffers of sessions gone are not reposted. 
+    fn replenish(device: u8, session_id: Option<u32>, qp_num: u32, class_id: u8)
+    {
+        if Self::is_srq_enabled() {
+            Self::replenish_srq(device, class_id);
+        } else if let Some(session_id) = session_id {
+            Self::replenish_recv(device, session_id, qp_num, class_id);
+        }
+    }
+
+    // Reposts a receive buffer for the one just consumed, together 
+    // with any the session failed to repost before, to the queue pair 
+    // `qp_num` of the session it was consumed on. 
+    fn replenish_recv(device: u8, session_id: u32, qp_num: u32, class_id: u8)
+    {
+        let mut deficit_map = IBVERBS_RECV_DEFICIT.get().unwrap()
+            .lock().unwrap();
+        let count = 1 + deficit_map.remove(&session_id).unwrap_or(0);
+
+        let mut qp_map = IBVERBS_QP_MAP.get().unwrap().lock().unwrap();
+        let rail_qp = match qp_map.get_mut(&session_id)
+            .and_then(|qps| qps.get_by_qpn_mut(qp_num)) 
+        {
+            Some(rail_qp) => rail_qp,
+            None => return,
+        };
+
+        let mut posted = 0;
+        for _ in 0..count
+        {
+            match Self::post_recv_buffer(&mut rail_qp.qp, device, session_id, class_id) {
+                Ok(_) => posted += 1,
+                Err(e) => {
+                    error!("replenish_recv: session {}: {}", session_id, e);
+                    break;
+                }
+            }
+        }
+        if posted < count {
+            deficit_map.insert(session_id, count - posted);
+        }
+    }
+
+    // Takes a vacant send memory region of `mr_size` bytes, 
+    // returns its class and index. 
+    pub fn acquire_send_mr(mr_size: u32) -> Result<(u8, u32), RpcNetworkError>
+    {
+        let smr_slab = IBVERBS_SMR_SLAB.get().unwrap();
+        let class_id = smr_slab.class_by_size(mr_size)
+            .ok_or(RpcNetworkError::MsgTooLarge(mr_size as usize))?;
+        let smr_index = smr_slab.get_pool(class_id).unwrap()
+            .acquire()
+            .ok_or(RpcNetworkError::MrExhausted)?;
+        Ok((class_id, smr_index))
+    }
+
+    // The caller must hold the memory region, i.e. have acquired it 
+    // and neither released nor posted it yet. 
+    pub unsafe fn get_send_mr(class_id: u8, mr_index: u32) -> &'static mut [u8]
+    {
+        let smr_pool = IBVERBS_SMR_SLAB.get().unwrap()
+            .get_pool(class_id).unwrap();
+        &mut smr_pool.get_buf_mut(mr_index).unwrap()[..]
+    }
+
+    // Reads a send memory region without taking it over, the bytes 
+    // change under the reader unless it holds the region. 
+    pub fn peek_send_mr(class_id: u8, mr_index: u32) -> &'static [u8]
+    {
+        let smr_pool = IBVERBS_SMR_SLAB.get().unwrap()
+            .get_pool(class_id).unwrap();
+        &smr_pool.get_buf(mr_index).unwrap()[..]
+    }
+
+    pub fn release_send_mr(class_id: u8, mr_index: u32)
+    {
+        if let Some(pool) = IBVERBS_SMR_SLAB.get().unwrap().get_pool(class_id)
+        {
+            pool.release(mr_index);
+        }
+    }
+
+    // Posts the first `len` bytes of a held send memory region as 
+    // filled by the caller, on the queue pair of the session whose turn 
+    // it is, the region goes back to the pool once the send completes. 
+    // It stays with the caller if posting fails. `credited` tells whether 
+    // the frame took a credit of the peer and `returned` how many credits 
+    // it returns, both given back should the frame be lost. 
+    pub fn post_send_mr(
+        session_id: u32, 
+        class_id: u8, 
+        mr_index: u32, 
+        len: usize, 
+        credited: bool, 
+        returned: u32
+    ) -> Result<(), RpcNetworkError>
+    {
+        let qp_map = IBVERBS_QP_MAP.get().unwrap();
+        let mut qp_map = qp_map.lock().unwrap();
+        let rail_qp = qp_map.get_mut(&session_id)
+            .ok_or(RpcNetworkError::NoSession(session_id))?
+            .next_usable(Self::is_qp_usable)
+            .ok_or(RpcNetworkError::SessionFailed(session_id))?;
+        Self::reserve_inflight()?;
+
+        let wr_id = Self::make_wr_id(Self::get_wr_id(), false, 0, class_id, mr_index);
+        trace!("post_send: wr_id = {}, mr_index = {}, len = {}, rail = {}", 
+            wr_id, mr_index, len, rail_qp.rail);
+        let send = RpcPostedSend {
+            len: len,
+            inline: None,
+            credited: credited,
+            returned: returned,
+        };
+        Self::post_recorded(session_id, rail_qp, class_id, mr_index, send, wr_id)?;
+        trace!("post_send: wr_id = {}", wr_id);
+
+        Ok(())
+    }
+
+    // Posts a frame small enough to go inline, the work request 
+    // carrying its bytes so that no memory region is held for it. 
+    // The frame is kept until the send completes, to be posted again 
+    // should it be flushed. `credited` and `returned` are as for 
+    // post_send_mr. 
+    pub fn post_send_inline(
+        session_id: u32, 
+        frame: Vec<u8>, 
+        credited: bool, 
+        returned: u32
+    ) -> Result<(), RpcNetworkError>
+    {
+        let qp_map = IBVERBS_QP_MAP.get().unwrap();
+        let mut qp_map = qp_map.lock().unwrap();
+        let qps = qp_map.get_mut(&session_id)
+            .ok_or(RpcNetworkError::NoSession(session_id))?;
+        if frame.len() > Self::get_inline_limit(qps) {
+            return Err(RpcNetworkError::MsgTooLarge(frame.len()));
+        }
+        let rail_qp = qps.next_usable(Self::is_qp_usable)
+            .ok_or(RpcNetworkError::SessionFailed(session_id))?;
+        Self::reserve_inflight()?;
+
+        // no memory region tells the send apart, its sequence 
+        // number stands in for the index of one 
+        let seq = Self::get_wr_id();
+        let wr_id = Self::make_wr_id(seq, false, 0, IBVERBS_INLINE_CLASS, seq as u32);
+        trace!("post_send_inline: wr_id = {}, len = {}, rail = {}", 
+            wr_id, frame.len(), rail_qp.rail);
+        let send = RpcPostedSend {
+            len: frame.len(),
+            inline: Some(frame),
+            credited: credited,
+            returned: returned,
+        };
+        Self::post_recorded(session_id, rail_qp, IBVERBS_INLINE_CLASS, 0, send, wr_id)
+    }
+
+    // Records a send and posts it from where it is recorded, before 
+    // the completion may come. Takes back the work request and the 
+    // in-flight slot if posting fails. 
+    fn post_recorded(
+        session_id: u32, 
+        rail_qp: &mut RpcRailQp<ibverbs::QueuePair>, 
+        class_id: u8, 
+        mr_index: u32, 
+        send: RpcPostedSend, 
+        wr_id: u64
+    ) -> Result<(), RpcNetworkError>
+    {
+        IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .insert(wr_id, session_id);
+        let result = {
+            let mut send_map = IBVERBS_SEND_MAP.get().unwrap().lock().unwrap();
+            let send = send_map.entry(wr_id).or_insert(send);
+            Self::post_frame(rail_qp, class_id, mr_index, send, wr_id)
+        };
+        if let Err(e) = result {
+            Self::release_wr_id(wr_id);
+            Self::release_inflight();
+            return Err(RpcNetworkError::Verbs(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Posts a send on the queue pair, inline from the bytes it keeps, 
+    // or else from its memory region, or from the copy of the region 
+    // the device of the queue pair keeps when it is not the first device. 
+    fn post_frame(
+        rail_qp: &mut RpcRailQp<ibverbs::QueuePair>, 
+        class_id: u8, 
+        mr_index: u32, 
+        send: &RpcPostedSend, 
+        wr_id: u64
+    ) -> std::io::Result<()>
+    {
+        if let Some(ref bin) = send.inline {
+            unsafe { rail_qp.qp.post_send_inline(bin, wr_id) }?;
+            IBVERBS_INLINE_CNT.fetch_add(
+                1, 
+                std::sync::atomic::Ordering::Relaxed
+            );
+            return Ok(());
+        }
+
+        let len = send.len;
+        let smr_pool = IBVERBS_SMR_SLAB.get().unwrap()
+            .get_pool(class_id).unwrap();
+        let mut mr_send = unsafe { smr_pool.get_buf_mut(mr_index) }.unwrap(); 
+        if let Some(ref smr_copy) = Self::get_device(rail_qp.device).smr_copy {
+            let mr_copy = unsafe { smr_copy.get_pool(class_id).unwrap()
+                .get_buf_mut(mr_index) }.unwrap();
+            mr_copy[..len].copy_from_slice(&mr_send[..len]);
+            mr_send = mr_copy;
+        }
+
+        unsafe { 
+            rail_qp.qp.post_send(
+                &mut mr_send, 
+                ..len, 
+                wr_id
+            )
+        }?;
+
+        Ok(())
+    }
+
+    // Sends a frame through a memory region of the size the session 
+    // is bound to, posting only the bytes of the frame. 
+    // Fails with a retryable error when memory regions or in-flight 
+    // slots run out, instead of blocking the caller. 
+    pub fn send_to(&self, session_id: u32, mr_size: u32, bin: &[u8]) -> Result<(), RpcNetworkError>
+    {
+        trace!("send_to: session_id = {}, data_len = {}", session_id, bin.len());
+        if bin.len() > mr_size as usize {
+            return Err(RpcNetworkError::MsgTooLarge(bin.len()));
+        }
+
+        // tiny frames go inline, sparing the adapter a read of 
+        // a memory region 
+        if bin.len() <= Self::get_session_inline_limit(session_id) {
+            return Self::post_send_inline(session_id, bin.to_vec(), false, 0);
+        }
+
+        let (class_id, smr_index) = Self::acquire_send_mr(mr_size)?;
+        let mr_send = unsafe { Self::get_send_mr(class_id, smr_index) };
+        mr_send[..bin.len()].copy_from_slice(bin);
+        if let Err(e) = Self::post_send_mr(session_id, class_id, smr_index, bin.len(), false, 0) {
+            Self::release_send_mr(class_id, smr_index);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    // Access granted to peers on queue pairs and registered regions, 
+    // for one-sided operations of any kind. 
+    fn get_remote_access() -> ibverbs::ibv_access_flags
+    {
+        ibverbs::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE 
+            | ibverbs::ibv_access_flags::IBV_ACCESS_REMOTE_READ 
+            | ibverbs::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE 
+            | ibverbs::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC
+    }
+
+    // Registers an application buffer with the first device, for 
+    // one-sided operations to read from and write into. The region owns 
+    // the buffer until it is dropped. 
+    pub fn register_region(buf: Vec<u8>) -> Result<RpcMemRegion, RpcNetworkError>
+    {
+        let pd = &Self::get_device(0).pd;
+        let mr = pd.register::<u8>(buf, Self::get_remote_access())
+            .map_err(|e| RpcNetworkError::Verbs(e.to_string()))?;
+        let region_id = IBVERBS_REGION_CNT.fetch_add(
+            1, 
+            std::sync::atomic::Ordering::SeqCst
+        );
+        let region = RpcMemRegion::new(region_id, mr);
+        IBVERBS_REGION_MAP.get().unwrap().write().unwrap()
+            .insert(region_id, region.remote_key());
+        Ok(region)
+    }
+
+    // Calls `f` with the key of a live region, which is not dropped 
+    // before the call returns. 
+    pub fn with_region_key<R, F>(region_id: u32, f: F) -> Option<R>
+    where F: FnOnce(&RpcRemoteKey) -> R
+    {
+        let region_map = IBVERBS_REGION_MAP.get()?.read().unwrap();
+        region_map.get(&region_id).map(f)
+    }
+
+    // Advertises the region to peers connecting from now on. 
+    pub fn expose_region(region: &RpcMemRegion)
+    {
+        IBVERBS_EXPOSED_MAP.get().unwrap().write().unwrap()
+            .insert(region.get_id(), region.remote_key());
+    }
+
+    pub fn withdraw_region(region_id: u32)
+    {
+        if let Some(exposed_map) = IBVERBS_EXPOSED_MAP.get() {
+            exposed_map.write().unwrap().remove(&region_id);
+        }
+    }
+
+    // Forgets a region being dropped, waiting for atomic operations 
+    // of peers on it to finish. 
+    pub fn unregister_region(region_id: u32)
+    {
+        Self::withdraw_region(region_id);
+        if let Some(region_map) = IBVERBS_REGION_MAP.get() {
+            region_map.write().unwrap().remove(&region_id);
+        }
+    }
+
+    // Keys of the regions currently exposed, none before init. 
+    pub fn get_exposed_regions() -> Vec<RpcRemoteKey>
+    {
+        match IBVERBS_EXPOSED_MAP.get() {
+            Some(exposed_map) => exposed_map.read().unwrap()
+                .values().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Reads `len` bytes at `remote_offset` of the peer region into the 
+    // local region at `local_offset`, resolving once they have landed. 
+    pub async fn remote_read(
+        session_id: u32, 
+        region: RpcMemRegion, 
+        local_offset: usize, 
+        remote: &RpcRemoteKey, 
+        remote_offset: u64, 
+        len: usize
+    ) -> RpcRemoteOpResult
+    {
+        Self::remote_op(session_id, RpcRemoteOpKind::Read, region, 
+            local_offset, remote, remote_offset, len).await
+    }
+
+    // Writes `len` bytes at `local_offset` of the local region to the 
+    // peer region at `remote_offset`, resolving once they have landed. 
+    pub async fn remote_write(
+        session_id: u32, 
+        region: RpcMemRegion, 
+        local_offset: usize, 
+        remote: &RpcRemoteKey, 
+        remote_offset: u64, 
+        len: usize
+    ) -> RpcRemoteOpResult
+    {
+        Self::remote_op(session_id, RpcRemoteOpKind::Write, region, 
+            local_offset, remote, remote_offset, len).await
+    }
+
+    // Performs an atomic operation on the word at `offset` of the peer 
+    // region, which must be 8-byte aligned, returns its previous value. 
+    pub async fn remote_atomic(
+        session_id: u32, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        op: RpcAtomicOp
+    ) -> Result<u64, RpcNetworkError>
+    {
+        let remote_addr = remote.addr_of(offset, 8)?;
+        if remote_addr % 8 != 0 {
+            return Err(RpcNetworkError::Misaligned(remote_addr));
+        }
+
+        let buf = IBVERBS_ATOMIC_BUFS.get().unwrap().lock().unwrap().pop();
+        let buf = match buf {
+            Some(buf) => buf,
+            None => Self::register_region(vec![0u8; 8])?,
+        };
+        let (buf, result) = match Self::remote_op(session_id, 
+            RpcRemoteOpKind::Atomic(op), buf, 0, remote, offset, 8).await 
+        {
+            Ok(buf) => {
+                let value = u64::from_ne_bytes(buf.as_slice()[..8].try_into().unwrap());
+                (Some(buf), Ok(value))
+            },
+            Err((buf, e)) => (buf, Err(e)),
+        };
+        if let Some(buf) = buf {
+            IBVERBS_ATOMIC_BUFS.get().unwrap().lock().unwrap().push(buf);
+        }
+        result
+    }
+
+    // Posts a one-sided operation on the queue pair of the session. 
+    // The local region is held by the network until the operation 
+    // completes, then handed back along with its outcome. 
+    async fn remote_op(
+        session_id: u32, 
+        kind: RpcRemoteOpKind, 
+        mut region: RpcMemRegion, 
+        local_offset: usize, 
+        remote: &RpcRemoteKey, 
+        remote_offset: u64, 
+        len: usize
+    ) -> RpcRemoteOpResult
+    {
+        if let Err(e) = region.check_range(local_offset, len) {
+            return Err((Some(region), e));
+        }
+        let remote_addr = match remote.addr_of(remote_offset, len) {
+            Ok(remote_addr) => remote_addr,
+            Err(e) => return Err((Some(region), e)),
+        };
+
+        let wr_id = Self::make_wr_id(
+            Self::get_wr_id(), false, 0, IBVERBS_REMOTE_OP_CLASS, region.get_id());
+        let local_range = local_offset..local_offset + len;
+        let (done, result) = tokio::sync::oneshot::channel();
+        {
+            let qp_map = IBVERBS_QP_MAP.get().unwrap();
+            let mut qp_map = qp_map.lock().unwrap();
+            let qps = match qp_map.get_mut(&session_id) {
+                Some(qps) => qps,
+                None => return Err((Some(region), RpcNetworkError::NoSession(session_id))),
+            };
+            let qp = match qps.next_usable(Self::is_qp_usable_remote) {
+                Some(rail_qp) => &mut rail_qp.qp,
+                None => return Err((Some(region), RpcNetworkError::SessionFailed(session_id))),
+            };
+            if let Err(e) = Self::reserve_inflight() {
+                return Err((Some(region), e));
+            }
+
+            // registered before posting, the completion may come 
+            // before the call returns 
+            let mut op_map = IBVERBS_REMOTE_OP_MAP.get().unwrap()
+                .lock().unwrap();
+            let mr = region.get_mr_mut();
+            let posted = unsafe {
+                match kind {
+                    RpcRemoteOpKind::Read => qp.post_read(
+                        mr, local_range, remote_addr, remote.rkey, wr_id),
+                    RpcRemoteOpKind::Write => qp.post_write(
+                        mr, local_range, remote_addr, remote.rkey, wr_id),
+                    RpcRemoteOpKind::Atomic(RpcAtomicOp::FetchAdd(add)) => qp.post_fetch_add(
+                        mr, local_range, remote_addr, remote.rkey, add, wr_id),
+                    RpcRemoteOpKind::Atomic(RpcAtomicOp::CompareSwap(compare, swap)) => qp.post_compare_swap(
+                        mr, local_range, remote_addr, remote.rkey, compare, swap, wr_id),
+                }
+            };
+            if let Err(e) = posted {
+                Self::release_inflight();
+                return Err((Some(region), RpcNetworkError::Verbs(e.to_string())));
+            }
+            op_map.insert(wr_id, RpcRemoteOp { 
+                session_id: session_id, 
+                region: region, 
+                done: done 
+            });
+        }
+        trace!("remote_op: {:?} wr_id = {}, session_id = {}, len = {}", 
+            kind, wr_id, session_id, len);
+
+        // every posted operation is answered by its completion, or by 
+        // the teardown of its session 
+        match result.await {
+            Ok(result) => result,
+            Err(_) => Err((None, RpcNetworkError::SessionFailed(session_id))),
+        }
+    }
+
+    // Answers the one-sided operation of the work request, if it is 
+    // one, with the error status it failed with if any. 
+    // Returns the session of the operation. 
+    fn on_remote_complete(wr_id: u64, error: Option<RpcWcError>) -> Option<u32>
+    {
+        let op = IBVERBS_REMOTE_OP_MAP.get().unwrap()
+            .lock().unwrap()
+            .remove(&wr_id)?;
+
+        let session_id = op.session_id;
+        let result = match error {
+            None => Ok(op.region),
+            Some(e) => Err((Some(op.region), Self::get_wr_error(session_id, e))),
+        };
+        let _ = op.done.send(result);
+        Self::on_send_vacancy();
+        Some(session_id)
+    }
+
+    // Answers the one-sided operations of the session still waiting 
+    // for a completion, once its queue pairs are destroyed. 
+    fn drop_remote_ops(session_id: u32)
+    {
+        let ops: Vec<RpcRemoteOp> = {
+            let mut op_map = IBVERBS_REMOTE_OP_MAP.get().unwrap()
+                .lock().unwrap();
+            let wr_ids: Vec<u64> = op_map.iter()
+                .filter(|(_, op)| op.session_id == session_id)
+                .map(|(wr_id, _)| *wr_id)
+                .collect();
+            wr_ids.iter()
+                .filter_map(|wr_id| op_map.remove(wr_id))
+                .collect()
+        };
+        let error = match Self::get_session_error(session_id) {
+            Some(e) => RpcNetworkError::SessionLost(session_id, e),
+            None => RpcNetworkError::SessionFailed(session_id),
+        };
+        for op in ops {
+            Self::on_send_vacancy();
+            let _ = op.done.send(Err((Some(op.region), error.clone())));
+        }
+    }
+
+    // Error a work request of the session failed with, what failed the 
+    // session when the request was only flushed by it. 
+    fn get_wr_error(session_id: u32, error: RpcWcError) -> RpcNetworkError
+    {
+        match Self::get_session_error(session_id) {
+            Some(cause) if error == RpcWcError::Flushed => 
+                RpcNetworkError::SessionLost(session_id, cause),
+            _ => RpcNetworkError::WorkRequest(error),
+        }
+    }
+
+    // First error a work request of the session failed with, 
+    // flushes aside, if any did. 
+    pub fn get_session_error(session_id: u32) -> Option<RpcWcError>
+    {
+        IBVERBS_SESSION_ERROR_MAP.get().unwrap().lock().unwrap()
+            .get(&session_id).cloned()
+    }
+
+    // Registers the region the peer of the session writes large 
+    // messages into, if configured so, returns its key to advertise. 
+    pub fn create_landing(session_id: u32) -> Option<RpcRemoteKey>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        if conf.large_msg != RpcLargeMsgMode::WriteImm {
+            return None;
+        }
+
+        match Self::register_region(vec![0u8; conf.landing_size as usize]) {
+            Ok(region) => {
+                let key = region.remote_key();
+                IBVERBS_LANDING_MAP.get().unwrap().write().unwrap()
+                    .insert(session_id, region);
+                Some(key)
+            },
+            Err(e) => {
+                error!("failed to register landing region of session {}: {}", 
+                    session_id, e);
+                None
+            },
+        }
+    }
+
+    // Drops the landing and staging regions of the session. 
+    pub fn drop_landing(session_id: u32)
+    {
+        IBVERBS_LANDING_MAP.get().unwrap().write().unwrap()
+            .remove(&session_id);
+        IBVERBS_STAGING_MAP.get().unwrap().lock().unwrap()
+            .remove(&session_id);
+    }
+
+    // Writes a frame into the landing region of the peer, signalled by 
+    // the immediate value `imm`. The frame is staged in a region of the 
+    // session first, which the caller must not reuse before the peer 
+    // has released its landing region. 
+    pub fn write_imm_frame(
+        session_id: u32, 
+        landing: &RpcRemoteKey, 
+        imm: u32, 
+        header: &RpcFrameHeader, 
+        body: &[u8]
+    ) -> Result<(), RpcNetworkError>
+    {
+        let frame_len = header.frame_len();
+        let remote_addr = landing.addr_of(0, frame_len)?;
+
+        let mut staging_map = IBVERBS_STAGING_MAP.get().unwrap()
+            .lock().unwrap();
+        if !staging_map.contains_key(&session_id) {
+            let region = Self::register_region(vec![0u8; landing.len as usize])?;
+            staging_map.insert(session_id, region);
+        }
+        let region = staging_map.get_mut(&session_id).unwrap();
+        region.check_range(0, frame_len)?;
+        let frame = region.as_mut_slice();
+        header.write_to(frame);
+        frame[SRPC_FRAME_HEADER_LEN as usize..frame_len].copy_from_slice(body);
+
+        let qp_map = IBVERBS_QP_MAP.get().unwrap();
+        let mut qp_map = qp_map.lock().unwrap();
+        let qp = &mut qp_map.get_mut(&session_id)
+            .ok_or(RpcNetworkError::NoSession(session_id))?
+            .next_usable(Self::is_qp_usable_remote)
+            .ok_or(RpcNetworkError::SessionFailed(session_id))?
+            .qp;
+        Self::reserve_inflight()?;
+
+        let wr_id = Self::make_wr_id(
+            Self::get_wr_id(), false, 0, IBVERBS_LANDING_CLASS, session_id);
+        IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .insert(wr_id, session_id);
+        // kept to give back what the frame took should the write fail 
+        IBVERBS_SEND_MAP.get().unwrap()
+            .lock().unwrap()
+            .insert(wr_id, RpcPostedSend {
+                len: frame_len,
+                inline: None,
+                credited: !header.kind.is_control(),
+                returned: header.credit,
+            });
+        let result = unsafe {
+            qp.post_write_imm(
+                region.get_mr_mut(), 
+                ..frame_len, 
+                remote_addr, 
+                landing.rkey, 
+                imm, 
+                wr_id
+            )
+        };
+        if let Err(e) = result {
+            Self::release_wr_id(wr_id);
+            Self::release_inflight();
+            return Err(RpcNetworkError::Verbs(e.to_string()));
+        }
+        trace!("write_imm_frame: wr_id = {}, session_id = {}, len = {}", 
+            wr_id, session_id, frame_len);
+
+        Ok(())
+    }
+
+    // Hands the `len` bytes the peer wrote into the landing region 
+    // of the session to the dispatcher. 
+    pub fn on_recv_landing(session_id: u32, imm: u32, len: usize)
+    {
+        let landing_map = IBVERBS_LANDING_MAP.get().unwrap()
+            .read().unwrap();
+        let region = match landing_map.get(&session_id) {
+            Some(region) => region,
+            None => {
+                error!("on_recv_landing: no landing region for session {}", session_id);
+                return;
+            }
+        };
+
+        let len = std::cmp::min(len, region.len());
+        RPC_DISPATCHER.get().unwrap().on_recv_landed(
+            session_id, imm, &region.as_slice()[..len]);
+    }
+
+    // Hands the `len` bytes received in the memory region of the 
+    // device to the dispatcher. 
+    pub fn on_recv(wr_id: u64, device: u8, session_id: u32, len: usize)
+    {
+        let class_id = Self::get_mr_class_by_wr_id(wr_id);
+        let mr_index = Self::get_mr_index_by_wr_id(wr_id);
+
+        trace!("on_recv: wr_id = {}, class_id = {}, mr_index = {}, len = {}", 
+            wr_id, class_id, mr_index, len);
+
+        // the region is lent to the dispatcher, 
+        // it is reposted only after the call returns 
+        let rmr_pool = Self::get_device(device).rmr_slab
+            .get_pool(class_id).unwrap();
+        let mr_recv = rmr_pool.get_buf(mr_index).unwrap();
+
+        let len = std::cmp::min(len, mr_recv.len());
+        RPC_DISPATCHER.get().unwrap().on_recv_frame(session_id, &mr_recv[..len]);
+    }
+
+    pub fn on_send(wr_id: u64)
+    {
+        match Self::find_session_id_by_wr_id(wr_id) {
+            Some(session_id) => 
+                RPC_DISPATCHER.get().unwrap().on_send_complete(session_id),
+            None => error!("on_send: no session for wr_id {}", wr_id),
+        }
+    }
+
+    // Connects a queue pair bound to memory regions of `mr_size` bytes. 
+    pub async fn connect_to(
+        &self, 
+        session_id: u32, 
+        peer_uri: &str, 
+        mr_size: u32
+    ) -> Result<RpcPeerMeta, std::string::String>
+    {
+        trace!("connect_to: session_id = {}, peer_uri = {}", session_id, peer_uri);
+        self.conn_map.write().unwrap()
+            .insert(session_id, (Vec::new(), Vec::new()));
+        
+        // connect, on every rail 
+        let qp_builders = Self::create_rail_qps(session_id, mr_size)
+            .and_then(|mut qp_builders| {
+                // receives are in place before the peer may send 
+                for qp_builder in qp_builders.iter_mut() {
+                    Self::post_recv_depth(qp_builder, session_id, mr_size)?;
+                }
+                Ok(qp_builders)
+            });
+        let qp_builders = match qp_builders {
+            Ok(qp_builders) => qp_builders,
+            Err(e) => {
+                self.disconnect(session_id);
+                return Err(e.to_string());
+            }
+        };
+        let loc_endpoints: Vec<ibverbs::QueuePairEndpoint> = qp_builders.iter()
+            .map(|qp_builder| qp_builder.endpoint()) // local endpoints 
+            .collect();
+
+        let (rmt_endpoints, rmt_meta) = 
+            match SrpcGrpcPreComm::get_endpoint(
+                &loc_endpoints, 
+                peer_uri, 
+                session_id, 
+                mr_size
+            ).await {
+                Ok(result) => result,
+                Err(e) => {
+                    self.disconnect(session_id);
+                    return Err(e);
+                }
+            }; 
+
+        // rails the peer has no queue pair for stay unused 
+        let qps = qp_builders.into_iter()
+            .zip(rmt_endpoints.into_iter())
+            .map(|(mut qp_builder, rmt_endpoint)| {
+                rmt_meta.qp_attr.apply(&mut qp_builder);
+                qp_builder.handshake(rmt_endpoint)
+                    .map_err(|e| RpcNetworkError::Verbs(e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|qps| Self::bind_rail_qps(session_id, qps));
+        if let Err(e) = qps {
+            self.disconnect(session_id);
+            return Err(e.to_string());
+        }
+        
+        Ok(rmt_meta)
+    }
+
+    pub fn disconnect(&self, session_id: u32)
+    {
+        self.conn_map.write().unwrap().remove(&session_id);
+        Self::drop_session(session_id);
+    }
+
+    // Releases what the session holds: its queue pairs on every rail, 
+    // the receive buffers posted to them and its landing region. 
+    // The session must have no send in flight. 
+    pub fn drop_session(session_id: u32)
+    {
+        Self::drop_landing(session_id);
+
+        let qps: Vec<ibverbs::QueuePair> = IBVERBS_QP_MAP.get().unwrap()
+            .lock().unwrap()
+            .remove(&session_id)
+            .map(|qps| qps.into_qps())
+            .unwrap_or_default();
+        // queue pairs dropped before binding count too, their numbers 
+        // may be given to queue pairs created later 
+        let qp_nums: Vec<u32> = {
+            let mut qpn_map = IBVERBS_QPN_MAP.get().unwrap().lock().unwrap();
+            let qp_nums = qpn_map.iter()
+                .filter(|(_, qpn_session_id)| **qpn_session_id == session_id)
+                .map(|(qp_num, _)| *qp_num)
+                .collect();
+            qpn_map.retain(|_, qpn_session_id| *qpn_session_id != session_id);
+            qp_nums
+        };
+        IBVERBS_FAILED_QPN_SET.get().unwrap().lock().unwrap()
+            .retain(|qp_num| !qp_nums.contains(qp_num));
+        IBVERBS_RECV_DEFICIT.get().unwrap().lock().unwrap()
+            .remove(&session_id);
+        // destroyed before their buffers are reused 
+        drop(qps);
+        Self::drop_session_cq(session_id);
+        Self::drop_remote_ops(session_id);
+        IBVERBS_SESSION_ERROR_MAP.get().unwrap().lock().unwrap()
+            .remove(&session_id);
+
+        // with no send in flight, what is left are the receives posted 
+        // to the queue pairs of the session, flushed or not; completions 
+        // of those still queued find their work request gone 
+        let wr_ids: Vec<u64> = IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .iter()
+            .filter(|(_, wr_session_id)| **wr_session_id == session_id)
+            .map(|(wr_id, _)| *wr_id)
+            .collect();
+        for wr_id in wr_ids {
+            if Self::release_wr_id(wr_id) {
+                Self::release_occupied_rmr(Self::get_device_by_wr_id(wr_id), wr_id);
+            }
+        }
+        trace!("drop_session: session {} released", session_id);
+    }
+}
+
+impl RpcNetworkCore
+{
+    fn find_session_id_by_wr_id(wr_id: u64) -> Option<u32>
+    {
+        IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .get(&wr_id).cloned()
+    }
+
+    // Receives on a shared receive queue are not bound to a session, 
+    // the queue pair number of the completion tells where they came from. 
+    // Completions of queue pairs no session knows are dropped. 
+    fn get_session_id_by_wc(wc: &ibverbs::ibv_wc) -> Option<u32>
+    {
+        let session_id = if Self::is_srq_enabled() {
+            IBVERBS_QPN_MAP.get().unwrap().lock().unwrap()
+                .get(&wc.qp_num).cloned()
+        } else {
+            Self::find_session_id_by_wr_id(wc.wr_id())
+        };
+        if session_id.is_none() {
+            error!("dropped a completion of unknown qp_num {}, wr_id {}", 
+                wc.qp_num, wc.wr_id());
+        }
+        session_id
+    }
+
+    // Forgets the work request, returns whether it was still recorded. 
+    fn release_wr_id(wr_id: u64) -> bool
+    {
+        let recorded = IBVERBS_WRID_MAP.get().unwrap()
+            .lock().unwrap()
+            .remove(&wr_id)
+            .is_some();
+        IBVERBS_SEND_MAP.get().unwrap()
+            .lock().unwrap()
+            .remove(&wr_id);
+        recorded
     }
 }
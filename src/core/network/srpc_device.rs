@@ -0,0 +1,343 @@
+use crate::conf::conf::RpcConf;
+
+use super::srpc_net_error::RpcNetworkError;
+
+// State of a port of an RDMA device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcPortInfo
+{
+    pub port_num: u8,
+    pub state: std::string::String,
+    pub lid: u16,
+    pub active_mtu: u32,
+    pub link_layer: std::string::String,
+    // GIDs by index, empty entries included
+    pub gids: Vec<std::string::String>,
+}
+
+impl RpcPortInfo
+{
+    pub fn is_active(&self) -> bool
+    {
+        self.state == "active"
+    }
+}
+
+// An RDMA device and its ports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcDeviceInfo
+{
+    pub name: std::string::String,
+    pub guid: u64,
+    pub ports: Vec<RpcPortInfo>,
+}
+
+impl std::fmt::Display for RpcDeviceInfo
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        writeln!(f, "{} (guid {:#018x})", self.name, self.guid)?;
+        for port in self.ports.iter()
+        {
+            writeln!(f, "  port {}: {}, lid {}, mtu {}, {}",
+                port.port_num, port.state, port.lid,
+                port.active_mtu, port.link_layer)?;
+            for (index, gid) in port.gids.iter().enumerate()
+            {
+                if !gid.is_empty()
+                {
+                    writeln!(f, "    gid {}: {}", index, gid)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn port_state_name(state: ibverbs::ibv_port_state::Type) -> std::string::String
+{
+    match state
+    {
+        ibverbs::ibv_port_state::IBV_PORT_DOWN => "down",
+        ibverbs::ibv_port_state::IBV_PORT_INIT => "init",
+        ibverbs::ibv_port_state::IBV_PORT_ARMED => "armed",
+        ibverbs::ibv_port_state::IBV_PORT_ACTIVE => "active",
+        ibverbs::ibv_port_state::IBV_PORT_ACTIVE_DEFER => "active-defer",
+        _ => "unknown",
+    }.to_string()
+}
+
+fn link_layer_name(link_layer: u8) -> std::string::String
+{
+    match link_layer as u32
+    {
+        ibverbs::IBV_LINK_LAYER_INFINIBAND => "infiniband",
+        ibverbs::IBV_LINK_LAYER_ETHERNET => "ethernet",
+        _ => "unspecified",
+    }.to_string()
+}
+
+// Active MTU of a port in bytes.
+fn mtu_bytes(mtu: ibverbs::ibv_mtu) -> u32
+{
+    match mtu
+    {
+        ibverbs::ibv_mtu::IBV_MTU_256 => 256,
+        ibverbs::ibv_mtu::IBV_MTU_512 => 512,
+        ibverbs::ibv_mtu::IBV_MTU_1024 => 1024,
+        ibverbs::ibv_mtu::IBV_MTU_2048 => 2048,
+        ibverbs::ibv_mtu::IBV_MTU_4096 => 4096,
+        _ => 0,
+    }
+}
+
+fn query_port(ctx: &ibverbs::Context, port_num: u8) -> Result<RpcPortInfo, RpcNetworkError>
+{
+    let attr = ctx.query_port(port_num)
+        .map_err(|e| RpcNetworkError::Device(
+            format!("port {}: {}", port_num, e)))?;
+
+    // entries failing to query are left empty, as the device reports them
+    let gids = (0..attr.gid_tbl_len as u32)
+        .map(|index| match ctx.query_gid(port_num, index)
+        {
+            Ok(gid) if gid != ibverbs::Gid::default() => format!("{}", gid),
+            _ => std::string::String::new(),
+        })
+        .collect();
+
+    Ok(RpcPortInfo
+    {
+        port_num: port_num,
+        state: port_state_name(attr.state),
+        lid: attr.lid,
+        active_mtu: mtu_bytes(attr.active_mtu),
+        link_layer: link_layer_name(attr.link_layer),
+        gids: gids,
+    })
+}
+
+fn query_device(device: &ibverbs::Device) -> Result<RpcDeviceInfo, RpcNetworkError>
+{
+    let name = device_name(device);
+    let ctx = device.open()
+        .map_err(|e| RpcNetworkError::Device(format!("{}: {}", name, e)))?;
+    let attr = ctx.query_device()
+        .map_err(|e| RpcNetworkError::Device(format!("{}: {}", name, e)))?;
+
+    // ports are numbered from 1
+    let ports = (1..=attr.phys_port_cnt)
+        .map(|port_num| query_port(&ctx, port_num))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(RpcDeviceInfo
+    {
+        name: name,
+        guid: u64::from_be(device.guid().unwrap_or(0)),
+        ports: ports,
+    })
+}
+
+fn device_name(device: &ibverbs::Device) -> std::string::String
+{
+    device.name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Enumerates the RDMA devices of the host along with the state
+// of their ports.
+pub fn list_devices() -> Result<Vec<RpcDeviceInfo>, RpcNetworkError>
+{
+    let devices = ibverbs::devices()
+        .map_err(|e| RpcNetworkError::Device(e.to_string()))?;
+    devices.iter()
+        .map(|device| query_device(&device))
+        .collect()
+}
+
+// Opens the devices the rails are on, in the order of the rails
+// naming them first, after checking some rail port is active and has
+// the GID configured for it. Rails down at start are left to failover.
+pub fn open_devices(rails: &[RpcRail]) -> Result<Vec<ibverbs::Context>, RpcNetworkError>
+{
+    let devices = ibverbs::devices()
+        .map_err(|e| RpcNetworkError::Device(e.to_string()))?;
+
+    let mut ctxs = Vec::new();
+    for name in get_rail_devices(rails).iter()
+    {
+        let device = devices.iter()
+            .find(|device| device_name(device) == *name)
+            .ok_or_else(|| RpcNetworkError::Device(
+                format!("no rdma device named {}", name)))?;
+        let ctx = device.open()
+            .map_err(|e| RpcNetworkError::Device(format!("{}: {}", name, e)))?;
+        ctxs.push(ctx);
+    }
+
+    let mut last_error = None;
+    for rail in rails.iter()
+    {
+        let ctx = &ctxs[get_rail_device(rails, rail)];
+        match check_rail(ctx, rail)
+        {
+            Ok(_) => return Ok(ctxs),
+            Err(e) =>
+            {
+                last_error = Some(RpcNetworkError::Device(
+                    format!("{} {}", rail.device, e)));
+            },
+        }
+    }
+    Err(last_error.unwrap_or_else(|| RpcNetworkError::Device(
+        "no port configured".to_string())))
+}
+
+fn check_rail(ctx: &ibverbs::Context, rail: &RpcRail) -> Result<(), std::string::String>
+{
+    let port = query_port(ctx, rail.port)
+        .map_err(|e| e.to_string())?;
+    if !port.is_active()
+    {
+        return Err(format!("port {} is {}", rail.port, port.state));
+    }
+    match port.gids.get(rail.gid_index as usize)
+    {
+        Some(gid) if !gid.is_empty() => Ok(()),
+        _ => Err(format!("port {} has no gid {}", rail.port, rail.gid_index)),
+    }
+}
+
+// A port queue pairs are opened on. Every session has a queue pair
+// on each rail and stripes its sends across those still usable.
+// Written as `[device/]port[:gid_index]`, the device being the
+// configured one when left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcRail
+{
+    pub device: std::string::String,
+    pub port: u8,
+    pub gid_index: u32,
+}
+
+impl std::str::FromStr for RpcRail
+{
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let (device, port_gid) = match s.rsplit_once('/')
+        {
+            Some((device, _)) if device.is_empty() =>
+                return Err(format!("rail {:?}: empty device name", s)),
+            Some((device, port_gid)) => (device.to_string(), port_gid),
+            None => (std::string::String::new(), s),
+        };
+        let mut parts = port_gid.splitn(2, ':');
+        let port = parts.next().unwrap_or("").parse::<u8>()
+            .map_err(|e| format!("bad rail port in {:?}: {}", s, e))?;
+        let gid_index = match parts.next()
+        {
+            Some(gid_index) => gid_index.parse::<u32>()
+                .map_err(|e| format!("bad rail gid index in {:?}: {}", s, e))?,
+            None => 0,
+        };
+        if port == 0
+        {
+            return Err(format!("rail {:?}: ports are numbered from 1", s));
+        }
+
+        Ok(RpcRail
+        {
+            device: device,
+            port: port,
+            gid_index: gid_index,
+        })
+    }
+}
+
+// Rails to open queue pairs on, the configured port alone unless
+// rails are listed. Rails naming no device are on the configured one,
+// the first device listed unless named.
+pub fn get_rails(conf: &RpcConf) -> Result<Vec<RpcRail>, RpcNetworkError>
+{
+    let mut rails = if conf.rails.is_empty()
+    {
+        vec![RpcRail
+        {
+            device: std::string::String::new(),
+            port: conf.ib_port,
+            gid_index: conf.gid_index,
+        }]
+    }
+    else
+    {
+        conf.rails.clone()
+    };
+
+    if rails.iter().any(|rail| rail.device.is_empty())
+    {
+        let device = if conf.device.is_empty()
+        {
+            let devices = ibverbs::devices()
+                .map_err(|e| RpcNetworkError::Device(e.to_string()))?;
+            let first = devices.iter().next()
+                .ok_or_else(|| RpcNetworkError::Device(
+                    "no rdma device available".to_string()))?;
+            device_name(&first)
+        }
+        else
+        {
+            conf.device.clone()
+        };
+        for rail in rails.iter_mut().filter(|rail| rail.device.is_empty())
+        {
+            rail.device = device.clone();
+        }
+    }
+    Ok(rails)
+}
+
+// Devices the rails are on, each once, in the order of the rails
+// naming them first. The device of the first rail comes first.
+pub fn get_rail_devices(rails: &[RpcRail]) -> Vec<std::string::String>
+{
+    let mut names: Vec<std::string::String> = Vec::new();
+    for rail in rails.iter()
+    {
+        if !names.contains(&rail.device)
+        {
+            names.push(rail.device.clone());
+        }
+    }
+    names
+}
+
+// Position of the device of the rail among get_rail_devices.
+pub fn get_rail_device(rails: &[RpcRail], rail: &RpcRail) -> usize
+{
+    get_rail_devices(rails).iter()
+        .position(|name| *name == rail.device)
+        .unwrap_or(0)
+}
+
+// Active MTU of the port in bytes, None if it fails to query.
+pub fn get_active_mtu(ctx: &ibverbs::Context, port_num: u8) -> Option<u32>
+{
+    match query_port(ctx, port_num)
+    {
+        Ok(port) if port.active_mtu != 0 => Some(port.active_mtu),
+        _ => None,
+    }
+}
+
+// Whether the port is up, as far as the device tells.
+pub fn is_port_active(ctx: &ibverbs::Context, port_num: u8) -> bool
+{
+    match query_port(ctx, port_num)
+    {
+        Ok(port) => port.is_active(),
+        Err(_) => false,
+    }
+}
@@ -11,12 +11,13 @@ use tracing::info;
 use tracing::trace;
 use tracing::{error};
 
-use crate::core::network::srpc_core_network::IBVERBS_QP_MAP;
+use crate::core::network::srpc_core_network::RpcNetworkCore;
+use crate::conf::conf::RPC_CONF;
+use crate::core::srpc_core::{RPC_CORE, RPC_DISPATCHER};
 use crate::core::srpc_session::RpcSession;
 
-use super::srpc_core_network::IBVERBS_SQ;
-use super::srpc_core_network::IBVERBS_RQ;
-use super::srpc_core_network::IBVERBS_PD;
+use super::srpc_net_error::RpcNetworkError;
+use super::srpc_peer_meta::RpcPeerMeta;
 
 // Communication through legacy TCP sockets functionality 
 // before RDMA connection is established. 
@@ -30,45 +31,144 @@ impl PreCommService for SrpcGrpcPreComm {
         &self,
         request: tonic::Request<GetEndpointRequest>,
     ) -> Result<tonic::Response<GetEndpointResponse>, tonic::Status> {
+        let peer_uri = match request.remote_addr() {
+            Some(addr) => addr.to_string(),
+            None => std::string::String::new(),
+        };
         let request = request.into_inner();
-        let src_endpoint_bin = request.src_endpoint;
-        let src_endpoint_slice = src_endpoint_bin.as_slice();
-        let reader = 
-            flexbuffers::Reader::get_root(src_endpoint_slice).unwrap(); 
-        let src_endpoint = 
-            ibverbs::QueuePairEndpoint::deserialize(reader).unwrap();
-
-        info!("remote endpoint: {:?}", src_endpoint);
-
-        // serialize designated endpoint 
-        let pd = IBVERBS_PD.get().unwrap();
-        let sq = IBVERBS_SQ.get().unwrap();
-        let rq = IBVERBS_RQ.get().unwrap();
-        let qp_builder = pd.create_qp(
-            &sq, 
-            64, 
-            &rq, 
-            64, 
-            ibverbs::ibv_qp_type::IBV_QPT_RC
-        ).build().unwrap();
-        let endpoint = qp_builder.endpoint();
-        let qp = qp_builder.handshake(src_endpoint).unwrap();
-        
-        let session_id = RpcSession::get_session_id(); 
-        info!("qp_map insert session_id: {}", session_id);
-        IBVERBS_QP_MAP.get().unwrap().lock().unwrap()
-            .insert(session_id, qp);
 
-        let mut serializer = 
-            flexbuffers::FlexbufferSerializer::new();
-        endpoint.serialize(&mut serializer).unwrap();
-        let endpoint_bin = serializer.view();
-        let endpoint_bin_vec = endpoint_bin.to_vec();
+        // validate caller identity and capabilities 
+        let mut src_meta = match request.src_meta {
+            Some(ref meta) => match RpcPeerMeta::from_proto(meta) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    error!("rejected peer {}: {}", peer_uri, e);
+                    return Err(tonic::Status::invalid_argument(e));
+                }
+            },
+            None => {
+                error!("precomm request from {} without peer meta", peer_uri);
+                return Err(tonic::Status::invalid_argument(
+                    "missing peer meta in precomm request"));
+            }
+        };
+        let mr_sizes = RpcNetworkCore::get_mr_sizes();
+        if !mr_sizes.contains(&src_meta.mr_size) {
+            let e = format!(
+                "memory region size {} not among local classes {:?}", 
+                src_meta.mr_size, mr_sizes);
+            error!("rejected peer {}:{}: {}", src_meta.node_id, peer_uri, e);
+            return Err(tonic::Status::failed_precondition(e));
+        }
+        let mut loc_meta = RpcPeerMeta::from_conf(
+            RPC_CONF.get().unwrap(), 
+            src_meta.mr_size
+        );
+        let compat = loc_meta.negotiate_version(&mut src_meta)
+            .and_then(|_| loc_meta.check_compat(&src_meta))
+            .and_then(|_| loc_meta.negotiate_qp_attr(&mut src_meta).map(|_| ()));
+        if let Err(e) = compat {
+            error!("rejected peer {}:{}: {}", src_meta.node_id, peer_uri, e);
+            return Err(tonic::Status::failed_precondition(e));
+        }
+        let src_id = src_meta.node_id;
+        info!("peer {} speaks protocol version {}, qp attributes {:?}", 
+            src_id, src_meta.proto_version, loc_meta.qp_attr);
+
+        // admission control before any queue pair is created 
+        if let Err(e) = RPC_CORE.get().unwrap()
+            .check_accept(&src_meta, &peer_uri) 
+        {
+            error!("rejected peer {}:{} by accept hook: {}", 
+                src_id, peer_uri, e);
+            return Err(tonic::Status::permission_denied(e));
+        }
+        // given back on any early return 
+        let dispatcher = RPC_DISPATCHER.get().unwrap();
+        let slot = match dispatcher.reserve_session() {
+            Ok(slot) => slot,
+            Err(e) => {
+                error!("rejected peer {}:{}: {}", src_id, peer_uri, e);
+                return Err(tonic::Status::resource_exhausted(e));
+            }
+        };
+
+        // one endpoint per rail of the caller, paired with the local 
+        // rails in order 
+        let src_endpoints = std::iter::once(&request.src_endpoint)
+            .chain(request.rail_endpoints.iter())
+            .map(|bin| Self::endpoint_from_bin(bin))
+            .collect::<Result<Vec<_>, _>>();
+        let src_endpoints = match src_endpoints {
+            Ok(src_endpoints) => src_endpoints,
+            Err(e) => {
+                error!("bad endpoint from peer {}:{}: {}", src_id, peer_uri, e);
+                return Err(tonic::Status::invalid_argument(e));
+            }
+        };
+
+        info!("remote endpoints: {:?} from peer {}:{}", 
+            src_endpoints, src_id, peer_uri);
+
+        // serialize designated endpoints 
+        let session_id = RpcSession::get_session_id(); 
+        let qp_builders = RpcNetworkCore::create_rail_qps(session_id, src_meta.mr_size)
+            .and_then(|mut qp_builders| {
+                // receives are in place before the caller may send 
+                for qp_builder in qp_builders.iter_mut() {
+                    RpcNetworkCore::post_recv_depth(
+                        qp_builder, session_id, src_meta.mr_size)?;
+                }
+                Ok(qp_builders)
+            });
+        let qp_builders = match qp_builders {
+            Ok(qp_builders) => qp_builders,
+            Err(e) => {
+                error!("failed to create queue pairs for peer {}:{}: {}", 
+                    src_id, peer_uri, e);
+                RpcNetworkCore::drop_session(session_id);
+                return Err(tonic::Status::resource_exhausted(e.to_string()));
+            }
+        };
+        let endpoints: Vec<ibverbs::QueuePairEndpoint> = qp_builders.iter()
+            .take(src_endpoints.len())
+            .map(|qp_builder| qp_builder.endpoint())
+            .collect();
+        let endpoint_bins = endpoints.iter()
+            .map(Self::endpoint_to_bin)
+            .collect::<Result<Vec<_>, _>>();
+        let mut endpoint_bins = match endpoint_bins {
+            Ok(endpoint_bins) => endpoint_bins.into_iter(),
+            Err(e) => {
+                error!("failed to serialize endpoints for peer {}:{}: {}", 
+                    src_id, peer_uri, e);
+                RpcNetworkCore::drop_session(session_id);
+                return Err(tonic::Status::internal(e));
+            }
+        };
+        let qps = qp_builders.into_iter()
+            .zip(src_endpoints.into_iter())
+            .map(|(mut qp_builder, src_endpoint)| {
+                loc_meta.qp_attr.apply(&mut qp_builder);
+                qp_builder.handshake(src_endpoint)
+                    .map_err(|e| RpcNetworkError::Verbs(e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|qps| RpcNetworkCore::bind_rail_qps(session_id, qps));
+        if let Err(e) = qps {
+            error!("failed to connect peer {}:{}: {}", src_id, peer_uri, e);
+            RpcNetworkCore::drop_session(session_id);
+            return Err(tonic::Status::resource_exhausted(e.to_string()));
+        }
+        dispatcher.accept_session(session_id, src_meta, &peer_uri, slot);
+        loc_meta.landing = RpcNetworkCore::create_landing(session_id);
 
         let response = GetEndpointResponse {
-            endpoint: endpoint_bin_vec.to_vec()
+            endpoint: endpoint_bins.next().unwrap_or_default(), 
+            dst_meta: Some(loc_meta.to_proto()),
+            rail_endpoints: endpoint_bins.collect(),
         };
-        info!("local endpoint: {:?}", endpoint);
+        info!("local endpoints: {:?}", endpoints);
 
         Ok(tonic::Response::new(response))
     }
@@ -77,16 +177,17 @@ impl PreCommService for SrpcGrpcPreComm {
 impl SrpcGrpcPreComm {
     pub async fn serve(
         loc_uri: &str
-    ) -> Result<(), tonic::transport::Error>
+    ) -> Result<(), std::string::String>
     {
-        let addr = format!("{}", loc_uri).parse().unwrap(); 
+        let addr: std::net::SocketAddr = loc_uri.parse()
+            .map_err(|e| format!("bad listen address {}: {}", loc_uri, e))?; 
         info!("SrpcGrpcPreComm: trying to serve on {:?}", addr);
         let result = tonic::transport::Server::builder()
             .add_service(PreCommServiceServer::new(SrpcGrpcPreComm {}))
             .serve(addr)
             .await;
         
-        result
+        result.map_err(|e| e.to_string())
     }
 
     async fn connect_to(peer_uri: &str) 
@@ -114,24 +215,50 @@ impl SrpcGrpcPreComm {
         }
     }
 
+    fn endpoint_to_bin(endpoint: &ibverbs::QueuePairEndpoint) -> Result<Vec<u8>, std::string::String> {
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new(); 
+        endpoint.serialize(&mut serializer)
+            .map_err(|e| e.to_string())?;
+        Ok(serializer.view().to_vec())
+    }
+
+    fn endpoint_from_bin(bin: &[u8]) -> Result<ibverbs::QueuePairEndpoint, std::string::String> {
+        let reader = flexbuffers::Reader::get_root(bin)
+            .map_err(|e| e.to_string())?;
+        ibverbs::QueuePairEndpoint::deserialize(reader)
+            .map_err(|e| e.to_string())
+    }
+
+    // Exchanges the endpoints of the queue pairs of a session, one per 
+    // rail, for those of the peer. The peer answers for as many rails 
+    // as both ends have. 
     pub async fn get_endpoint(
-        loc_endpoint: &ibverbs::QueuePairEndpoint, 
-        peer_uri: &str
-    ) -> Option<ibverbs::QueuePairEndpoint> {
+        loc_endpoints: &[ibverbs::QueuePairEndpoint], 
+        peer_uri: &str, 
+        session_id: u32, 
+        mr_size: u32
+    ) -> Result<(Vec<ibverbs::QueuePairEndpoint>, RpcPeerMeta), std::string::String> {
         let mut conn_handle = 
             Self::connect_to(peer_uri).await;
 
-        // serialize local endpoint 
-        info!("local endpoint: {:?}", loc_endpoint);
-        let mut serializer = 
-            flexbuffers::FlexbufferSerializer::new(); 
-        loc_endpoint.serialize(&mut serializer).unwrap();
-
-        let endpoint_bin = serializer.view();
-        let endpoint_bin_vec = endpoint_bin.to_vec();
+        // serialize local endpoints 
+        info!("local endpoints: {:?}", loc_endpoints);
+        let mut endpoint_bins = loc_endpoints.iter()
+            .map(Self::endpoint_to_bin)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let endpoint_bin_vec = endpoint_bins.next().unwrap_or_default();
 
+        let mut loc_meta = RpcPeerMeta::from_conf(
+            RPC_CONF.get().unwrap(), 
+            mr_size
+        );
+        loc_meta.landing = RpcNetworkCore::create_landing(session_id);
         let request = GetEndpointRequest {
             src_endpoint: endpoint_bin_vec,
+            src_meta: Some(loc_meta.to_proto()),
+            rail_endpoints: endpoint_bins.collect(),
         };
 
         let result = 
@@ -143,25 +270,38 @@ impl SrpcGrpcPreComm {
             Ok(response) => {
                 let response = response.into_inner();
 
-                let endpoint_bin = response.endpoint.as_slice();
-                
-                let reader = 
-                    flexbuffers::Reader::get_root(
-                        endpoint_bin
-                    ).unwrap();
-                let endpoint = 
-                    ibverbs::QueuePairEndpoint::deserialize(
-                        reader
-                    ).unwrap();
-
-                info!("remote endpoint: {:?}", endpoint);
-
-                return Some(endpoint);
+                // validate callee identity and capabilities 
+                let mut dst_meta = match response.dst_meta {
+                    Some(ref meta) => RpcPeerMeta::from_proto(meta)?,
+                    None => {
+                        return Err(format!(
+                            "missing peer meta in precomm response from {}", 
+                            peer_uri));
+                    }
+                };
+                loc_meta.accept_version(dst_meta.proto_version)?;
+                loc_meta.check_compat(&dst_meta)?;
+                // the callee answers with the values it negotiated, 
+                // negotiating again agrees with them 
+                let qp_attr = loc_meta.negotiate_qp_attr(&mut dst_meta)?;
+                info!("peer {} speaks protocol version {}, qp attributes {:?}", 
+                    dst_meta.node_id, dst_meta.proto_version, qp_attr);
+
+                let endpoints = std::iter::once(&response.endpoint)
+                    .chain(response.rail_endpoints.iter())
+                    .map(|bin| Self::endpoint_from_bin(bin))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                info!("remote endpoints: {:?}", endpoints);
+
+                return Ok((endpoints, dst_meta));
             },
             Err(e) => {
                 error!("gRPC failed to get endpoint: {:?}", e);
                 
-                return None;
+                return Err(format!(
+                    "precomm with {} failed: {}", 
+                    peer_uri, e.message()));
             }
         }
     }
@@ -0,0 +1,376 @@
+use std::cell::Cell;
+
+use crate::core::srpc_core::get_mut_from_immut;
+
+// Buffer backing one slot of a pool, either a registered
+// memory region or plain heap memory when no verbs device exists.
+pub trait RpcPoolBuf {
+    fn as_slice(&self) -> &[u8];
+    fn as_mut_slice(&mut self) -> &mut [u8];
+}
+
+impl RpcPoolBuf for ibverbs::MemoryRegion<u8> {
+    fn as_slice(&self) -> &[u8] {
+        &self[..]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self[..]
+    }
+}
+
+impl RpcPoolBuf for Vec<u8> {
+    fn as_slice(&self) -> &[u8] {
+        &self[..]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self[..]
+    }
+}
+
+// Number of free lists a pool is split into,
+// threads are spread across them to avoid contention.
+const SRPC_POOL_SHARDS: usize = 8;
+
+static SHARD_CURSOR: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+thread_local! {
+    // free list preferred by the current thread
+    static SHARD_HINT: Cell<Option<usize>> = Cell::new(None);
+}
+
+fn get_shard_hint() -> usize {
+    SHARD_HINT.with(|hint| {
+        match hint.get() {
+            Some(shard) => shard,
+            None => {
+                let shard = SHARD_CURSOR.fetch_add(
+                    1,
+                    std::sync::atomic::Ordering::Relaxed
+                ) % SRPC_POOL_SHARDS;
+                hint.set(Some(shard));
+                shard
+            }
+        }
+    })
+}
+
+// Fixed set of buffers handed out by index.
+// Acquire and release pop and push a free list in O(1);
+// each thread prefers its own free list and only steals
+// from the others when it runs dry. A buffer always goes back
+// to the free list it was dealt to, so that threads releasing
+// more than they acquire do not drain the other lists.
+#[derive(Debug)]
+pub struct RpcMrPool<B: RpcPoolBuf> {
+    bufs: std::vec::Vec<B>,
+    in_use: std::vec::Vec<std::sync::atomic::AtomicBool>,
+    shards: std::vec::Vec<std::sync::Mutex<std::vec::Vec<u32>>>,
+}
+
+unsafe impl<B: RpcPoolBuf> Send for RpcMrPool<B> {}
+unsafe impl<B: RpcPoolBuf> Sync for RpcMrPool<B> {}
+
+impl<B: RpcPoolBuf> RpcMrPool<B> {
+    pub fn new(bufs: std::vec::Vec<B>) -> RpcMrPool<B> {
+        let mut shards = std::vec::Vec::new();
+        for _ in 0..SRPC_POOL_SHARDS {
+            shards.push(std::sync::Mutex::new(std::vec::Vec::new()));
+        }
+
+        // deal the buffers out evenly
+        for index in (0..bufs.len()).rev() {
+            shards[Self::origin_of(index as u32)].get_mut().unwrap()
+                .push(index as u32);
+        }
+
+        let in_use = (0..bufs.len())
+            .map(|_| std::sync::atomic::AtomicBool::new(false))
+            .collect();
+
+        RpcMrPool {
+            bufs: bufs,
+            in_use: in_use,
+            shards: shards,
+        }
+    }
+
+    // Free list the buffer was dealt to.
+    fn origin_of(index: u32) -> usize {
+        index as usize % SRPC_POOL_SHARDS
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bufs.len()
+    }
+
+    // Takes a vacant buffer, returns its index.
+    pub fn acquire(&self) -> Option<u32> {
+        let hint = get_shard_hint();
+
+        for i in 0..SRPC_POOL_SHARDS {
+            let shard = &self.shards[(hint + i) % SRPC_POOL_SHARDS];
+            let index = shard.lock().unwrap().pop();
+            if let Some(index) = index {
+                self.in_use[index as usize].store(
+                    true,
+                    std::sync::atomic::Ordering::Release
+                );
+                return Some(index);
+            }
+        }
+
+        None
+    }
+
+    // Gives the buffer back to the pool, ignoring buffers
+    // that are not currently handed out.
+    pub fn release(&self, index: u32) -> bool {
+        let in_use = match self.in_use.get(index as usize) {
+            Some(in_use) => in_use,
+            None => return false,
+        };
+        if !in_use.swap(false, std::sync::atomic::Ordering::AcqRel) {
+            return false;
+        }
+
+        self.shards[Self::origin_of(index)].lock().unwrap().push(index);
+
+        true
+    }
+
+    pub fn is_in_use(&self, index: u32) -> bool {
+        match self.in_use.get(index as usize) {
+            Some(in_use) => in_use.load(std::sync::atomic::Ordering::Acquire),
+            None => false,
+        }
+    }
+
+    pub fn get_buf(&self, index: u32) -> Option<&B> {
+        self.bufs.get(index as usize)
+    }
+
+    // The caller must hold the buffer, i.e. have acquired it
+    // and not released it yet.
+    pub unsafe fn get_buf_mut(&self, index: u32) -> Option<&mut B> {
+        match self.bufs.get(index as usize) {
+            Some(buf) => Some(get_mut_from_immut(buf)),
+            None => None,
+        }
+    }
+}
+
+// Default number of buffers in each size class.
+pub const SRPC_MR_CLASS_COUNT: u32 = 1024;
+
+// Size class of memory regions, written as `size[:count]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpcMrClass {
+    pub size: u32,
+    pub count: u32,
+}
+
+impl std::str::FromStr for RpcMrClass {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let size = parts.next().unwrap_or("").parse::<u32>()
+            .map_err(|e| format!("bad memory region class {}: {}", s, e))?;
+        let count = match parts.next() {
+            Some(count) => count.parse::<u32>()
+                .map_err(|e| format!("bad memory region class {}: {}", s, e))?,
+            None => SRPC_MR_CLASS_COUNT,
+        };
+
+        Ok(RpcMrClass {
+            size: size,
+            count: count,
+        })
+    }
+}
+
+impl RpcMrClass {
+    // Sorts and deduplicates the configured classes,
+    // falling back to a single class of `default_size`.
+    pub fn normalize(
+        classes: &[RpcMrClass],
+        default_size: u32
+    ) -> std::vec::Vec<RpcMrClass> {
+        let mut classes: std::vec::Vec<RpcMrClass> = classes.iter()
+            .filter(|class| class.size > 0 && class.count > 0)
+            .cloned()
+            .collect();
+        if classes.is_empty() {
+            classes.push(RpcMrClass {
+                size: default_size,
+                count: SRPC_MR_CLASS_COUNT,
+            });
+        }
+
+        classes.sort_by_key(|class| class.size);
+        classes.dedup_by_key(|class| class.size);
+        classes
+    }
+}
+
+// One pool per size class, in ascending order of size.
+#[derive(Debug)]
+pub struct RpcMrSlab<B: RpcPoolBuf> {
+    classes: std::vec::Vec<(RpcMrClass, RpcMrPool<B>)>,
+}
+
+impl<B: RpcPoolBuf> RpcMrSlab<B> {
+    // Builds the pools, `alloc` creates one buffer of the given size.
+    pub fn new<F>(
+        classes: &[RpcMrClass],
+        mut alloc: F
+    ) -> RpcMrSlab<B>
+    where F: FnMut(usize) -> B
+    {
+        let mut pools = std::vec::Vec::new();
+        for class in classes {
+            let bufs = (0..class.count)
+                .map(|_| alloc(class.size as usize))
+                .collect();
+            pools.push((*class, RpcMrPool::new(bufs)));
+        }
+        pools.sort_by_key(|(class, _)| class.size);
+
+        RpcMrSlab {
+            classes: pools,
+        }
+    }
+
+    // Class whose buffers are exactly `size` bytes.
+    pub fn class_by_size(&self, size: u32) -> Option<u8> {
+        self.classes.iter()
+            .position(|(class, _)| class.size == size)
+            .map(|class_id| class_id as u8)
+    }
+
+    pub fn get_class(&self, class_id: u8) -> Option<RpcMrClass> {
+        self.classes.get(class_id as usize)
+            .map(|(class, _)| *class)
+    }
+
+    pub fn get_pool(&self, class_id: u8) -> Option<&RpcMrPool<B>> {
+        self.classes.get(class_id as usize)
+            .map(|(_, pool)| pool)
+    }
+
+    pub fn get_sizes(&self) -> std::vec::Vec<u32> {
+        self.classes.iter()
+            .map(|(class, _)| class.size)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap_pool(count: usize, size: usize) -> RpcMrPool<Vec<u8>> {
+        RpcMrPool::new((0..count).map(|_| vec![0u8; size]).collect())
+    }
+
+    #[test]
+    fn acquire_hands_out_every_buffer_once() {
+        let pool = heap_pool(20, 16);
+        let mut indexes: Vec<u32> = (0..20)
+            .map(|_| pool.acquire().unwrap())
+            .collect();
+        assert!(pool.acquire().is_none());
+
+        indexes.sort();
+        indexes.dedup();
+        assert_eq!(indexes, (0..20).collect::<Vec<u32>>());
+        assert!(indexes.iter().all(|index| pool.is_in_use(*index)));
+    }
+
+    #[test]
+    fn release_makes_the_buffer_vacant_once() {
+        let pool = heap_pool(4, 16);
+        let index = pool.acquire().unwrap();
+
+        assert!(pool.release(index));
+        assert!(!pool.is_in_use(index));
+        // released twice or never handed out
+        assert!(!pool.release(index));
+        assert!(!pool.release(100));
+    }
+
+    #[test]
+    fn release_returns_the_buffer_to_its_origin_shard() {
+        let pool = heap_pool(SRPC_POOL_SHARDS * 2, 16);
+        // drain the pool from a single thread, stealing from every shard
+        let indexes: Vec<u32> = (0..SRPC_POOL_SHARDS * 2)
+            .map(|_| pool.acquire().unwrap())
+            .collect();
+        for index in indexes.iter() {
+            pool.release(*index);
+        }
+
+        for (shard_id, shard) in pool.shards.iter().enumerate() {
+            let shard = shard.lock().unwrap();
+            assert_eq!(shard.len(), 2);
+            assert!(shard.iter()
+                .all(|index| *index as usize % SRPC_POOL_SHARDS == shard_id));
+        }
+    }
+
+    #[test]
+    fn buffers_released_on_other_threads_stay_balanced() {
+        let pool = std::sync::Arc::new(heap_pool(SRPC_POOL_SHARDS * 4, 16));
+        let indexes: Vec<u32> = (0..SRPC_POOL_SHARDS * 4)
+            .map(|_| pool.acquire().unwrap())
+            .collect();
+
+        let releaser = pool.clone();
+        std::thread::spawn(move || {
+            for index in indexes {
+                assert!(releaser.release(index));
+            }
+        }).join().unwrap();
+
+        assert!(pool.shards.iter()
+            .all(|shard| shard.lock().unwrap().len() == 4));
+    }
+
+    #[test]
+    fn held_buffer_is_writable() {
+        let pool = heap_pool(2, 8);
+        let index = pool.acquire().unwrap();
+
+        let buf = unsafe { pool.get_buf_mut(index) }.unwrap();
+        buf.as_mut_slice().copy_from_slice(b"abcdefgh");
+        assert_eq!(pool.get_buf(index).unwrap().as_slice(), b"abcdefgh");
+    }
+
+    #[test]
+    fn slab_sorts_and_finds_classes_by_size() {
+        let classes = RpcMrClass::normalize(&[
+            RpcMrClass { size: 4096, count: 2 },
+            RpcMrClass { size: 256, count: 4 },
+            RpcMrClass { size: 256, count: 8 },
+        ], 1024);
+        let slab: RpcMrSlab<Vec<u8>> = RpcMrSlab::new(&classes, |size| vec![0u8; size]);
+
+        assert_eq!(slab.get_sizes(), vec![256, 4096]);
+        assert_eq!(slab.class_by_size(256), Some(0));
+        assert_eq!(slab.class_by_size(4096), Some(1));
+        assert_eq!(slab.class_by_size(1024), None);
+        assert_eq!(slab.get_pool(0).unwrap().capacity(), 4);
+    }
+
+    #[test]
+    fn class_parses_size_and_count() {
+        assert_eq!("512:16".parse::<RpcMrClass>(),
+            Ok(RpcMrClass { size: 512, count: 16 }));
+        assert_eq!("512".parse::<RpcMrClass>(),
+            Ok(RpcMrClass { size: 512, count: SRPC_MR_CLASS_COUNT }));
+        assert!("x:1".parse::<RpcMrClass>().is_err());
+    }
+}
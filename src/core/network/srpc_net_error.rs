@@ -0,0 +1,176 @@
+// Reasons a message could not be handed to the network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcNetworkError
+{
+    // Every memory region of the size class is in use.
+    MrExhausted,
+    // Too many sends in flight on this node.
+    InflightLimit,
+    // Too many sends in flight on the session.
+    SessionInflightLimit(u32),
+    // The peer granted no credit left on the session.
+    NoCredit(u32),
+    // The landing region of the peer still holds the previous message.
+    LandingBusy(u32),
+    // No queue pair is bound to the session.
+    NoSession(u32),
+    // No session to the peer is connected.
+    NoPeer(u32),
+    // No session to the peer is able to carry the message.
+    MsgTooLarge(usize),
+    // The verbs layer refused the work request.
+    Verbs(std::string::String),
+    // Bytes at an offset lie beyond the end of the memory region.
+    OutOfRange(u64, usize),
+    // The remote word of an atomic operation is not 8-byte aligned.
+    Misaligned(u64),
+    // The work request completed with an error status.
+    Completion(std::string::String),
+    // The configured device, port or GID is unavailable.
+    Device(std::string::String),
+    // The adapter completed the work request with an error status.
+    WorkRequest(RpcWcError),
+    // The session failed, its queue pair carries no more work requests.
+    SessionFailed(u32),
+    // The session failed on a work request completing with the error,
+    // (session_id, error).
+    SessionLost(u32, RpcWcError),
+    // The protocol version of the session does not carry the frame,
+    // (session_id, version).
+    Unsupported(u32, u32),
+    // The message could not be encoded for the session.
+    Codec(std::string::String),
+}
+
+impl RpcNetworkError
+{
+    // Whether the send may succeed once in-flight sends complete.
+    pub fn is_retryable(&self) -> bool
+    {
+        match self
+        {
+            RpcNetworkError::MrExhausted => true,
+            RpcNetworkError::InflightLimit => true,
+            RpcNetworkError::SessionInflightLimit(_) => true,
+            RpcNetworkError::NoCredit(_) => true,
+            RpcNetworkError::LandingBusy(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for RpcNetworkError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            RpcNetworkError::MrExhausted =>
+                write!(f, "no vacant memory region"),
+            RpcNetworkError::InflightLimit =>
+                write!(f, "in-flight send limit reached"),
+            RpcNetworkError::SessionInflightLimit(session_id) =>
+                write!(f, "in-flight send limit reached on session {}", session_id),
+            RpcNetworkError::NoCredit(session_id) =>
+                write!(f, "no credit left on session {}", session_id),
+            RpcNetworkError::LandingBusy(session_id) =>
+                write!(f, "landing region busy on session {}", session_id),
+            RpcNetworkError::NoSession(session_id) =>
+                write!(f, "no queue pair for session {}", session_id),
+            RpcNetworkError::NoPeer(peer_id) =>
+                write!(f, "no session to peer {}", peer_id),
+            RpcNetworkError::MsgTooLarge(len) =>
+                write!(f, "message of {} bytes is too large", len),
+            RpcNetworkError::Verbs(e) =>
+                write!(f, "verbs error: {}", e),
+            RpcNetworkError::OutOfRange(offset, len) =>
+                write!(f, "{} bytes at offset {} out of the memory region", len, offset),
+            RpcNetworkError::Misaligned(addr) =>
+                write!(f, "address {:#x} is not 8-byte aligned", addr),
+            RpcNetworkError::Completion(e) =>
+                write!(f, "work request failed: {}", e),
+            RpcNetworkError::Device(e) =>
+                write!(f, "device error: {}", e),
+            RpcNetworkError::WorkRequest(e) =>
+                write!(f, "work request failed: {}", e),
+            RpcNetworkError::SessionFailed(session_id) =>
+                write!(f, "session {} failed", session_id),
+            RpcNetworkError::SessionLost(session_id, e) =>
+                write!(f, "session {} failed: {}", session_id, e),
+            RpcNetworkError::Unsupported(session_id, version) =>
+                write!(f, "frame not carried by protocol version {} of session {}",
+                    version, session_id),
+            RpcNetworkError::Codec(e) =>
+                write!(f, "codec error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcNetworkError {}
+
+// Error statuses of completions, as far as callers tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcWcError
+{
+    // Flushed as the queue pair entered the error state on an earlier failure.
+    Flushed,
+    // The peer acknowledged nothing within the retry count, it is likely gone.
+    RetryExceeded,
+    // The peer had no receive posted within the RNR retry count.
+    RnrRetryExceeded,
+    // The peer refused access to its memory, wrong key or range.
+    RemoteAccess,
+    // The peer found the request invalid or failed to perform it.
+    RemoteOperation,
+    // A receive buffer was too small for the frame.
+    LocalLength,
+    // A local buffer was not registered for the access.
+    LocalProtection,
+    // Any other status, as reported by the verbs layer.
+    Other(u32),
+}
+
+impl RpcWcError
+{
+    pub fn from_status(status: ibverbs::ibv_wc_status::Type) -> RpcWcError
+    {
+        match status
+        {
+            ibverbs::ibv_wc_status::IBV_WC_WR_FLUSH_ERR => RpcWcError::Flushed,
+            ibverbs::ibv_wc_status::IBV_WC_RETRY_EXC_ERR => RpcWcError::RetryExceeded,
+            ibverbs::ibv_wc_status::IBV_WC_RNR_RETRY_EXC_ERR => RpcWcError::RnrRetryExceeded,
+            ibverbs::ibv_wc_status::IBV_WC_REM_ACCESS_ERR => RpcWcError::RemoteAccess,
+            ibverbs::ibv_wc_status::IBV_WC_REM_INV_REQ_ERR
+            | ibverbs::ibv_wc_status::IBV_WC_REM_OP_ERR => RpcWcError::RemoteOperation,
+            ibverbs::ibv_wc_status::IBV_WC_LOC_LEN_ERR => RpcWcError::LocalLength,
+            ibverbs::ibv_wc_status::IBV_WC_LOC_PROT_ERR => RpcWcError::LocalProtection,
+            status => RpcWcError::Other(status as u32),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcWcError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            RpcWcError::Flushed =>
+                write!(f, "flushed after the queue pair failed"),
+            RpcWcError::RetryExceeded =>
+                write!(f, "transport retries exceeded"),
+            RpcWcError::RnrRetryExceeded =>
+                write!(f, "receiver-not-ready retries exceeded"),
+            RpcWcError::RemoteAccess =>
+                write!(f, "remote access refused"),
+            RpcWcError::RemoteOperation =>
+                write!(f, "remote operation failed"),
+            RpcWcError::LocalLength =>
+                write!(f, "local length error"),
+            RpcWcError::LocalProtection =>
+                write!(f, "local protection error"),
+            RpcWcError::Other(status) =>
+                write!(f, "completion status {}", status),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::conf::conf::{RpcConf, RPC_CONF};
+use crate::msg::srpc_frame::RpcFrameLayout;
+
+use super::srpc_core_network::RpcNetworkCore;
+use super::srpc_grpc::precomm_grpc::{PeerMeta, RemoteKey};
+use super::srpc_qp_attr::RpcQpAttr;
+use super::srpc_remote_mr::RpcRemoteKey;
+
+// Wire protocol versions spoken by this build, in ascending order.
+// Version 2 replaced the length trailer with the frame trailer,
+// version 3 moved it to the front and sends only the bytes in use,
+// version 4 added frames of several kinds, batches first.
+pub const SRPC_PROTO_VERSIONS: &[u32] = &[1, 2, 3, 4];
+// Codec used to serialize RPC messages.
+pub const SRPC_CODEC: &str = "flexbuffers";
+
+// Optional feature bits advertised during the handshake.
+pub const SRPC_FEATURES: u64 = 0;
+
+// Identity and capabilities of one end of a connection,
+// exchanged through the precomm handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcPeerMeta
+{
+    pub node_id: u32,
+    pub proto_version: u32,
+    pub proto_versions: Vec<u32>,
+    pub mr_size: u32,
+    pub max_msg_size: u32,
+    pub features: u64,
+    pub codec: std::string::String,
+    pub credits: u32,
+    pub regions: Vec<RpcRemoteKey>,
+    pub landing: Option<RpcRemoteKey>,
+    pub ud_endpoint: Vec<u8>,
+    pub qp_attr: RpcQpAttr,
+}
+
+impl RpcPeerMeta
+{
+    // Describes the local end according to the configuration,
+    // for a session using memory regions of `mr_size` bytes.
+    pub fn from_conf(conf: &RpcConf, mr_size: u32) -> RpcPeerMeta
+    {
+        let proto_versions = Self::supported_versions(conf);
+        let proto_version = proto_versions.last()
+            .cloned().unwrap_or(0);
+
+        RpcPeerMeta
+        {
+            node_id: conf.loc_id,
+            proto_version: proto_version,
+            proto_versions: proto_versions,
+            mr_size: mr_size,
+            max_msg_size: Self::max_msg_size_of(mr_size, proto_version),
+            features: SRPC_FEATURES,
+            codec: SRPC_CODEC.to_string(),
+            credits: RpcNetworkCore::get_recv_credits(mr_size),
+            regions: RpcNetworkCore::get_exposed_regions(),
+            // set once the session is known
+            landing: None,
+            ud_endpoint: RpcNetworkCore::get_ud_endpoint(),
+            qp_attr: Self::qp_attr_of(conf),
+        }
+    }
+
+    // Queue pair attributes to advertise, with the active MTU of the
+    // ports when the path MTU is left to them.
+    fn qp_attr_of(conf: &RpcConf) -> RpcQpAttr
+    {
+        let mut qp_attr = RpcQpAttr::from_conf(conf);
+        if qp_attr.path_mtu == 0
+        {
+            qp_attr.path_mtu = RpcNetworkCore::get_active_path_mtu();
+        }
+        qp_attr
+    }
+
+    // Protocol versions to advertise, restricted to the ones
+    // enabled in the configuration if any.
+    fn supported_versions(conf: &RpcConf) -> Vec<u32>
+    {
+        SRPC_PROTO_VERSIONS.iter()
+            .filter(|v| conf.proto_versions.is_empty()
+                || conf.proto_versions.contains(v))
+            .cloned()
+            .collect()
+    }
+
+    // Largest frame body fitting in a memory region
+    // with the frame layout of the protocol version.
+    pub fn max_msg_size_of(mr_size: u32, proto_version: u32) -> u32
+    {
+        RpcFrameLayout::of_version(proto_version).max_body_len(mr_size)
+    }
+
+    // Fails on queue pair attributes out of range.
+    pub fn from_proto(meta: &PeerMeta) -> Result<RpcPeerMeta, std::string::String>
+    {
+        // peers advertising a single version leave the list empty
+        let mut proto_versions = meta.proto_versions.clone();
+        if proto_versions.is_empty()
+        {
+            proto_versions.push(meta.proto_version);
+        }
+
+        // peers without tuning take the local values
+        let qp_attr = match meta.qp_attr
+        {
+            Some(ref qp_attr) => RpcQpAttr::from_proto(qp_attr)?,
+            None => RpcQpAttr::from_conf(RPC_CONF.get().unwrap()),
+        };
+
+        Ok(RpcPeerMeta
+        {
+            node_id: meta.node_id,
+            proto_version: meta.proto_version,
+            proto_versions: proto_versions,
+            mr_size: meta.mr_size,
+            max_msg_size: meta.max_msg_size,
+            features: meta.features,
+            codec: meta.codec.clone(),
+            credits: meta.credits,
+            regions: meta.regions.iter()
+                .map(Self::key_from_proto)
+                .collect(),
+            landing: meta.landing.as_ref().map(Self::key_from_proto),
+            ud_endpoint: meta.ud_endpoint.clone(),
+            qp_attr: qp_attr,
+        })
+    }
+
+    pub fn to_proto(&self) -> PeerMeta
+    {
+        PeerMeta
+        {
+            node_id: self.node_id,
+            proto_version: self.proto_version,
+            proto_versions: self.proto_versions.clone(),
+            mr_size: self.mr_size,
+            max_msg_size: self.max_msg_size,
+            features: self.features,
+            codec: self.codec.clone(),
+            credits: self.credits,
+            regions: self.regions.iter()
+                .map(Self::key_to_proto)
+                .collect(),
+            landing: self.landing.as_ref().map(Self::key_to_proto),
+            ud_endpoint: self.ud_endpoint.clone(),
+            qp_attr: Some(self.qp_attr.to_proto()),
+        }
+    }
+
+    fn key_from_proto(key: &RemoteKey) -> RpcRemoteKey
+    {
+        RpcRemoteKey
+        {
+            region_id: key.region_id,
+            addr: key.addr,
+            len: key.len,
+            rkey: key.rkey,
+        }
+    }
+
+    fn key_to_proto(key: &RpcRemoteKey) -> RemoteKey
+    {
+        RemoteKey
+        {
+            region_id: key.region_id,
+            addr: key.addr,
+            len: key.len,
+            rkey: key.rkey,
+        }
+    }
+
+    // Picks the highest protocol version supported by both ends,
+    // then records it on both descriptions. Peers size their messages
+    // for the highest version they advertise, both ends are sized
+    // again for the one picked.
+    pub fn negotiate_version(
+        &mut self, 
+        peer: &mut RpcPeerMeta
+    ) -> Result<u32, std::string::String>
+    {
+        let version = self.proto_versions.iter()
+            .filter(|v| peer.proto_versions.contains(v))
+            .max()
+            .cloned();
+
+        match version
+        {
+            Some(version) =>
+            {
+                self.set_version(version);
+                peer.set_version(version);
+                Ok(version)
+            },
+            None =>
+            {
+                Err(format!(
+                    "no common protocol version: local {:?}, peer {} has {:?}",
+                    self.proto_versions, peer.node_id, peer.proto_versions
+                ))
+            },
+        }
+    }
+
+    // Adopts the protocol version chosen by the remote end,
+    // provided it is one the local end advertised.
+    pub fn accept_version(&mut self, version: u32) -> Result<(), std::string::String>
+    {
+        if !self.proto_versions.contains(&version)
+        {
+            return Err(format!(
+                "peer chose protocol version {} not in {:?}",
+                version, self.proto_versions
+            ));
+        }
+
+        self.set_version(version);
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: u32)
+    {
+        self.proto_version = version;
+        self.max_msg_size = Self::max_msg_size_of(self.mr_size, version);
+    }
+
+    // Checks whether the remote end is able to talk to the local end.
+    // Frames of a session are received into memory regions of the
+    // size agreed on, so both ends must use the same region size.
+    // Node IDs tell the senders of frames apart, so a peer must not
+    // claim the local one.
+    pub fn check_compat(&self, peer: &RpcPeerMeta) -> Result<(), std::string::String>
+    {
+        if peer.node_id == 0 || peer.node_id == self.node_id
+        {
+            return Err(format!(
+                "peer claims node id {}, local is {}",
+                peer.node_id, self.node_id
+            ));
+        }
+
+        if self.proto_version != peer.proto_version
+        {
+            return Err(format!(
+                "protocol version mismatch: local {}, peer {} has {}",
+                self.proto_version, peer.node_id, peer.proto_version
+            ));
+        }
+
+        if self.codec != peer.codec
+        {
+            return Err(format!(
+                "codec mismatch: local {:?}, peer {} has {:?}",
+                self.codec, peer.node_id, peer.codec
+            ));
+        }
+
+        if self.mr_size != peer.mr_size
+        {
+            return Err(format!(
+                "memory region size mismatch: local {}, peer {} has {}",
+                self.mr_size, peer.node_id, peer.mr_size
+            ));
+        }
+
+        if self.max_msg_size != peer.max_msg_size
+        {
+            return Err(format!(
+                "max message size mismatch: local {}, peer {} has {}",
+                self.max_msg_size, peer.node_id, peer.max_msg_size
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Key of a region the peer exposed during the handshake.
+    pub fn get_region(&self, region_id: u32) -> Option<&RpcRemoteKey>
+    {
+        self.regions.iter().find(|key| key.region_id == region_id)
+    }
+
+    // Negotiates the queue pair attributes with the remote end, then
+    // records the outcome on both descriptions.
+    pub fn negotiate_qp_attr(&mut self, peer: &mut RpcPeerMeta) -> Result<RpcQpAttr, std::string::String>
+    {
+        let qp_attr = self.qp_attr.negotiate(&peer.qp_attr);
+        qp_attr.check()?;
+
+        // both ends keep the outcome, the session reporting the
+        // values its queue pairs connect with
+        self.qp_attr = qp_attr;
+        peer.qp_attr = qp_attr;
+        Ok(qp_attr)
+    }
+
+    // Features supported by both ends.
+    pub fn common_features(&self, peer: &RpcPeerMeta) -> u64
+    {
+        self.features & peer.features
+    }
+}
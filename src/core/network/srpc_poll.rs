@@ -0,0 +1,93 @@
+// How completion queues are watched for new completions.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RpcPollMode {
+    // Poll in a tight loop on a blocking thread, lowest latency.
+    Busy,
+    // Sleep on the completion channel until the device signals.
+    Event,
+    // Spin for a while after the last completion,
+    // then sleep on the completion channel.
+    Hybrid,
+}
+
+impl std::str::FromStr for RpcPollMode {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "busy" => Ok(RpcPollMode::Busy),
+            "event" => Ok(RpcPollMode::Event),
+            "hybrid" => Ok(RpcPollMode::Hybrid),
+            _ => Err(format!("unknown polling mode: {}", s)),
+        }
+    }
+}
+
+// Tells the pollers of a completion queue to stop once the queue
+// is no longer used, e.g. when the session owning it is dropped.
+#[derive(Debug, Default)]
+pub struct RpcPollStop {
+    stopped: std::sync::atomic::AtomicBool,
+    notify: tokio::sync::Notify,
+}
+
+impl RpcPollStop {
+    pub fn new() -> RpcPollStop {
+        RpcPollStop::default()
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, std::sync::atomic::Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    // Resolves once stop was called, also if it was before.
+    pub async fn stopped(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // registered before checking, so a stop in between is not missed
+        notified.as_mut().enable();
+        if self.is_stopped() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!("busy".parse::<RpcPollMode>(), Ok(RpcPollMode::Busy));
+        assert_eq!("hybrid".parse::<RpcPollMode>(), Ok(RpcPollMode::Hybrid));
+        assert!("spin".parse::<RpcPollMode>().is_err());
+    }
+
+    #[tokio::test]
+    async fn stopped_resolves_after_stop() {
+        let stop = std::sync::Arc::new(RpcPollStop::new());
+        let waiter = {
+            let stop = stop.clone();
+            tokio::spawn(async move { stop.stopped().await })
+        };
+        tokio::task::yield_now().await;
+
+        assert!(!stop.is_stopped());
+        stop.stop();
+        waiter.await.unwrap();
+        assert!(stop.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn stopped_resolves_when_already_stopped() {
+        let stop = RpcPollStop::new();
+        stop.stop();
+        stop.stopped().await;
+    }
+}
@@ -0,0 +1,215 @@
+use crate::conf::conf::RpcConf;
+
+use super::srpc_grpc::precomm_grpc::QpAttr;
+
+// Transport attributes of the queue pairs of a session. Both ends
+// advertise theirs during the handshake and connect with the values
+// negotiated from them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpcQpAttr
+{
+    // path MTU in bytes, 0 for the active MTU of the ports
+    pub path_mtu: u32,
+    // local ACK timeout, 4.096us * 2^timeout, 0 waits forever
+    pub timeout: u8,
+    // retransmissions before a send fails
+    pub retry_cnt: u8,
+    // retries on receiver-not-ready, 7 retries forever
+    pub rnr_retry: u8,
+    // delay the peer waits before retrying a receiver-not-ready, encoded
+    pub min_rnr_timer: u8,
+    // bytes a send may carry inline, local to each end
+    pub max_inline: u32,
+}
+
+impl RpcQpAttr
+{
+    pub fn from_conf(conf: &RpcConf) -> RpcQpAttr
+    {
+        RpcQpAttr
+        {
+            path_mtu: conf.path_mtu,
+            timeout: conf.qp_timeout,
+            retry_cnt: conf.retry_cnt,
+            rnr_retry: conf.rnr_retry,
+            min_rnr_timer: conf.min_rnr_timer,
+            max_inline: conf.max_inline,
+        }
+    }
+
+    // Values both ends connect with: the smaller path MTU, as frames
+    // must fit the path either way, and the more patient timeout and
+    // retry counts, so that neither end gives up on a fabric the other
+    // was tuned for, the RNR timer being compared by the delay it
+    // stands for. The inline size stays local.
+    // Negotiating again with the outcome yields the outcome.
+    pub fn negotiate(&self, peer: &RpcQpAttr) -> RpcQpAttr
+    {
+        let path_mtu = match (self.path_mtu, peer.path_mtu)
+        {
+            (0, mtu) | (mtu, 0) => mtu,
+            (mtu, peer_mtu) => std::cmp::min(mtu, peer_mtu),
+        };
+        let timeout = if self.timeout == 0 || peer.timeout == 0
+        {
+            0
+        }
+        else
+        {
+            std::cmp::max(self.timeout, peer.timeout)
+        };
+
+        RpcQpAttr
+        {
+            path_mtu: path_mtu,
+            timeout: timeout,
+            retry_cnt: std::cmp::max(self.retry_cnt, peer.retry_cnt),
+            rnr_retry: std::cmp::max(self.rnr_retry, peer.rnr_retry),
+            min_rnr_timer: std::cmp::max_by_key(self.min_rnr_timer, peer.min_rnr_timer,
+                |timer| Self::rnr_delay_of(*timer)),
+            max_inline: self.max_inline,
+        }
+    }
+
+    // Checks the values are ones the verbs layer accepts.
+    pub fn check(&self) -> Result<(), std::string::String>
+    {
+        if self.path_mtu != 0 && Self::mtu_of(self.path_mtu).is_none()
+        {
+            return Err(format!("path mtu {} not among 256, 512, 1024, 2048, 4096",
+                self.path_mtu));
+        }
+        if self.timeout > 31
+        {
+            return Err(format!("qp timeout {} beyond 31", self.timeout));
+        }
+        if self.retry_cnt > 7 || self.rnr_retry > 7
+        {
+            return Err(format!("retry counts {}/{} beyond 7",
+                self.retry_cnt, self.rnr_retry));
+        }
+        if self.min_rnr_timer > 31
+        {
+            return Err(format!("min rnr timer {} beyond 31", self.min_rnr_timer));
+        }
+        Ok(())
+    }
+
+    // Delay an encoded RNR timer stands for, in units of 10us, per the
+    // table of the InfiniBand specification; 0 is the longest one.
+    fn rnr_delay_of(min_rnr_timer: u8) -> u32
+    {
+        const RNR_DELAYS: [u32; 32] = [
+            65536, 1, 2, 3, 4, 6, 8, 12,
+            16, 24, 32, 48, 64, 96, 128, 192,
+            256, 384, 512, 768, 1024, 1536, 2048, 3072,
+            4096, 6144, 8192, 12288, 16384, 24576, 32768, 49152,
+        ];
+        RNR_DELAYS.get(min_rnr_timer as usize).cloned().unwrap_or(0)
+    }
+
+    fn mtu_of(path_mtu: u32) -> Option<ibverbs::ibv_mtu>
+    {
+        match path_mtu
+        {
+            256 => Some(ibverbs::ibv_mtu::IBV_MTU_256),
+            512 => Some(ibverbs::ibv_mtu::IBV_MTU_512),
+            1024 => Some(ibverbs::ibv_mtu::IBV_MTU_1024),
+            2048 => Some(ibverbs::ibv_mtu::IBV_MTU_2048),
+            4096 => Some(ibverbs::ibv_mtu::IBV_MTU_4096),
+            _ => None,
+        }
+    }
+
+    // Sets the negotiated values on a queue pair about to connect.
+    pub fn apply(&self, qp: &mut ibverbs::PreparedQueuePair)
+    {
+        if let Some(mtu) = Self::mtu_of(self.path_mtu)
+        {
+            qp.set_path_mtu(mtu);
+        }
+        qp.set_timeout(self.timeout);
+        qp.set_retry_count(self.retry_cnt);
+        qp.set_rnr_retry(self.rnr_retry);
+        qp.set_min_rnr_timer(self.min_rnr_timer);
+    }
+
+    // Fails on values not fitting the fields, which no peer sends.
+    pub fn from_proto(attr: &QpAttr) -> Result<RpcQpAttr, std::string::String>
+    {
+        let field = |name: &str, value: u32| u8::try_from(value)
+            .map_err(|_| format!("qp attribute {} {} out of range", name, value));
+
+        Ok(RpcQpAttr
+        {
+            path_mtu: attr.path_mtu,
+            timeout: field("timeout", attr.timeout)?,
+            retry_cnt: field("retry_cnt", attr.retry_cnt)?,
+            rnr_retry: field("rnr_retry", attr.rnr_retry)?,
+            min_rnr_timer: field("min_rnr_timer", attr.min_rnr_timer)?,
+            max_inline: attr.max_inline,
+        })
+    }
+
+    pub fn to_proto(&self) -> QpAttr
+    {
+        QpAttr
+        {
+            path_mtu: self.path_mtu,
+            timeout: self.timeout as u32,
+            retry_cnt: self.retry_cnt as u32,
+            rnr_retry: self.rnr_retry as u32,
+            min_rnr_timer: self.min_rnr_timer as u32,
+            max_inline: self.max_inline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn attr(min_rnr_timer: u8) -> RpcQpAttr
+    {
+        RpcQpAttr
+        {
+            path_mtu: 4096,
+            timeout: 14,
+            retry_cnt: 7,
+            rnr_retry: 7,
+            min_rnr_timer: min_rnr_timer,
+            max_inline: 64,
+        }
+    }
+
+    #[test]
+    fn rnr_timer_takes_the_longer_delay()
+    {
+        // 31 is 491.52ms, 0 is 655.36ms
+        assert_eq!(attr(31).negotiate(&attr(0)).min_rnr_timer, 0);
+        assert_eq!(attr(0).negotiate(&attr(31)).min_rnr_timer, 0);
+        assert_eq!(attr(12).negotiate(&attr(5)).min_rnr_timer, 12);
+    }
+
+    #[test]
+    fn negotiating_again_yields_the_outcome()
+    {
+        let mut peer = attr(3);
+        peer.path_mtu = 1024;
+        peer.timeout = 0;
+        let outcome = attr(0).negotiate(&peer);
+        assert_eq!(outcome.path_mtu, 1024);
+        assert_eq!(outcome.timeout, 0);
+        assert_eq!(outcome.negotiate(&peer), outcome);
+    }
+
+    #[test]
+    fn out_of_range_fields_are_rejected()
+    {
+        let mut proto = attr(0).to_proto();
+        assert_eq!(RpcQpAttr::from_proto(&proto), Ok(attr(0)));
+        proto.retry_cnt = 263;
+        assert!(RpcQpAttr::from_proto(&proto).is_err());
+    }
+}
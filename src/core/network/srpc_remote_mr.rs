@@ -0,0 +1,149 @@
+use serde::{Serialize, Deserialize};
+
+use super::srpc_core_network::RpcNetworkCore;
+use super::srpc_net_error::RpcNetworkError;
+use crate::msg::srpc_atomic::RpcAtomicOp;
+
+// Key of a memory region exposed to peers, all they need to read
+// and write it with one-sided operations. Carried by the precomm
+// handshake, or by the application in its own RPC payloads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRemoteKey
+{
+    pub region_id: u32,
+    pub addr: u64,
+    pub len: u64,
+    pub rkey: u32,
+}
+
+impl RpcRemoteKey
+{
+    // Remote address of `len` bytes at `offset` in the region,
+    // fails if they do not lie within it.
+    pub fn addr_of(&self, offset: u64, len: usize) -> Result<u64, RpcNetworkError>
+    {
+        match offset.checked_add(len as u64)
+        {
+            Some(end) if end <= self.len => Ok(self.addr + offset),
+            _ => Err(RpcNetworkError::OutOfRange(offset, len)),
+        }
+    }
+}
+
+// One-sided operations on the region of a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcRemoteOpKind
+{
+    // Copies remote bytes into the local region.
+    Read,
+    // Copies local bytes into the remote region.
+    Write,
+    // Performs the operation on a remote word, the previous value
+    // of which lands in the local region.
+    Atomic(RpcAtomicOp),
+}
+
+// Application buffer registered with the device. Peers given its key
+// access it without involving the local CPU, so the owner must not
+// rely on its content while they may be writing to it.
+// The region is withdrawn from peers when dropped.
+pub struct RpcMemRegion
+{
+    region_id: u32,
+    mr: ibverbs::MemoryRegion<u8>,
+}
+
+unsafe impl Send for RpcMemRegion {}
+unsafe impl Sync for RpcMemRegion {}
+
+impl RpcMemRegion
+{
+    pub fn new(region_id: u32, mr: ibverbs::MemoryRegion<u8>) -> RpcMemRegion
+    {
+        RpcMemRegion
+        {
+            region_id: region_id,
+            mr: mr,
+        }
+    }
+
+    pub fn get_id(&self) -> u32
+    {
+        self.region_id
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.mr.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8]
+    {
+        &self.mr[..]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8]
+    {
+        &mut self.mr[..]
+    }
+
+    // Key to hand to peers for them to access the region.
+    pub fn remote_key(&self) -> RpcRemoteKey
+    {
+        RpcRemoteKey
+        {
+            region_id: self.region_id,
+            addr: self.mr.as_ptr() as u64,
+            len: self.mr.len() as u64,
+            rkey: self.mr.rkey(),
+        }
+    }
+
+    // Fails if `len` bytes at `offset` do not lie within the region.
+    pub fn check_range(&self, offset: usize, len: usize) -> Result<(), RpcNetworkError>
+    {
+        match offset.checked_add(len)
+        {
+            Some(end) if end <= self.len() => Ok(()),
+            _ => Err(RpcNetworkError::OutOfRange(offset as u64, len)),
+        }
+    }
+
+    pub fn get_mr_mut(&mut self) -> &mut ibverbs::MemoryRegion<u8>
+    {
+        &mut self.mr
+    }
+}
+
+impl std::fmt::Debug for RpcMemRegion
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        f.debug_struct("RpcMemRegion")
+            .field("region_id", &self.region_id)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl Drop for RpcMemRegion
+{
+    fn drop(&mut self)
+    {
+        RpcNetworkCore::unregister_region(self.region_id);
+    }
+}
+
+// Outcome of a one-sided operation. The local region comes back
+// either way, as it belongs to the device while the operation runs,
+// unless the operation was dropped without being answered.
+pub type RpcRemoteOpResult = Result<RpcMemRegion, (Option<RpcMemRegion>, RpcNetworkError)>;
+
+// A one-sided operation posted and not completed yet.
+#[derive(Debug)]
+pub struct RpcRemoteOp
+{
+    pub session_id: u32,
+    pub region: RpcMemRegion,
+    pub done: tokio::sync::oneshot::Sender<RpcRemoteOpResult>,
+}
@@ -0,0 +1,106 @@
+use super::srpc_core_network::RpcNetworkCore;
+use crate::msg::srpc_frame::RpcFrameLayout;
+
+// Registered send memory region leased to the application, which
+// writes its payload in place instead of handing over a copy.
+// The region goes back to the pool when the buffer is dropped
+// without being sent.
+#[derive(Debug)]
+pub struct RpcSendBuf
+{
+    session_id: u32,
+    peer_id: u32,
+    class_id: u8,
+    mr_index: u32,
+    mr_size: u32,
+    layout: RpcFrameLayout,
+    sent: bool,
+}
+
+impl RpcSendBuf
+{
+    pub fn new(
+        session_id: u32, 
+        peer_id: u32, 
+        class_id: u8, 
+        mr_index: u32, 
+        mr_size: u32, 
+        layout: RpcFrameLayout
+    ) -> RpcSendBuf
+    {
+        RpcSendBuf
+        {
+            session_id: session_id,
+            peer_id: peer_id,
+            class_id: class_id,
+            mr_index: mr_index,
+            mr_size: mr_size,
+            layout: layout,
+            sent: false,
+        }
+    }
+
+    pub fn get_session_id(&self) -> u32
+    {
+        self.session_id
+    }
+
+    pub fn get_peer_id(&self) -> u32
+    {
+        self.peer_id
+    }
+
+    // Class and index of the memory region.
+    pub fn get_mr(&self) -> (u8, u32)
+    {
+        (self.class_id, self.mr_index)
+    }
+
+    // Bytes of payload the buffer is able to hold.
+    pub fn capacity(&self) -> usize
+    {
+        self.layout.max_body_len(self.mr_size) as usize
+    }
+
+    // How the frame is laid out in the memory region.
+    pub fn get_layout(&self) -> RpcFrameLayout
+    {
+        self.layout
+    }
+
+    // The payload area, where the frame layout of the session puts the body.
+    pub fn as_slice(&self) -> &[u8]
+    {
+        let range = self.layout.body_range(self.capacity());
+        &RpcNetworkCore::peek_send_mr(self.class_id, self.mr_index)[range]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8]
+    {
+        let range = self.layout.body_range(self.capacity());
+        &mut self.as_frame_mut()[range]
+    }
+
+    // The whole memory region, header included.
+    pub fn as_frame_mut(&mut self) -> &mut [u8]
+    {
+        unsafe { RpcNetworkCore::get_send_mr(self.class_id, self.mr_index) }
+    }
+
+    // The region has been posted and belongs to the network now.
+    pub fn mark_sent(&mut self)
+    {
+        self.sent = true;
+    }
+}
+
+impl Drop for RpcSendBuf
+{
+    fn drop(&mut self)
+    {
+        if !self.sent
+        {
+            RpcNetworkCore::release_send_mr(self.class_id, self.mr_index);
+        }
+    }
+}
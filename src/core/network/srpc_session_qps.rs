@@ -0,0 +1,164 @@
+// Queue pairs are told apart by the number their completions carry.
+pub trait RpcQpNum {
+    fn qp_num(&self) -> u32;
+}
+
+impl RpcQpNum for ibverbs::QueuePair {
+    fn qp_num(&self) -> u32 {
+        ibverbs::QueuePair::qp_num(self)
+    }
+}
+
+// A connected queue pair of a session on one rail.
+#[derive(Debug)]
+pub struct RpcRailQp<Q: RpcQpNum> {
+    pub rail: u8,
+    // device of the rail, 0 being the one holding the memory regions
+    pub device: u8,
+    // bytes a send may carry inline, as the device granted
+    pub max_inline: u32,
+    pub qp: Q,
+}
+
+// Connected queue pairs of a session, one per rail in rail order.
+// Sends are striped across the usable ones in turn; which are usable,
+// i.e. on a rail up and not failed, is up to the caller to tell.
+#[derive(Debug)]
+pub struct RpcSessionQps<Q: RpcQpNum> {
+    qps: std::vec::Vec<RpcRailQp<Q>>,
+    // position the next send starts looking from
+    next: usize,
+}
+
+impl<Q: RpcQpNum> RpcSessionQps<Q> {
+    // Sessions start on different rails, `first` being taken
+    // modulo the number of queue pairs.
+    pub fn new(qps: std::vec::Vec<RpcRailQp<Q>>, first: usize) -> RpcSessionQps<Q> {
+        let next = if qps.is_empty() { 0 } else { first % qps.len() };
+        RpcSessionQps {
+            qps: qps,
+            next: next,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.qps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.qps.is_empty()
+    }
+
+    // The usable queue pair whose turn it is, the one after it
+    // gets the next turn.
+    pub fn next_usable<F>(&mut self, usable: F) -> Option<&mut RpcRailQp<Q>>
+    where F: Fn(&RpcRailQp<Q>) -> bool
+    {
+        let len = self.qps.len();
+        let pos = (0..len)
+            .map(|i| (self.next + i) % len)
+            .find(|pos| usable(&self.qps[*pos]))?;
+        self.next = (pos + 1) % len;
+        self.qps.get_mut(pos)
+    }
+
+    pub fn any_usable<F>(&self, usable: F) -> bool
+    where F: Fn(&RpcRailQp<Q>) -> bool
+    {
+        self.qps.iter().any(usable)
+    }
+
+    // Bytes a send may carry inline on any of the queue pairs, so that
+    // it can be posted again on another one.
+    pub fn max_inline(&self) -> u32 {
+        self.qps.iter()
+            .map(|rail_qp| rail_qp.max_inline)
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, RpcRailQp<Q>> {
+        self.qps.iter()
+    }
+
+    pub fn get_by_qpn_mut(&mut self, qp_num: u32) -> Option<&mut RpcRailQp<Q>> {
+        self.qps.iter_mut()
+            .find(|rail_qp| rail_qp.qp.qp_num() == qp_num)
+    }
+
+    pub fn into_qps(self) -> std::vec::Vec<Q> {
+        self.qps.into_iter()
+            .map(|rail_qp| rail_qp.qp)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct FakeQp(u32);
+
+    impl RpcQpNum for FakeQp {
+        fn qp_num(&self) -> u32 {
+            self.0
+        }
+    }
+
+    fn session_qps(rails: u8, first: usize) -> RpcSessionQps<FakeQp> {
+        let qps = (0..rails)
+            .map(|rail| RpcRailQp {
+                rail: rail,
+                device: rail % 2,
+                max_inline: 64 * (rail as u32 + 1),
+                qp: FakeQp(100 + rail as u32),
+            })
+            .collect();
+        RpcSessionQps::new(qps, first)
+    }
+
+    fn next_rail(qps: &mut RpcSessionQps<FakeQp>, failed: &[u8]) -> Option<u8> {
+        qps.next_usable(|rail_qp| !failed.contains(&rail_qp.rail))
+            .map(|rail_qp| rail_qp.rail)
+    }
+
+    #[test]
+    fn sends_take_rails_in_turn() {
+        let mut qps = session_qps(3, 4);
+        let rails: Vec<u8> = (0..6)
+            .filter_map(|_| next_rail(&mut qps, &[]))
+            .collect();
+        assert_eq!(rails, vec![1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn unusable_rails_are_skipped() {
+        let mut qps = session_qps(3, 0);
+        let rails: Vec<u8> = (0..4)
+            .filter_map(|_| next_rail(&mut qps, &[1]))
+            .collect();
+        assert_eq!(rails, vec![0, 2, 0, 2]);
+
+        assert_eq!(next_rail(&mut qps, &[0, 1, 2]), None);
+        assert!(!qps.any_usable(|rail_qp| rail_qp.rail > 2));
+        assert!(qps.any_usable(|rail_qp| rail_qp.device == 1));
+        assert_eq!(qps.max_inline(), 64);
+    }
+
+    #[test]
+    fn queue_pairs_are_found_by_number() {
+        let mut qps = session_qps(2, 0);
+        assert_eq!(qps.get_by_qpn_mut(101).map(|rail_qp| rail_qp.rail), Some(1));
+        assert!(qps.get_by_qpn_mut(7).is_none());
+        assert_eq!(qps.into_qps(), vec![FakeQp(100), FakeQp(101)]);
+    }
+
+    #[test]
+    fn no_queue_pair_has_no_turn() {
+        let mut qps = session_qps(0, 3);
+        assert!(qps.is_empty());
+        assert_eq!(qps.max_inline(), 0);
+        assert_eq!(next_rail(&mut qps, &[]), None);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::core::network::srpc_core_network::RpcNetworkCore;
+use crate::core::network::srpc_remote_mr::RpcRemoteKey;
+use crate::msg::srpc_atomic::RpcAtomicReq;
+
+// Who performs remote atomic operations.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RpcAtomicMode {
+    // The adapter of the peer, without involving its CPU.
+    Rdma,
+    // The dispatcher of the peer, on request frames. Slower, but runs
+    // on transports without atomics, and tests alike.
+    Emulated,
+}
+
+impl std::str::FromStr for RpcAtomicMode {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rdma" => Ok(RpcAtomicMode::Rdma),
+            "emulated" => Ok(RpcAtomicMode::Emulated),
+            _ => Err(format!("unknown atomic mode: {}", s)),
+        }
+    }
+}
+
+// Where the regions targeted by atomic requests of peers live,
+// the dispatcher performing the requests on them.
+pub trait RpcAtomicBackend: Send + Sync {
+    // Performs the request on a local region, returns the previous
+    // value of the word.
+    fn execute(&self, req: &RpcAtomicReq) -> Result<u64, std::string::String>;
+}
+
+// Regions registered with the device, see RpcNetworkCore::register_region.
+#[derive(Debug, Default)]
+pub struct RpcVerbsAtomics {}
+
+impl RpcAtomicBackend for RpcVerbsAtomics {
+    fn execute(&self, req: &RpcAtomicReq) -> Result<u64, std::string::String> {
+        // the region stays registered while its key is looked at
+        RpcNetworkCore::with_region_key(req.region_id, |key| execute_atomic(req, key))
+            .unwrap_or_else(|| Err(format!("no region {}", req.region_id)))
+    }
+}
+
+// Regions held in process memory, for atomic requests to be served
+// without a device, e.g. by tests.
+#[derive(Debug, Default)]
+pub struct RpcLocalAtomics {
+    regions: std::sync::RwLock<
+        std::collections::BTreeMap<u32, (RpcRemoteKey, std::boxed::Box<[std::sync::atomic::AtomicU64]>)>
+        >,
+    region_cnt: std::sync::atomic::AtomicU32,
+}
+
+impl RpcLocalAtomics {
+    pub fn new() -> RpcLocalAtomics {
+        Self::default()
+    }
+
+    // Allocates a region of `words` zeroed words, returns its key.
+    pub fn register(&self, words: usize) -> RpcRemoteKey {
+        let words: std::boxed::Box<[std::sync::atomic::AtomicU64]> = (0..words)
+            .map(|_| std::sync::atomic::AtomicU64::new(0))
+            .collect();
+        let region_id = self.region_cnt.fetch_add(
+            1,
+            std::sync::atomic::Ordering::SeqCst
+        ) + 1;
+        let addr = words.as_ptr() as u64;
+        let key = RpcRemoteKey {
+            region_id: region_id,
+            addr: addr,
+            len: (words.len() * 8) as u64,
+            // not a protection in process memory, only told apart
+            rkey: (addr >> 3) as u32 ^ region_id,
+        };
+        self.regions.write().unwrap().insert(region_id, (key.clone(), words));
+        key
+    }
+
+    pub fn unregister(&self, region_id: u32) {
+        self.regions.write().unwrap().remove(&region_id);
+    }
+}
+
+impl RpcAtomicBackend for RpcLocalAtomics {
+    fn execute(&self, req: &RpcAtomicReq) -> Result<u64, std::string::String> {
+        let regions = self.regions.read().unwrap();
+        match regions.get(&req.region_id) {
+            Some((key, _)) => execute_atomic(req, key),
+            None => Err(format!("no region {}", req.region_id)),
+        }
+    }
+}
+
+// Performs an atomic request of the peer on the region of `key`, after
+// checking it targets an aligned word of it and the peer holds the key.
+// The region must stay alive during the call.
+fn execute_atomic(req: &RpcAtomicReq, key: &RpcRemoteKey) -> Result<u64, std::string::String> {
+    if key.rkey != req.rkey {
+        return Err(format!("wrong key for region {}", req.region_id));
+    }
+    let offset = req.addr.wrapping_sub(key.addr);
+    if req.addr < key.addr || key.addr_of(offset, 8).is_err() {
+        return Err(format!("address {:#x} out of region {}", req.addr, req.region_id));
+    }
+    if req.addr % 8 != 0 {
+        return Err(format!("address {:#x} is not aligned", req.addr));
+    }
+
+    let word = unsafe { &*(req.addr as *const std::sync::atomic::AtomicU64) };
+    Ok(req.op.apply(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg::srpc_atomic::RpcAtomicOp;
+
+    fn req_of(key: &RpcRemoteKey, offset: u64, op: RpcAtomicOp) -> RpcAtomicReq {
+        RpcAtomicReq {
+            op: op,
+            region_id: key.region_id,
+            rkey: key.rkey,
+            addr: key.addr + offset,
+        }
+    }
+
+    #[test]
+    fn fetch_add_yields_the_previous_value() {
+        let atomics = RpcLocalAtomics::new();
+        let key = atomics.register(2);
+        let req = req_of(&key, 8, RpcAtomicOp::FetchAdd(5));
+        assert_eq!(atomics.execute(&req), Ok(0));
+        assert_eq!(atomics.execute(&req), Ok(5));
+        // the other word is left alone
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::FetchAdd(0))), Ok(0));
+    }
+
+    #[test]
+    fn compare_swap_stores_on_match_only() {
+        let atomics = RpcLocalAtomics::new();
+        let key = atomics.register(1);
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::CompareSwap(1, 7))), Ok(0));
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::CompareSwap(0, 7))), Ok(0));
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::CompareSwap(0, 9))), Ok(7));
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::FetchAdd(0))), Ok(7));
+    }
+
+    #[test]
+    fn bad_requests_are_refused() {
+        let atomics = RpcLocalAtomics::new();
+        let key = atomics.register(1);
+
+        let mut req = req_of(&key, 0, RpcAtomicOp::FetchAdd(1));
+        req.rkey ^= 1;
+        assert!(atomics.execute(&req).is_err());
+        assert!(atomics.execute(&req_of(&key, 8, RpcAtomicOp::FetchAdd(1))).is_err());
+        assert!(atomics.execute(&req_of(&key, 4, RpcAtomicOp::FetchAdd(1))).is_err());
+        let mut req = req_of(&key, 0, RpcAtomicOp::FetchAdd(1));
+        req.addr = key.addr - 8;
+        assert!(atomics.execute(&req).is_err());
+
+        atomics.unregister(key.region_id);
+        assert!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::FetchAdd(1))).is_err());
+    }
+
+    #[test]
+    fn concurrent_adds_all_land() {
+        let atomics = std::sync::Arc::new(RpcLocalAtomics::new());
+        let key = atomics.register(1);
+        let handles: Vec<_> = (0..4).map(|_| {
+            let atomics = atomics.clone();
+            let req = req_of(&key, 0, RpcAtomicOp::FetchAdd(1));
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    atomics.execute(&req).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(atomics.execute(&req_of(&key, 0, RpcAtomicOp::FetchAdd(0))), Ok(400));
+    }
+}
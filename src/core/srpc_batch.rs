@@ -0,0 +1,113 @@
+use crate::msg::srpc_frame::{RpcFrameHeader, SRPC_FRAME_HEADER_LEN};
+
+// Frames to the same session waiting to be coalesced into one batch
+// frame, whose body is the frames laid out back to back, each with
+// its own header.
+#[derive(Debug)]
+pub struct RpcFrameBatch
+{
+    buf: std::vec::Vec<u8>,
+    count: usize,
+    // When the oldest frame was added.
+    since: Option<std::time::Instant>,
+    // The batch failed to leave for lack of room on the session,
+    // it waits for room to be made instead of its time to flush.
+    stalled: bool,
+}
+
+impl RpcFrameBatch
+{
+    pub fn new() -> RpcFrameBatch
+    {
+        RpcFrameBatch
+        {
+            buf: std::vec::Vec::new(),
+            count: 0,
+            since: None,
+            stalled: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.count == 0
+    }
+
+    pub fn count(&self) -> usize
+    {
+        self.count
+    }
+
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.buf[..]
+    }
+
+    // Whether a frame of `frame_len` bytes still fits, the batch body
+    // being limited to `max_len` bytes and `max_count` frames.
+    pub fn fits(&self, frame_len: usize, max_len: usize, max_count: usize) -> bool
+    {
+        self.count < max_count && self.buf.len() + frame_len <= max_len
+    }
+
+    pub fn push(&mut self, header: &RpcFrameHeader, body: &[u8])
+    {
+        let start = self.buf.len();
+        self.buf.resize(start + header.frame_len(), 0);
+        header.write_to(&mut self.buf[start..]);
+        self.buf[start + SRPC_FRAME_HEADER_LEN as usize..].copy_from_slice(body);
+
+        if self.count == 0
+        {
+            self.since = Some(std::time::Instant::now());
+        }
+        self.count += 1;
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.buf.clear();
+        self.count = 0;
+        self.since = None;
+        self.stalled = false;
+    }
+
+    pub fn set_stalled(&mut self, stalled: bool)
+    {
+        self.stalled = stalled;
+    }
+
+    pub fn is_stalled(&self) -> bool
+    {
+        self.stalled
+    }
+
+    // Time left before the batch has waited `latency` for more frames,
+    // zero once it is due to be sent. None when there is nothing to
+    // wait for: the batch is empty or stalled.
+    pub fn time_to_flush(&self, latency: std::time::Duration) -> Option<std::time::Duration>
+    {
+        if self.stalled
+        {
+            return None;
+        }
+        let since = self.since?;
+        Some(latency.saturating_sub(since.elapsed()))
+    }
+
+    // Splits the body of a batch frame into its frames.
+    pub fn split(body: &[u8]) -> Result<std::vec::Vec<(RpcFrameHeader, &[u8])>, std::string::String>
+    {
+        let mut frames = std::vec::Vec::new();
+        let mut rest = body;
+        while !rest.is_empty()
+        {
+            let header = RpcFrameHeader::read_from(rest)?;
+            let frame_len = header.frame_len();
+            let frame_body = header.body_of(rest);
+            frames.push((header, frame_body));
+            rest = &rest[frame_len..];
+        }
+        Ok(frames)
+    }
+}
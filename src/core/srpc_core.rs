@@ -1,4 +1,5 @@
 use crate::msg::srpc_msg::{RpcMsgHandle};
+use crate::msg::srpc_frame::RpcRecvView;
 
 use std::boxed::Box;
 // type CallBackBox = Box<dyn Fn(RpcMsgHandle)>;
@@ -12,6 +13,29 @@ impl std::fmt::Debug for dyn CallBack {
 }
 pub type CallBackBox = Box<dyn CallBack>;
 
+// Handles a raw frame in place, while its memory region is lent 
+// to it, right from the completion polling context. 
+pub trait RawCallBack: Fn(&RpcRecvView) { }
+impl<F> RawCallBack for F where F: Fn(&RpcRecvView) { }
+impl std::fmt::Debug for dyn RawCallBack {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "dyn RawCallBack")
+    }
+}
+pub type RawCallBackBox = Box<dyn RawCallBack>;
+
+// Inspects the identity and metadata of an incoming peer 
+// together with its address, and rejects it by returning the reason. 
+pub trait AcceptHook: Fn(&RpcPeerMeta, &str) -> Result<(), std::string::String> { }
+impl<F> AcceptHook for F 
+    where F: Fn(&RpcPeerMeta, &str) -> Result<(), std::string::String> { }
+impl std::fmt::Debug for dyn AcceptHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "dyn AcceptHook")
+    }
+}
+pub type AcceptHookBox = Box<dyn AcceptHook>;
+
 use once_cell::sync::OnceCell;
 use tracing::{info, trace};
 pub static RPC_CORE: once_cell::sync::OnceCell<
@@ -22,6 +46,7 @@ pub static RPC_DISPATCHER: once_cell::sync::OnceCell<
     > = OnceCell::new();
 
 use super::{srpc_dispatcher::RpcDispatcher};
+use super::network::srpc_peer_meta::RpcPeerMeta;
 
 #[derive(PartialEq, Debug)]
 pub enum RpcCoreStatus {
@@ -48,6 +73,10 @@ pub struct RpcCore {
     cb_map: std::collections::BTreeMap<
         u8, CallBackBox
         >, // req_type -> cb_box  
+    raw_cb_map: std::collections::BTreeMap<
+        u8, RawCallBackBox
+        >, // req_type -> raw_cb_box 
+    accept_hook: Option<AcceptHookBox>, 
 
     // runtime section 
     req_counter: std::sync::atomic::AtomicU64, 
@@ -76,6 +105,8 @@ impl<'cb> RpcCore {
             // sessions: std::Vec::new(),
             nn_id: None,
             cb_map: std::collections::BTreeMap::new(),
+            raw_cb_map: std::collections::BTreeMap::new(),
+            accept_hook: None,
             req_counter: 0.into(),
             runtime_lock: std::sync::Mutex::new(()),
             dispatcher: dispatcher.clone(),
@@ -167,6 +198,71 @@ impl<'cb> RpcCore {
         Ok(())
     }
 
+    // Registers a callback lent the raw frames of the request type. 
+    pub fn reg_raw_cb(
+        &self, 
+        cb_index: u8, 
+        cb: RawCallBackBox
+    ) -> Result<(), std::string::String> {
+        // Check the status.
+        if self.status != RpcCoreStatus::Stopped {
+            return Err("The RPC core is not stopped.".to_string());
+        }
+
+        // Check the callback name.
+        if cb_index == 0 {
+            return Err("The callback index is empty.".to_string());
+        }
+
+        // Register the callback function.
+        {
+            let _unrefed_lock = 
+                self.runtime_lock.lock().unwrap();
+            unsafe { 
+                let raw_cb_map_mut = 
+                    get_mut_from_immut(&self.raw_cb_map);
+                raw_cb_map_mut.insert(cb_index, cb); 
+            }
+        }
+
+        Ok(())
+    }
+
+    // Registers the hook deciding whether incoming peers are accepted. 
+    pub fn reg_accept_hook(
+        &self, 
+        hook: AcceptHookBox
+    ) -> Result<(), std::string::String> {
+        // Check the status.
+        if self.status != RpcCoreStatus::Stopped {
+            return Err("The RPC core is not stopped.".to_string());
+        }
+
+        {
+            let _unrefed_lock = 
+                self.runtime_lock.lock().unwrap();
+            unsafe { 
+                let core_mut = get_mut_from_immut(self);
+                core_mut.accept_hook = Some(hook); 
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the accept hook against an incoming peer, 
+    // peers are accepted when no hook is registered. 
+    pub fn check_accept(
+        &self, 
+        peer_meta: &RpcPeerMeta, 
+        peer_uri: &str
+    ) -> Result<(), std::string::String> {
+        match self.accept_hook {
+            Some(ref hook) => (*hook)(peer_meta, peer_uri),
+            None => Ok(()),
+        }
+    }
+
     pub fn get_req_index(&self) -> u64 {
         self.req_counter.fetch_add(
             1, 
@@ -183,6 +279,13 @@ impl<'cb> RpcCore {
         self.cb_map.get(&req_type)
     }
 
+    pub fn get_raw_cb_by_reqtype(
+        &self, 
+        req_type: u8
+    ) -> Option<&RawCallBackBox> {
+        self.raw_cb_map.get(&req_type)
+    }
+
 }
 
 pub unsafe fn get_mut_from_immut<T>(immut: &T) -> &mut T {
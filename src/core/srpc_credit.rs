@@ -0,0 +1,254 @@
+// Request type of protocol version 1 messages only carrying
+// credits back to the peer, such messages reach no callback.
+pub const SRPC_REQ_TYPE_CREDIT: u8 = 0;
+
+// Receive buffers kept out of the credits granted to the peer,
+// credit update frames land in them.
+pub const SRPC_CREDIT_RESERVE: u32 = 1;
+
+// Receive buffers kept out of the credits for the other control
+// frames, release frames handing the landing region back, of which
+// at most one is outstanding on a session.
+pub const SRPC_CONTROL_RESERVE: u32 = 1;
+
+// All the receive buffers kept out of the credits.
+pub const SRPC_RECV_RESERVE: u32 = SRPC_CREDIT_RESERVE + SRPC_CONTROL_RESERVE;
+
+// Receiver-granted flow control of one session, independent of
+// the transport carrying the frames.
+// The peer grants one credit per receive buffer it has posted;
+// each data frame sent takes one, and the receiver gives them back
+// as it consumes frames, either piggybacked on its own frames or in
+// a credit update frame once enough of them are owed.
+#[derive(Debug)]
+pub struct RpcCredit {
+    // Whether the peer grants credits at all.
+    limited: bool,
+    // Credits granted by the peer and not used yet.
+    available: std::sync::atomic::AtomicU32,
+    // Frames received from the peer whose credits are not returned yet.
+    owed: std::sync::atomic::AtomicU32,
+    // Credits owed before an explicit update is due.
+    update_threshold: u32,
+}
+
+impl RpcCredit {
+    // Flow control for a peer granting `initial` credits up front,
+    // 0 if the peer does not grant credits.
+    pub fn new(initial: u32, update_threshold: u32) -> RpcCredit {
+        RpcCredit {
+            limited: initial != 0,
+            available: std::sync::atomic::AtomicU32::new(initial),
+            owed: std::sync::atomic::AtomicU32::new(0),
+            update_threshold: std::cmp::max(update_threshold, 1),
+        }
+    }
+
+    pub fn unlimited() -> RpcCredit {
+        Self::new(0, 0)
+    }
+
+    // Credits to grant a peer when `recv_depth` receive buffers
+    // are posted for the session.
+    pub fn initial_credits(recv_depth: u32) -> u32 {
+        std::cmp::max(recv_depth.saturating_sub(SRPC_RECV_RESERVE), 1)
+    }
+
+    // Credits to grant each of at most `sessions` peers sharing
+    // `srq_depth` receive buffers, so that all of them together never
+    // grant more than the buffers posted, reserve included.
+    pub fn shared_credits(srq_depth: u32, sessions: u32) -> u32 {
+        Self::initial_credits(srq_depth / std::cmp::max(sessions, 1))
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.limited
+    }
+
+    pub fn get_available(&self) -> u32 {
+        self.available.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    pub fn get_owed(&self) -> u32 {
+        self.owed.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    // Takes one credit to send a data frame,
+    // fails when the peer has no vacant receive buffer left.
+    pub fn try_consume(&self) -> bool {
+        if !self.limited {
+            return true;
+        }
+
+        self.available.fetch_update(
+            std::sync::atomic::Ordering::AcqRel,
+            std::sync::atomic::Ordering::Acquire,
+            |n| n.checked_sub(1)
+        ).is_ok()
+    }
+
+    // Gives back a credit taken for a frame that was not sent.
+    pub fn refund(&self) {
+        if self.limited {
+            self.grant(1);
+        }
+    }
+
+    // Adds the credits returned by the peer.
+    pub fn grant(&self, credits: u32) {
+        if credits == 0 {
+            return;
+        }
+        let _ = self.available.fetch_update(
+            std::sync::atomic::Ordering::AcqRel,
+            std::sync::atomic::Ordering::Acquire,
+            |n| Some(n.saturating_add(credits))
+        );
+    }
+
+    // Records a data frame received from the peer,
+    // returns whether an explicit credit update is due.
+    pub fn on_recv(&self) -> bool {
+        if !self.limited {
+            return false;
+        }
+
+        let owed = self.owed.fetch_add(
+            1,
+            std::sync::atomic::Ordering::AcqRel
+        ) + 1;
+        owed >= self.update_threshold
+    }
+
+    // Takes the credits owed to the peer, to be carried by a frame.
+    pub fn take_owed(&self) -> u32 {
+        self.owed.swap(0, std::sync::atomic::Ordering::AcqRel)
+    }
+
+    // Puts back credits taken for a frame that was not sent.
+    pub fn restore_owed(&self, credits: u32) {
+        if credits != 0 {
+            self.owed.fetch_add(
+                credits,
+                std::sync::atomic::Ordering::AcqRel
+            );
+        }
+    }
+
+    // Whether enough credits are owed to warrant an update frame.
+    pub fn is_update_due(&self) -> bool {
+        self.limited && self.get_owed() >= self.update_threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_credits_keep_the_reserve() {
+        assert_eq!(RpcCredit::initial_credits(32), 32 - SRPC_RECV_RESERVE);
+        assert_eq!(RpcCredit::initial_credits(2), 1);
+        assert_eq!(RpcCredit::initial_credits(1), 1);
+        assert_eq!(RpcCredit::initial_credits(0), 1);
+    }
+
+    #[test]
+    fn shared_credits_stay_within_the_shared_queue() {
+        let sessions = 16;
+        let credits = RpcCredit::shared_credits(1024, sessions);
+        assert_eq!(credits, 64 - SRPC_RECV_RESERVE);
+        assert!((credits + SRPC_RECV_RESERVE) * sessions <= 1024);
+        assert_eq!(RpcCredit::shared_credits(1024, 0),
+            RpcCredit::initial_credits(1024));
+    }
+
+    #[test]
+    fn consume_stops_at_zero() {
+        let credit = RpcCredit::new(2, 1);
+        assert!(credit.is_limited());
+        assert!(credit.try_consume());
+        assert!(credit.try_consume());
+        assert!(!credit.try_consume());
+        assert_eq!(credit.get_available(), 0);
+    }
+
+    #[test]
+    fn unlimited_never_runs_out() {
+        let credit = RpcCredit::unlimited();
+        assert!(!credit.is_limited());
+        for _ in 0..1000 {
+            assert!(credit.try_consume());
+        }
+        assert!(!credit.on_recv());
+        assert!(!credit.is_update_due());
+    }
+
+    #[test]
+    fn grant_and_refund_add_credits() {
+        let credit = RpcCredit::new(1, 1);
+        assert!(credit.try_consume());
+        credit.refund();
+        assert_eq!(credit.get_available(), 1);
+        credit.grant(3);
+        assert_eq!(credit.get_available(), 4);
+        credit.grant(0);
+        assert_eq!(credit.get_available(), 4);
+    }
+
+    #[test]
+    fn grant_saturates() {
+        let credit = RpcCredit::new(u32::MAX - 1, 1);
+        credit.grant(10);
+        assert_eq!(credit.get_available(), u32::MAX);
+    }
+
+    #[test]
+    fn update_is_due_at_the_threshold() {
+        let credit = RpcCredit::new(8, 3);
+        assert!(!credit.on_recv());
+        assert!(!credit.on_recv());
+        assert!(!credit.is_update_due());
+        assert!(credit.on_recv());
+        assert!(credit.is_update_due());
+
+        assert_eq!(credit.take_owed(), 3);
+        assert_eq!(credit.get_owed(), 0);
+        assert!(!credit.is_update_due());
+    }
+
+    #[test]
+    fn restore_owed_puts_credits_back() {
+        let credit = RpcCredit::new(8, 4);
+        credit.on_recv();
+        credit.on_recv();
+        let owed = credit.take_owed();
+        credit.restore_owed(owed);
+        assert_eq!(credit.get_owed(), 2);
+        credit.restore_owed(0);
+        assert_eq!(credit.get_owed(), 2);
+    }
+
+    #[test]
+    fn threshold_is_at_least_one() {
+        let credit = RpcCredit::new(4, 0);
+        assert!(credit.on_recv());
+    }
+
+    #[test]
+    fn concurrent_consumers_never_overdraw() {
+        let credit = std::sync::Arc::new(RpcCredit::new(100, 1));
+        let handles: Vec<_> = (0..4).map(|_| {
+            let credit = credit.clone();
+            std::thread::spawn(move || {
+                (0..50).filter(|_| credit.try_consume()).count()
+            })
+        }).collect();
+
+        let consumed: usize = handles.into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(consumed, 100);
+        assert_eq!(credit.get_available(), 0);
+    }
+}
@@ -2,13 +2,24 @@ use std::collections::VecDeque;
 use serde::{Serialize, Deserialize};
 
 #[allow(unused_imports)]
-use tracing::{info, trace};
+use tracing::{error, info, trace, warn};
 
 use crate::conf::conf::RPC_CONF;
 use crate::msg::srpc_msg::{RpcMsgHandle, RpcOnceMsg};
 use crate::core::srpc_core::RPC_CORE;
 use crate::core::network::srpc_core_network::RpcNetworkCore; 
-use crate::core::srpc_session::RpcSession;
+use crate::core::network::srpc_net_error::{RpcNetworkError, RpcWcError};
+use crate::core::network::srpc_peer_meta::RpcPeerMeta;
+use crate::core::network::srpc_send_buf::RpcSendBuf;
+use crate::core::network::srpc_remote_mr::{RpcMemRegion, RpcRemoteKey, RpcRemoteOpResult};
+use crate::msg::srpc_frame::{RpcFrameKind, RpcFrameHeader, RpcFrameLayout, RpcFragHeader, RpcRecvView, SRPC_FRAME_HEADER_LEN};
+use crate::core::srpc_session::{RpcSession, RpcSessionSlot, RpcSessionStatus};
+use crate::core::srpc_session_pool::RpcSessionPool;
+use crate::core::srpc_credit::{RpcCredit, SRPC_REQ_TYPE_CREDIT};
+use crate::core::srpc_batch::RpcFrameBatch;
+use crate::core::srpc_frag::{RpcFragIn, RpcFragOut, RpcLargeMsgMode};
+use crate::core::srpc_atomic::{RpcAtomicBackend, RpcAtomicMode, RpcVerbsAtomics};
+use crate::msg::srpc_atomic::{RpcAtomicOp, RpcAtomicReq, RpcAtomicResp, SRPC_ATOMIC_REQ_LEN, SRPC_ATOMIC_RESP_LEN};
 
 use super::srpc_core::RPC_DISPATCHER;
 use super::srpc_session::SESSION_COUNTER; 
@@ -33,9 +44,38 @@ pub struct RpcDispatcher
         std::collections::BTreeMap<u32, RpcSession>
         >>,
     peer_map: std::sync::Arc<std::sync::RwLock<
-        std::collections::BTreeMap<u32, u32>
-        >>, // peer_id -> session_id 
+        std::collections::BTreeMap<u32, RpcSessionPool>
+        >>, // peer_id -> session_ids 
     session_counter: std::sync::atomic::AtomicU32,
+    session_slots: std::sync::Arc<std::sync::atomic::AtomicU32>, // established or establishing 
+    credit_due: std::sync::Arc<std::sync::RwLock<
+        std::collections::BTreeSet<u32>
+        >>, // sessions owing the peer a credit update 
+    work_notify: tokio::sync::Notify, // woken up when there is work to do 
+    batch_map: std::sync::Arc<std::sync::RwLock<
+        std::collections::BTreeMap<u32, RpcFrameBatch>
+        >>, // session_id -> frames waiting to be coalesced 
+    frag_out: std::sync::Arc<std::sync::RwLock<
+        std::collections::BTreeMap<u32, RpcFragOut>
+        >>, // peer_id -> message being sent in fragments 
+    frag_in: std::sync::Arc<std::sync::RwLock<
+        std::collections::BTreeMap<(u32, u32), RpcFragIn>
+        >>, // (session_id, xfer_id) -> message being reassembled 
+    release_due: std::sync::Arc<std::sync::RwLock<
+        std::collections::BTreeSet<u32>
+        >>, // sessions owing the peer its landing region 
+    atomic_cnt: std::sync::atomic::AtomicU64, // ids of emulated atomic requests 
+    atomic_pending: std::sync::Arc<std::sync::Mutex<
+        std::collections::BTreeMap<u64, (u32, tokio::sync::oneshot::Sender<
+            Result<u64, RpcNetworkError>
+            >)>
+        >>, // id -> (session_id, caller waiting for the outcome) 
+    atomic_resp_due: std::sync::Arc<std::sync::RwLock<
+        VecDeque<(u32, u64, RpcAtomicResp)>
+        >>, // (session_id, id, outcome) of atomic requests of peers 
+    atomic_backend: std::sync::RwLock<
+        std::sync::Arc<dyn RpcAtomicBackend>
+        >, // regions the atomic requests of peers are performed on 
 }
 
 unsafe impl Send for RpcDispatcher {}
@@ -64,6 +104,33 @@ impl RpcDispatcher
                 std::collections::BTreeMap::new()
             )),
             session_counter: std::sync::atomic::AtomicU32::new(0),
+            session_slots: std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0)),
+            credit_due: std::sync::Arc::new(std::sync::RwLock::new(
+                std::collections::BTreeSet::new()
+            )),
+            work_notify: tokio::sync::Notify::new(),
+            batch_map: std::sync::Arc::new(std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )),
+            frag_out: std::sync::Arc::new(std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )),
+            frag_in: std::sync::Arc::new(std::sync::RwLock::new(
+                std::collections::BTreeMap::new()
+            )),
+            release_due: std::sync::Arc::new(std::sync::RwLock::new(
+                std::collections::BTreeSet::new()
+            )),
+            atomic_cnt: std::sync::atomic::AtomicU64::new(1),
+            atomic_pending: std::sync::Arc::new(std::sync::Mutex::new(
+                std::collections::BTreeMap::new()
+            )),
+            atomic_resp_due: std::sync::Arc::new(std::sync::RwLock::new(
+                VecDeque::new()
+            )),
+            atomic_backend: std::sync::RwLock::new(
+                std::sync::Arc::new(RpcVerbsAtomics::default())
+            ),
             peer_map: std::sync::Arc::new(std::sync::RwLock::new(
                 std::collections::BTreeMap::new()
             )),
@@ -85,46 +152,648 @@ impl RpcDispatcher
         self.network = network;
     }
 
+    // Connects a session using the largest memory regions. 
     pub async fn connect_to(&self, peer_id: u32, peer_uri: &str) -> Result<u32, Box<dyn std::error::Error>>
     {
+        let mr_size = RpcNetworkCore::get_mr_sizes()
+            .last().cloned().unwrap();
+        self.connect_class_to(peer_id, peer_uri, mr_size).await
+    }
+
+    // Connects a session whose memory regions are of `mr_size` bytes, 
+    // i.e. able to carry frames up to that size. 
+    pub async fn connect_class_to(&self, peer_id: u32, peer_uri: &str, mr_size: u32) -> Result<u32, Box<dyn std::error::Error>>
+    {
+        // given back if connecting fails 
+        let slot = self.reserve_session()?;
         let session_id = RpcSession::get_session_id();
 
-        let _ = self.network.connect_to(session_id, peer_uri).await;
-        let session = RpcSession::new(
+        let peer_meta = self.network.connect_to(session_id, peer_uri, mr_size).await?;
+        // frames of the session are told apart by the node it claims 
+        if peer_meta.node_id != peer_id {
+            RpcNetworkCore::drop_session(session_id);
+            return Err(format!("peer {} at {} identifies itself as node {}", 
+                peer_id, peer_uri, peer_meta.node_id).into());
+        }
+        self.insert_session(session_id, peer_id, peer_meta, peer_uri, slot);
+
+        trace!("connected to peer {}:{} with session {} of mr_size {}", 
+            peer_id, peer_uri, session_id, mr_size);
+
+        Ok(session_id)
+    }
+
+    // Connects the configured number of queue pairs to the peer 
+    // for every memory region size class, traffic to the peer is 
+    // then spread across them. 
+    pub async fn connect_pool_to(&self, peer_id: u32, peer_uri: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>>
+    {
+        let conf = RPC_CONF.get().unwrap();
+
+        let mut session_ids = Vec::new();
+        for mr_size in RpcNetworkCore::get_mr_sizes() {
+            for _ in 0..std::cmp::max(conf.qp_per_peer, 1) {
+                let session_id = 
+                    self.connect_class_to(peer_id, peer_uri, mr_size).await?;
+                session_ids.push(session_id);
+            }
+        }
+
+        Ok(session_ids)
+    }
+
+    // Called by the network once a send of the session has completed. 
+    pub fn on_send_complete(&self, session_id: u32)
+    {
+        if let Some(session) = self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            session.dec_inflight();
+        }
+        // deferred sends may go now 
+        self.work_notify.notify_one();
+    }
+
+    // A frame posted on the session was lost, its queue pair failed with 
+    // no other to post it again on. What it took is given back as for 
+    // a frame that did not leave: the credit of the peer, if it took 
+    // one, and the credits it was returning. 
+    pub fn on_send_dropped(&self, session_id: u32, credited: bool, returned: u32)
+    {
+        if let Some(session) = self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            session.dec_inflight();
+            let credit = session.get_credit();
+            credit.restore_owed(returned);
+            if credited {
+                credit.refund();
+            }
+        }
+        self.work_notify.notify_one();
+    }
+
+    // A frame written into the landing region of the peer was lost. 
+    // The peer never releases the region for it, so it is free again, 
+    // and what the frame took is given back as for any dropped frame. 
+    pub fn on_landing_dropped(&self, session_id: u32, credited: bool, returned: u32)
+    {
+        if let Some(session) = self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            session.release_landing();
+        }
+        self.on_send_dropped(session_id, credited, returned);
+    }
+
+    // Moves the session to the failed state after a work request failed 
+    // on it. Later messages to the peer take its other sessions, what 
+    // was bound to the session is dropped and the callers waiting on it 
+    // get the error. Only the first failure of a session counts. 
+    pub fn on_session_failed(&self, session_id: u32, error: RpcWcError)
+    {
+        // a flushed request only follows the one that failed the session 
+        let error = RpcNetworkCore::get_session_error(session_id).unwrap_or(error);
+        let peer_id = {
+            let mut session_map = self.session_map.write().unwrap();
+            let session = match session_map.get_mut(&session_id) {
+                Some(session) => session,
+                None => return,
+            };
+            if let RpcSessionStatus::Failed(_) = session.get_status() {
+                return;
+            }
+            // the session keeps its slot until torn down, what it 
+            // holds counts towards the limit until then; it is torn 
+            // down, its queue pairs, receives and work requests given 
+            // back, once the flushed sends are handled, see 
+            // check_failed_sessions 
+            session.set_status(RpcSessionStatus::Failed(error));
+            session.get_peer_id()
+        };
+        error!("session {} to peer {} failed: {}", session_id, peer_id, error);
+
+        self.drop_session_state(session_id, peer_id, RpcNetworkError::SessionLost(session_id, error));
+
+        // queued messages to the peer go to its other sessions, if any 
+        self.work_notify.notify_one();
+    }
+
+    // Closes the session: the peer gets no more frames on it and, once 
+    // its sends in flight completed, its queue pairs and buffers are 
+    // given back, so is its slot. 
+    pub async fn disconnect(&self, session_id: u32) -> Result<(), RpcNetworkError>
+    {
+        let peer_id = {
+            let mut session_map = self.session_map.write().unwrap();
+            let session = session_map.get_mut(&session_id)
+                .ok_or(RpcNetworkError::NoSession(session_id))?;
+            if let RpcSessionStatus::Connected | RpcSessionStatus::Running 
+                = session.get_status() 
+            {
+                session.set_status(RpcSessionStatus::Disconnected);
+            }
+            session.get_peer_id()
+        };
+        self.drop_session_state(session_id, peer_id, RpcNetworkError::NoSession(session_id));
+
+        // completions of sends in flight still refer to the session 
+        while self.with_session(session_id, |session| session.get_inflight())
+            .unwrap_or(0) > 0 
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        self.close_session(session_id);
+        trace!("disconnected session {} from peer {}", session_id, peer_id);
+
+        Ok(())
+    }
+
+    // Forgets the session and releases what the network holds for it, 
+    // dropping the session gives its slot back. 
+    fn close_session(&self, session_id: u32)
+    {
+        self.session_map.write().unwrap().remove(&session_id);
+        self.network.disconnect(session_id);
+    }
+
+    // Drops what was bound to a session leaving the peer, the callers 
+    // waiting on it get `error`. 
+    fn drop_session_state(&self, session_id: u32, peer_id: u32, error: RpcNetworkError)
+    {
+        if let Some(pool) = self.peer_map.write().unwrap().get_mut(&peer_id) {
+            pool.remove(session_id);
+        }
+
+        // frames not sent yet are lost with the session 
+        if let Some(batch) = self.batch_map.write().unwrap().remove(&session_id) {
+            if !batch.is_empty() {
+                warn!("session {}: dropped a batch of {} frames", 
+                    session_id, batch.count());
+            }
+        }
+        self.frag_out.write().unwrap()
+            .retain(|_, frag| frag.get_session_id() != session_id);
+        self.frag_in.write().unwrap()
+            .retain(|(frag_session_id, _), _| *frag_session_id != session_id);
+        self.credit_due.write().unwrap().remove(&session_id);
+        self.release_due.write().unwrap().remove(&session_id);
+        RpcNetworkCore::drop_landing(session_id);
+
+        let failed: Vec<_> = {
+            let mut pending = self.atomic_pending.lock().unwrap();
+            let ids: Vec<u64> = pending.iter()
+                .filter(|(_, (pending_session_id, _))| *pending_session_id == session_id)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter().filter_map(|id| pending.remove(id)).collect()
+        };
+        for (_, done) in failed {
+            let _ = done.send(Err(error.clone()));
+        }
+    }
+
+    // Reserves room for one more session within the configured limit. 
+    // The slot is given back when dropped, so that a session failing 
+    // to establish, early return or panic alike, does not keep it. 
+    pub fn reserve_session(&self) -> Result<RpcSessionSlot, std::string::String>
+    {
+        let max_sessions = RPC_CONF.get().unwrap().max_sessions;
+        RpcSessionSlot::reserve(&self.session_slots, max_sessions)
+    }
+
+    // Registers the session accepted by the precomm server, 
+    // so that the local end is able to respond and push to the caller. 
+    pub fn accept_session(
+        &self, 
+        session_id: u32, 
+        peer_meta: RpcPeerMeta, 
+        peer_uri: &str, 
+        slot: RpcSessionSlot
+    )
+    {
+        let peer_id = peer_meta.node_id;
+        self.insert_session(session_id, peer_id, peer_meta, peer_uri, slot);
+
+        trace!("accepted peer {}:{} with session {}", 
+            peer_id, peer_uri, session_id);
+    }
+
+    fn insert_session(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_meta: RpcPeerMeta, 
+        peer_uri: &str, 
+        slot: RpcSessionSlot
+    )
+    {
+        let mut session = RpcSession::new(
+            session_id, 
             peer_id, 
             peer_uri.to_string(), 
             RPC_DISPATCHER.get().unwrap().clone()
         );
+        let mr_size = peer_meta.mr_size;
+        if !peer_meta.ud_endpoint.is_empty() && RpcNetworkCore::is_ud_enabled() {
+            RpcNetworkCore::add_ud_peer(peer_id, &peer_meta.ud_endpoint);
+        }
+        // return credits once half of the granted ones are consumed 
+        let loc_credits = RpcNetworkCore::get_recv_credits(mr_size);
+        session.set_credit(RpcCredit::new(peer_meta.credits, loc_credits / 2));
+        session.set_peer_meta(peer_meta);
+        session.set_slot(slot);
+        session.set_status(RpcSessionStatus::Connected);
         self.session_map.write().unwrap()
             .insert(session_id, session);
         self.peer_map.write().unwrap()
-            .insert(peer_id, session_id); 
+            .entry(peer_id)
+            .or_insert_with(RpcSessionPool::new)
+            .insert(session_id, mr_size); 
+    }
 
-        trace!("connected to peer {}:{} with session {}", 
-            peer_id, peer_uri, session_id);
+    // Handles a frame received on the session. `frame` holds the bytes 
+    // received in the memory region, lent for the duration of the call only, 
+    // laid out as the protocol version of the session has it. 
+    pub fn on_recv_frame(&self, session_id: u32, frame: &[u8])
+    {  
+        let layout = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => session.get_frame_layout(),
+            None => RpcFrameLayout::Header,
+        };
+        let (header, body) = match layout.read(frame) {
+            Ok(frame) => frame,
+            Err(e) => {
+                error!("on_recv_frame: session {}: {}", session_id, e);
+                return;
+            }
+        };
 
-        // FIXME: return error on failure 
-        Ok(session_id)
+        if layout == RpcFrameLayout::Legacy {
+            self.on_recv_legacy(session_id, header, body);
+        } else {
+            self.on_recv_framed(session_id, &header, body);
+        }
     }
 
-    pub fn on_recv_msg(&self, session_id: u32, bin: &[u8])
-    {  
+    // Handles a version 1 frame, a serialized message carrying the 
+    // credits and the fields later versions put in the header. 
+    fn on_recv_legacy(&self, session_id: u32, mut header: RpcFrameHeader, body: &[u8])
+    {
+        let msg = flexbuffers::Reader::get_root(body)
+            .map_err(|e| e.to_string())
+            .and_then(|reader| RpcOnceMsg::deserialize(reader)
+                .map_err(|e| e.to_string()));
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("on_recv_legacy: session {}: {}", session_id, e);
+                return;
+            }
+        };
+        if msg.req_type == SRPC_REQ_TYPE_CREDIT {
+            header.kind = RpcFrameKind::Credit;
+        }
+        header.req_type = msg.req_type;
+        header.src_id = msg.src_id;
+        header.msg_id = msg.msg_id;
+        header.credit = msg.credit;
+        trace!("on_recv_legacy: session {}: {:?}", session_id, header);
+
+        let (peer_id, peer_uri) = self.on_recv_credit(session_id, &header)
+            .unwrap_or((0, "".to_string()));
+        if header.kind == RpcFrameKind::Msg {
+            self.on_recv_msg(session_id, peer_id, peer_uri, msg);
+        }
+    }
+
+    // Handles a frame whose header was read, version 2 on. 
+    fn on_recv_framed(&self, session_id: u32, header: &RpcFrameHeader, body: &[u8])
+    {
+        trace!("on_recv_framed: session {}: {:?}", session_id, header);
+        let (peer_id, peer_uri) = self.on_recv_credit(session_id, header)
+            .unwrap_or((0, "".to_string()));
+        let speaks = self.with_session(session_id, |session| session.speaks(header.kind))
+            .unwrap_or(false);
+        if !speaks {
+            error!("on_recv_framed: session {}: unexpected {:?} frame", 
+                session_id, header.kind);
+            return;
+        }
+
+        match header.kind
+        {
+            RpcFrameKind::Batch => 
+            {
+                let frames = match RpcFrameBatch::split(body) {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        error!("on_recv_framed: session {}: {}", session_id, e);
+                        return;
+                    }
+                };
+                for (header, body) in frames {
+                    if header.kind == RpcFrameKind::Batch {
+                        error!("on_recv_framed: session {}: nested batch", session_id);
+                        continue;
+                    }
+                    self.on_recv_body(session_id, peer_id, peer_uri.clone(), &header, body);
+                }
+            },
+            _ => 
+            {
+                self.on_recv_body(session_id, peer_id, peer_uri, header, body);
+            },
+        }
+    }
+
+    fn on_recv_body(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_uri: std::string::String, 
+        header: &RpcFrameHeader, 
+        body: &[u8]
+    )
+    {
+        match header.kind
+        {
+            RpcFrameKind::Credit | RpcFrameKind::Batch => {},
+            RpcFrameKind::Raw => 
+            {
+                self.on_recv_raw(session_id, peer_id, peer_uri, header, body);
+            },
+            RpcFrameKind::Msg => 
+            {
+                self.on_recv_msg_bin(session_id, peer_id, peer_uri, body);
+            },
+            RpcFrameKind::Frag => 
+            {
+                self.on_recv_frag(session_id, peer_id, peer_uri, header, body);
+            },
+            RpcFrameKind::Release => 
+            {
+                self.on_recv_release(session_id);
+            },
+            RpcFrameKind::Atomic => 
+            {
+                self.on_recv_atomic(session_id, header, body);
+            },
+            RpcFrameKind::AtomicResp => 
+            {
+                self.on_recv_atomic_resp(session_id, header, body);
+            },
+        }
+    }
+
+    // Serves the atomic requests of peers from `backend` instead of 
+    // the regions registered with the device. 
+    pub fn set_atomic_backend(&self, backend: std::sync::Arc<dyn RpcAtomicBackend>)
+    {
+        *self.atomic_backend.write().unwrap() = backend;
+    }
+
+    // Performs an atomic request of the peer, the outcome is sent 
+    // back by the dispatcher loop. 
+    fn on_recv_atomic(&self, session_id: u32, header: &RpcFrameHeader, body: &[u8])
+    {
+        let backend = self.atomic_backend.read().unwrap().clone();
+        let result = RpcAtomicReq::read_from(body)
+            .and_then(|req| backend.execute(&req));
+        let resp = match result {
+            Ok(value) => RpcAtomicResp { refused: false, value: value },
+            Err(e) => {
+                warn!("on_recv_atomic: session {}: refused: {}", session_id, e);
+                RpcAtomicResp { refused: true, value: 0 }
+            }
+        };
+
+        self.atomic_resp_due.write().unwrap()
+            .push_back((session_id, header.msg_id, resp));
+        self.work_notify.notify_one();
+    }
+
+    fn on_recv_atomic_resp(&self, session_id: u32, header: &RpcFrameHeader, body: &[u8])
+    {
+        let done = self.atomic_pending.lock().unwrap()
+            .remove(&header.msg_id);
+        let done = match done {
+            Some((_, done)) => done,
+            None => {
+                error!("on_recv_atomic_resp: session {}: unknown request {}", 
+                    session_id, header.msg_id);
+                return;
+            }
+        };
+
+        let result = match RpcAtomicResp::read_from(body) {
+            Ok(resp) if !resp.refused => Ok(resp.value),
+            Ok(_) => Err(RpcNetworkError::Completion(
+                "atomic operation refused by the peer".to_string())),
+            Err(e) => Err(RpcNetworkError::Completion(e)),
+        };
+        let _ = done.send(result);
+    }
+
+    fn on_recv_msg_bin(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_uri: std::string::String, 
+        bin: &[u8]
+    )
+    {
+        let msg = flexbuffers::Reader::get_root(bin)
+            .map_err(|e| e.to_string())
+            .and_then(|reader| RpcOnceMsg::deserialize(reader)
+                .map_err(|e| e.to_string()));
+        match msg {
+            Ok(msg) => self.on_recv_msg(session_id, peer_id, peer_uri, msg),
+            Err(e) => error!("on_recv_msg_bin: session {}: {}", session_id, e),
+        }
+    }
+
+    // Reassembles a message sent in fragments, handled as a whole 
+    // once its last fragment has arrived. 
+    fn on_recv_frag(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_uri: std::string::String, 
+        header: &RpcFrameHeader, 
+        body: &[u8]
+    )
+    {
+        let (frag_header, piece) = match RpcFragHeader::read_from(body) {
+            Ok(frag) => frag,
+            Err(e) => {
+                error!("on_recv_frag: session {}: {}", session_id, e);
+                return;
+            }
+        };
+
         let conf = RPC_CONF.get().unwrap();
-        let mr_size = conf.loc_mr_size as usize;
-
-        let raw_len = u16::from_be_bytes([
-            bin[mr_size - 2], 
-            bin[mr_size - 1]
-        ]) as usize;
-        trace!("on_recv_msg: raw_len {}", raw_len);
-        let raw_msg = &bin[..raw_len];
-        trace!("on_recv_msg: raw_msg.len() {}", raw_msg.len());
-        let reader = 
-            flexbuffers::Reader::get_root(raw_msg).unwrap();
-        let msg = 
-            RpcOnceMsg::deserialize(reader).unwrap();
+        // fragments of consecutive messages may interleave, the 
+        // transfer the sender numbered tells them apart 
+        let key = (session_id, frag_header.xfer_id);
+        let mut frag_in = self.frag_in.write().unwrap();
+        // fragments striped across rails arrive in any order, whichever 
+        // comes first starts the message, its whole size being 
+        // allocated at once, so the peer gets only so many at a time 
+        if !frag_in.contains_key(&key) {
+            if frag_header.total > conf.max_frag_msg_size {
+                error!("on_recv_frag: session {}: msg {} of {} bytes exceeds {}", 
+                    session_id, header.msg_id, frag_header.total, conf.max_frag_msg_size);
+                return;
+            }
+            let partial = frag_in.range((session_id, 0)..=(session_id, u32::MAX))
+                .count();
+            if conf.max_frag_msgs != 0 && partial >= conf.max_frag_msgs as usize {
+                error!("on_recv_frag: session {}: msg {} refused, {} messages partly received", 
+                    session_id, header.msg_id, partial);
+                return;
+            }
+            frag_in.insert(key, RpcFragIn::new(frag_header.total));
+        }
+        let complete = match frag_in.get_mut(&key) {
+            Some(msg) => msg.push(&frag_header, piece),
+            None => Err(format!("fragment at offset {} of an unknown message", 
+                frag_header.offset)),
+        };
+        let msg = match complete {
+            Ok(false) => return,
+            Ok(true) => frag_in.remove(&key).unwrap(),
+            Err(e) => {
+                frag_in.remove(&key);
+                error!("on_recv_frag: session {}: msg {}: {}", 
+                    session_id, header.msg_id, e);
+                return;
+            }
+        };
+        drop(frag_in);
+
+        trace!("on_recv_frag: session {}: msg {} of {} bytes", 
+            session_id, header.msg_id, msg.get_total());
+        self.on_recv_msg_bin(session_id, peer_id, peer_uri, msg.as_bytes());
+    }
+
+    // Handles a frame the peer wrote into the landing region of the 
+    // session, then owes the region back to the peer. 
+    pub fn on_recv_landed(&self, session_id: u32, imm: u32, frame: &[u8])
+    {
+        // landed frames are laid out as version 3 has it, whatever 
+        // the version of the session 
+        match RpcFrameHeader::read_from(frame) {
+            Ok(header) if header.msg_id as u32 == imm => {
+                self.on_recv_framed(session_id, &header, header.body_of(frame));
+            },
+            Ok(header) => {
+                // the receive buffer is used all the same 
+                self.on_recv_credit(session_id, &header);
+                error!("on_recv_landed: session {}: msg {} does not match immediate {}", 
+                    session_id, header.msg_id, imm);
+            },
+            Err(e) => {
+                error!("on_recv_landed: session {}: {}", session_id, e);
+            },
+        }
 
+        self.release_due.write().unwrap().insert(session_id);
+        self.work_notify.notify_one();
+    }
+
+    // The peer is done with the message written into its landing 
+    // region, the next one may go. 
+    fn on_recv_release(&self, session_id: u32)
+    {
+        if let Some(session) = self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            session.release_landing();
+        }
+        RpcNetworkCore::notify_send_vacancy();
+        self.work_notify.notify_one();
+    }
+
+    // Takes the credits returned by the frame and counts the one it 
+    // used, returns the peer of the session. 
+    fn on_recv_credit(
+        &self, 
+        session_id: u32, 
+        header: &RpcFrameHeader
+    ) -> Option<(u32, std::string::String)>
+    {
+        let mut credit_due = false;
+        let peer = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => {
+                let credit = session.get_credit();
+                if header.credit != 0 {
+                    credit.grant(header.credit);
+                    RpcNetworkCore::notify_send_vacancy();
+                    self.work_notify.notify_one();
+                }
+                // control frames take no credit 
+                if !header.kind.is_control() {
+                    credit_due = credit.on_recv();
+                }
+                Some((session.get_peer_id(), session.get_peer_uri().to_string()))
+            },
+            None => None,
+        };
+        if credit_due {
+            self.credit_due.write().unwrap().insert(session_id);
+        }
+        peer
+    }
+
+    // Lends a raw frame to the callback registered for its request 
+    // type, or queues a copy of it as a request if there is none. 
+    fn on_recv_raw(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_uri: std::string::String, 
+        header: &RpcFrameHeader, 
+        body: &[u8]
+    )
+    {
+        let core = RPC_CORE.get().unwrap();
+        if let Some(cb) = core.get_raw_cb_by_reqtype(header.req_type) {
+            let view = RpcRecvView {
+                session_id: session_id,
+                peer_id: peer_id,
+                req_type: header.req_type,
+                src_id: header.src_id,
+                msg_id: header.msg_id,
+                data: body,
+            };
+            (*cb)(&view);
+            return;
+        }
+
+        let mut msg = RpcOnceMsg::default();
+        msg.req_type = header.req_type;
+        msg.src_id = header.src_id;
+        msg.msg_id = header.msg_id;
+        msg.payload.set_data(body.to_vec());
+        self.on_recv_msg(session_id, peer_id, peer_uri, msg);
+    }
+
+    fn on_recv_msg(
+        &self, 
+        session_id: u32, 
+        peer_id: u32, 
+        peer_uri: std::string::String, 
+        msg: RpcOnceMsg
+    )
+    {
         let mut msg_handle = RpcMsgHandle::default();
+        msg_handle.peer_id = peer_id;
+        msg_handle.peer_uri = peer_uri;
         msg_handle.set_msg(msg);
 
         match msg_handle.msg_type
@@ -150,6 +819,7 @@ impl RpcDispatcher
         let mut queue_lock = self.recv_req_queue.write().unwrap();
         queue_lock.push_back(req);
         trace!("on_recv_req: {}", queue_lock.len());
+        self.work_notify.notify_one();
     }
 
     pub fn on_recv_resp(&self, _peer_id: u32, resp: RpcMsgHandle)
@@ -157,6 +827,7 @@ impl RpcDispatcher
         let mut queue_lock = self.recv_resp_queue.write().unwrap();
         queue_lock.push_back(resp);
         trace!("on_recv_resp: {}", queue_lock.len());
+        self.work_notify.notify_one();
     }
 
     pub fn push_req(&self, req: RpcMsgHandle)
@@ -165,12 +836,14 @@ impl RpcDispatcher
         queue_lock.push_back(req);
 
         trace!("push_req: {}", queue_lock.len());
+        self.work_notify.notify_one();
     }
 
     pub fn push_resp(&self, resp: RpcMsgHandle)
     {
         let mut queue_lock = self.send_resp_queue.write().unwrap();
         queue_lock.push_back(resp);
+        self.work_notify.notify_one();
     }
 
     fn check_recv_req(&self)
@@ -188,39 +861,918 @@ impl RpcDispatcher
         }
     }
 
+    // Returns the credits owed on sessions that had nothing to 
+    // piggyback them on, sessions failing to send stay due. 
+    fn check_credit_update(&self)
+    {
+        let mut due_lock = self.credit_due.write().unwrap();
+        let due: Vec<u32> = due_lock.iter().cloned().collect();
+        for session_id in due {
+            let is_due = match self.session_map.read().unwrap()
+                .get(&session_id)
+            {
+                Some(session) => session.get_credit().is_update_due(),
+                None => false,
+            };
+            if !is_due {
+                due_lock.remove(&session_id);
+                continue;
+            }
+
+            let header = RpcFrameHeader::new(RpcFrameKind::Credit);
+            match self.send_on_session(session_id, header, &[]) {
+                Ok(_) => {
+                    due_lock.remove(&session_id);
+                },
+                Err(e) => {
+                    trace!("check_credit_update: session {}: {}", session_id, e);
+                },
+            }
+        }
+    }
+
+    // Hands landing regions back to the peers that wrote into them, 
+    // sessions failing to send stay due. 
+    fn check_landing_release(&self)
+    {
+        let mut due_lock = self.release_due.write().unwrap();
+        let due: Vec<u32> = due_lock.iter().cloned().collect();
+        for session_id in due {
+            let header = RpcFrameHeader::new(RpcFrameKind::Release);
+            match self.send_on_session(session_id, header, &[]) {
+                Ok(_) | Err(RpcNetworkError::NoSession(_)) => {
+                    due_lock.remove(&session_id);
+                },
+                Err(e) => {
+                    trace!("check_landing_release: session {}: {}", session_id, e);
+                },
+            }
+        }
+    }
+
+    // Sends the outcomes of atomic requests of peers, in order, 
+    // until a session runs out of room. 
+    fn check_atomic_resp(&self)
+    {
+        let mut due_lock = self.atomic_resp_due.write().unwrap();
+        while let Some((session_id, id, resp)) = due_lock.pop_front() {
+            let mut header = RpcFrameHeader::new(RpcFrameKind::AtomicResp);
+            header.msg_id = id;
+            let mut body = [0u8; SRPC_ATOMIC_RESP_LEN as usize];
+            resp.write_to(&mut body);
+
+            match self.send_on_session(session_id, header, &body) {
+                Ok(_) => {},
+                Err(e) if e.is_retryable() => {
+                    trace!("check_atomic_resp: session {}: {}, retry later", session_id, e);
+                    due_lock.push_front((session_id, id, resp));
+                    break;
+                },
+                Err(e) => {
+                    error!("check_atomic_resp: session {}: dropped: {}", session_id, e);
+                },
+            }
+        }
+    }
+
+    // Resumes the messages being sent in fragments, returns the peers 
+    // still having one pending, whose later messages must wait. 
+    fn check_frag_out(&self) -> std::collections::BTreeSet<u32>
+    {
+        let peers: Vec<u32> = self.frag_out.read().unwrap()
+            .keys().cloned().collect();
+        for peer_id in peers {
+            if let Err(e) = self.send_frags(peer_id) {
+                error!("check_frag_out: dropped msg to peer {}: {}", peer_id, e);
+            }
+        }
+        self.frag_out.read().unwrap()
+            .keys().cloned().collect()
+    }
+
+    // Sends the queued requests. Requests refused for lack of memory 
+    // regions, in-flight slots or credits stay queued, in order, 
+    // for the next round instead of being dropped. 
     fn check_send_req(&self)
     {
         let conf = RPC_CONF.get().unwrap();
-        let mr_size = conf.loc_mr_size as usize;
+        self.check_credit_update();
+        self.check_landing_release();
+        self.check_atomic_resp();
 
         let mut queue_lock = self.send_req_queue.write().unwrap();
         let len = queue_lock.len();
+        let mut deferred = VecDeque::new();
+        let mut blocked_peers = self.check_frag_out();
         for _ in 0..len {
             let msg_handle = queue_lock.pop_front().unwrap();
             trace!("check_send_req: msg = {:?}", msg_handle);
 
-            let rpc_msg = msg_handle.msg;
+            // keep the order of messages to the same peer 
+            if blocked_peers.contains(&msg_handle.peer_id) {
+                deferred.push_back(msg_handle);
+                continue;
+            }
+
+            let result = if self.is_datagram(&msg_handle) {
+                self.send_datagram(&msg_handle)
+            } else if conf.batch_max > 1 {
+                self.batch_msg(&msg_handle)
+            } else {
+                self.send_msg(&msg_handle)
+            };
+            match result {
+                Ok(_) => {
+                    // a message left in fragments holds back the next ones 
+                    if self.frag_out.read().unwrap().contains_key(&msg_handle.peer_id) {
+                        blocked_peers.insert(msg_handle.peer_id);
+                    }
+                },
+                Err(RpcNetworkError::SessionInflightLimit(session_id)) 
+                | Err(RpcNetworkError::NoCredit(session_id)) 
+                | Err(RpcNetworkError::LandingBusy(session_id)) => {
+                    trace!("check_send_req: session {} is busy", session_id);
+                    blocked_peers.insert(msg_handle.peer_id);
+                    deferred.push_back(msg_handle);
+                },
+                Err(e) if e.is_retryable() => {
+                    trace!("check_send_req: {}, retry later", e);
+                    deferred.push_back(msg_handle);
+                    break;
+                },
+                Err(e) => {
+                    error!("check_send_req: dropped msg to peer {}: {}", 
+                        msg_handle.peer_id, e);
+                },
+            }
+        }
+
+        // put deferred messages back ahead of the unvisited ones 
+        while let Some(msg_handle) = deferred.pop_back() {
+            queue_lock.push_front(msg_handle);
+        }
+        drop(queue_lock);
+
+        self.check_batch_flush();
+    }
+
+    fn send_msg(&self, msg_handle: &RpcMsgHandle) -> Result<(), RpcNetworkError>
+    {
+        let rpc_msg = &msg_handle.msg;
 
-            // ensure message buffer is of equal length to 
-            // pre-allocated RDMA memory region 
-            let mut serializer = 
-                flexbuffers::FlexbufferSerializer::new();
-            rpc_msg.serialize(&mut serializer).unwrap();
-            let msg_bin = serializer.view();
-            let raw_msg_bin_len = msg_bin.len() as u16;
-            trace!("check_send_req: flexbuff msg_bin len= {:?}", raw_msg_bin_len);
-            let mut msg_bin = msg_bin.to_vec();
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new();
+        rpc_msg.serialize(&mut serializer).unwrap();
+        let msg_bin = serializer.view();
+        let frame_len = msg_bin.len() + SRPC_FRAME_HEADER_LEN as usize;
 
-            msg_bin.resize(mr_size, 0);
-            let msg_bin = msg_bin.as_mut_slice();
-            trace!("check_send_req: resize msg_bin len= {:?}", msg_bin.len());
-            msg_bin[mr_size - 2] = raw_msg_bin_len.to_be_bytes()[0];
-            msg_bin[mr_size - 1] = raw_msg_bin_len.to_be_bytes()[1];
+        // pick the session with the smallest memory regions 
+        // holding the frame 
+        let session_id = match self.get_session_id_by_peer_id(
+            msg_handle.peer_id, frame_len) 
+        {
+            Some(session_id) => session_id,
+            None => return self.send_large(msg_handle, msg_bin),
+        };
 
-            let session_id = self.get_session_id_by_peer_id(
-                msg_handle.peer_id).unwrap();
-            self.network.send_to(session_id, msg_bin);
+        self.send_on_session(session_id, Self::msg_header(rpc_msg), msg_bin)
+    }
+
+    // Whether the message goes out as a datagram: its request type is 
+    // one configured so and the peer advertised a datagram queue pair. 
+    fn is_datagram(&self, msg_handle: &RpcMsgHandle) -> bool
+    {
+        RPC_CONF.get().unwrap().ud_req_types.contains(&msg_handle.msg.req_type) 
+            && RpcNetworkCore::has_ud_peer(msg_handle.peer_id)
+    }
+
+    // Sends the message as a datagram, which takes no memory region 
+    // nor credit of a session but may be lost or overtake messages sent 
+    // on sessions. Messages too large for a datagram take a session. 
+    fn send_datagram(&self, msg_handle: &RpcMsgHandle) -> Result<(), RpcNetworkError>
+    {
+        let rpc_msg = &msg_handle.msg;
+
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new();
+        rpc_msg.serialize(&mut serializer).unwrap();
+        let msg_bin = serializer.view();
+        let frame_len = msg_bin.len() + SRPC_FRAME_HEADER_LEN as usize;
+        if frame_len > RpcNetworkCore::get_ud_msg_size() {
+            trace!("send_datagram: {} bytes to peer {}, sent on a session", 
+                frame_len, msg_handle.peer_id);
+            return self.send_msg(msg_handle);
         }
+
+        let mut header = Self::msg_header(rpc_msg);
+        header.len = msg_bin.len() as u32;
+        RpcNetworkCore::send_ud(msg_handle.peer_id, &header, msg_bin)
+    }
+
+    // Handles a datagram of the peer as if received on its session 
+    // with the smallest memory regions. 
+    pub fn on_recv_datagram(&self, peer_id: u32, frame: &[u8])
+    {
+        let header = match RpcFrameHeader::read_from(frame) {
+            Ok(header) => header,
+            Err(e) => {
+                error!("on_recv_datagram: peer {}: {}", peer_id, e);
+                return;
+            }
+        };
+        trace!("on_recv_datagram: peer {}: {:?}", peer_id, header);
+        let body = header.body_of(frame);
+
+        let session_id = match self.get_session_id_by_peer_id(peer_id, 0) {
+            Some(session_id) => session_id,
+            None => {
+                trace!("on_recv_datagram: no session to peer {}", peer_id);
+                return;
+            }
+        };
+        let peer_uri = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => session.get_peer_uri().to_string(),
+            None => return,
+        };
+
+        match header.kind
+        {
+            RpcFrameKind::Msg | RpcFrameKind::Raw => 
+            {
+                self.on_recv_body(session_id, peer_id, peer_uri, &header, body);
+            },
+            _ => 
+            {
+                error!("on_recv_datagram: peer {}: unexpected {:?} frame", 
+                    peer_id, header.kind);
+            },
+        }
+    }
+
+    // Sends a message too large for the memory regions of any session 
+    // to the peer, on a session with the largest ones: into the landing 
+    // region of the peer if configured so and the message fits, 
+    // in fragments otherwise. 
+    fn send_large(&self, msg_handle: &RpcMsgHandle, msg_bin: &[u8]) -> Result<(), RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let frame_len = msg_bin.len() + SRPC_FRAME_HEADER_LEN as usize;
+        let session_id = self.get_largest_session_id_by_peer_id(msg_handle.peer_id)
+            .ok_or(RpcNetworkError::NoPeer(msg_handle.peer_id))?;
+        let header = Self::msg_header(&msg_handle.msg);
+
+        if conf.large_msg == RpcLargeMsgMode::WriteImm {
+            let landing = match self.session_map.read().unwrap()
+                .get(&session_id)
+            {
+                Some(session) => session.get_landing().cloned(),
+                None => None,
+            };
+            if let Some(landing) = landing {
+                if frame_len as u64 <= landing.len {
+                    return self.send_landed(session_id, header, msg_bin, &landing);
+                }
+            }
+        }
+
+        let xfer_id = self.session_map.read().unwrap()
+            .get(&session_id)
+            .map(|session| session.next_xfer_id())
+            .ok_or(RpcNetworkError::NoSession(session_id))?;
+        let frag = RpcFragOut::new(session_id, xfer_id, header, msg_bin.to_vec());
+        self.frag_out.write().unwrap()
+            .insert(msg_handle.peer_id, frag);
+        self.send_frags(msg_handle.peer_id)
+    }
+
+    // Sends the pending fragments of the message to the peer until the 
+    // session runs out of room, the rest goes in later rounds. 
+    // A message failing otherwise is dropped. 
+    fn send_frags(&self, peer_id: u32) -> Result<(), RpcNetworkError>
+    {
+        let mut frag_out = self.frag_out.write().unwrap();
+        let session_id = match frag_out.get(&peer_id) {
+            Some(frag) => frag.get_session_id(),
+            None => return Ok(()),
+        };
+        let max_len = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => session.get_max_msg_size() as usize,
+            None => {
+                frag_out.remove(&peer_id);
+                return Err(RpcNetworkError::NoSession(session_id));
+            }
+        };
+
+        let frag = frag_out.get_mut(&peer_id).unwrap();
+        while !frag.is_done() {
+            let (header, body) = frag.next_frag(max_len);
+            match self.send_on_session(session_id, header, &body) {
+                Ok(_) => frag.advance(body.len()),
+                Err(e) if e.is_retryable() => {
+                    trace!("send_frags: session {}: {}, retry later", session_id, e);
+                    return Ok(());
+                },
+                Err(e) => {
+                    frag_out.remove(&peer_id);
+                    return Err(e);
+                },
+            }
+        }
+        frag_out.remove(&peer_id);
+
+        Ok(())
+    }
+
+    // Writes a frame into the landing region of the peer, where it 
+    // takes a receive buffer, and thus a credit, like any other frame. 
+    fn send_landed(
+        &self, 
+        session_id: u32, 
+        mut header: RpcFrameHeader, 
+        body: &[u8], 
+        landing: &RpcRemoteKey
+    ) -> Result<(), RpcNetworkError>
+    {
+        let session_map = self.session_map.read().unwrap();
+        let session = session_map.get(&session_id)
+            .ok_or(RpcNetworkError::NoSession(session_id))?;
+        if !session.try_take_landing() {
+            return Err(RpcNetworkError::LandingBusy(session_id));
+        }
+        if let Err(e) = self.admit_frame(session, &mut header) {
+            session.release_landing();
+            return Err(e);
+        }
+        header.len = body.len() as u32;
+        trace!("send_landed: session {}: {:?}", session_id, header);
+
+        session.inc_inflight();
+        if let Err(e) = RpcNetworkCore::write_imm_frame(
+            session_id, landing, header.msg_id as u32, &header, body) 
+        {
+            session.dec_inflight();
+            self.revoke_frame(session, &mut header);
+            session.release_landing();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn msg_header(rpc_msg: &RpcOnceMsg) -> RpcFrameHeader
+    {
+        let mut header = RpcFrameHeader::new(RpcFrameKind::Msg);
+        header.req_type = rpc_msg.req_type;
+        header.src_id = rpc_msg.src_id;
+        header.msg_id = rpc_msg.msg_id;
+        header
+    }
+
+    // Adds the message to the batch of the session it goes through, 
+    // the batch is sent once full. Messages too large to share a frame 
+    // are sent on their own, after the batch to keep them in order. 
+    fn batch_msg(&self, msg_handle: &RpcMsgHandle) -> Result<(), RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let rpc_msg = &msg_handle.msg;
+
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new();
+        rpc_msg.serialize(&mut serializer).unwrap();
+        let msg_bin = serializer.view();
+        let frame_len = msg_bin.len() + SRPC_FRAME_HEADER_LEN as usize;
+
+        let session_id = match self.get_session_id_by_peer_id(
+            msg_handle.peer_id, frame_len) 
+        {
+            Some(session_id) => session_id,
+            None => return self.send_large(msg_handle, msg_bin),
+        };
+        let (max_len, speaks_batch) = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => 
+                (session.get_max_msg_size() as usize, session.speaks(RpcFrameKind::Batch)),
+            None => return Err(RpcNetworkError::NoSession(session_id)),
+        };
+        let header = Self::msg_header(rpc_msg);
+        // peers of older versions take messages one by one 
+        if !speaks_batch {
+            return self.send_on_session(session_id, header, msg_bin);
+        }
+
+        let mut batch_map = self.batch_map.write().unwrap();
+        let batch = batch_map.entry(session_id)
+            .or_insert_with(RpcFrameBatch::new);
+
+        if frame_len > max_len {
+            self.flush_batch(session_id, batch)?;
+            return self.send_on_session(session_id, header, msg_bin);
+        }
+        if !batch.fits(frame_len, max_len, conf.batch_max) {
+            self.flush_batch(session_id, batch)?;
+        }
+        batch.push(&header, msg_bin);
+
+        // a full batch that fails to leave is retried on the next round 
+        if batch.count() >= conf.batch_max {
+            if let Err(e) = self.flush_batch(session_id, batch) {
+                trace!("batch_msg: session {}: {}", session_id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sends the frames of the batch as one frame, or as is when there 
+    // is a single one. The batch is kept if it fails to leave, stalled 
+    // until room is made if it may leave later. 
+    fn flush_batch(&self, session_id: u32, batch: &mut RpcFrameBatch) -> Result<(), RpcNetworkError>
+    {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let result = if batch.count() == 1 {
+            let frame = batch.as_bytes();
+            let header = RpcFrameHeader::read_from(frame)
+                .map_err(|e| RpcNetworkError::Verbs(e))?;
+            self.send_on_session(session_id, header.clone(), header.body_of(frame))
+        } else {
+            let header = RpcFrameHeader::new(RpcFrameKind::Batch);
+            self.send_on_session(session_id, header, batch.as_bytes())
+        };
+        match result {
+            Ok(_) => batch.clear(),
+            Err(ref e) => batch.set_stalled(e.is_retryable()),
+        }
+        result
+    }
+
+    // Sends the batches that waited long enough for more messages, 
+    // and retries the stalled ones, the loop only coming round when 
+    // woken up, e.g. by a completion making room. 
+    fn check_batch_flush(&self)
+    {
+        let latency = Self::get_batch_latency();
+        let mut batch_map = self.batch_map.write().unwrap();
+        for (session_id, batch) in batch_map.iter_mut() {
+            if !batch.is_stalled() 
+                && batch.time_to_flush(latency) != Some(std::time::Duration::ZERO) 
+            {
+                continue;
+            }
+            if let Err(e) = self.flush_batch(*session_id, batch) {
+                trace!("check_batch_flush: session {}: {}", session_id, e);
+            }
+        }
+    }
+
+    // Gives up the messages partly received in fragments that waited 
+    // too long for the next one. 
+    fn check_frag_expiry(&self)
+    {
+        let timeout = match Self::get_frag_timeout() {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        self.frag_in.write().unwrap().retain(|(session_id, xfer_id), msg| {
+            if msg.time_to_expire(timeout) != std::time::Duration::ZERO {
+                return true;
+            }
+            warn!("session {}: gave up transfer {} after {:?} without a fragment", 
+                session_id, xfer_id, timeout);
+            false
+        });
+    }
+
+    fn get_frag_timeout() -> Option<std::time::Duration>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        match conf.frag_timeout_ms {
+            0 => None,
+            ms => Some(std::time::Duration::from_millis(ms)),
+        }
+    }
+
+    // Time until the earliest message partly received expires, if any. 
+    fn get_frag_wait(&self) -> Option<std::time::Duration>
+    {
+        let timeout = Self::get_frag_timeout()?;
+        self.frag_in.read().unwrap()
+            .values()
+            .map(|msg| msg.time_to_expire(timeout))
+            .min()
+    }
+
+    fn get_batch_latency() -> std::time::Duration
+    {
+        let conf = RPC_CONF.get().unwrap();
+        std::time::Duration::from_micros(conf.batch_flush_us)
+    }
+
+    // Time until the earliest pending batch is due, if any, 
+    // stalled batches waiting for a wakeup instead. 
+    fn get_batch_wait(&self) -> Option<std::time::Duration>
+    {
+        let latency = Self::get_batch_latency();
+        self.batch_map.read().unwrap()
+            .values()
+            .filter_map(|batch| batch.time_to_flush(latency))
+            .min()
+    }
+
+    // Checks the session is able to take one more frame, then takes 
+    // a credit for it unless it is a control frame, along with the 
+    // credits owed to the peer for the frame to carry back. 
+    fn admit_frame(
+        &self, 
+        session: &RpcSession, 
+        header: &mut RpcFrameHeader
+    ) -> Result<(), RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        match session.get_status() {
+            RpcSessionStatus::Failed(error) => 
+                return Err(RpcNetworkError::SessionLost(session.get_id(), *error)),
+            RpcSessionStatus::Disconnected => 
+                return Err(RpcNetworkError::NoSession(session.get_id())),
+            _ => {},
+        }
+        if !session.speaks(header.kind) {
+            return Err(RpcNetworkError::Unsupported(
+                session.get_id(), session.get_proto_version().unwrap_or(0)));
+        }
+        // the send queue of the session must never overflow 
+        let max_inflight_session = if conf.max_inflight_session != 0 {
+            conf.max_inflight_session
+        } else {
+            conf.sq_depth
+        };
+        if session.get_inflight() >= max_inflight_session as u64 {
+            return Err(RpcNetworkError::SessionInflightLimit(session.get_id()));
+        }
+
+        let credit = session.get_credit();
+        if !header.kind.is_control() && !credit.try_consume() {
+            return Err(RpcNetworkError::NoCredit(session.get_id()));
+        }
+        header.credit = credit.take_owed();
+
+        Ok(())
+    }
+
+    // Gives back what admit_frame took for a frame that did not leave. 
+    fn revoke_frame(&self, session: &RpcSession, header: &mut RpcFrameHeader)
+    {
+        let credit = session.get_credit();
+        credit.restore_owed(header.credit);
+        header.credit = 0;
+        if !header.kind.is_control() {
+            credit.refund();
+        }
+    }
+
+    // Sends a frame on the session, writing the header and `body` 
+    // straight into a memory region of the session, laid out as the 
+    // protocol version of the session has it. Frames small enough 
+    // to go inline are built apart and hold no memory region. 
+    fn send_on_session(
+        &self, 
+        session_id: u32, 
+        mut header: RpcFrameHeader, 
+        body: &[u8]
+    ) -> Result<(), RpcNetworkError>
+    {
+        let session_map = self.session_map.read().unwrap();
+        let session = session_map.get(&session_id)
+            .ok_or(RpcNetworkError::NoSession(session_id))?;
+        let mr_size = session.get_mr_size() as usize;
+        let max_len = session.get_max_msg_size() as usize;
+        let layout = session.get_frame_layout();
+        if body.len() > max_len {
+            return Err(RpcNetworkError::MsgTooLarge(body.len()));
+        }
+
+        // frames without a header at the front fill their region, 
+        // so the length is known before a version 1 body is built 
+        let frame_len = layout.frame_len(mr_size, body.len());
+        let inline = frame_len <= RpcNetworkCore::get_session_inline_limit(session_id);
+        let smr = if inline {
+            None
+        } else {
+            Some(RpcNetworkCore::acquire_send_mr(mr_size as u32)?)
+        };
+        let release_smr = |smr: Option<(u8, u32)>| {
+            if let Some((class_id, mr_index)) = smr {
+                RpcNetworkCore::release_send_mr(class_id, mr_index);
+            }
+        };
+        if let Err(e) = self.admit_frame(session, &mut header) {
+            release_smr(smr);
+            return Err(e);
+        }
+
+        // version 1 frames carry the credits inside the serialized 
+        // message, which is thus built once they are taken 
+        let legacy_bin = match layout {
+            RpcFrameLayout::Legacy => Some(Self::legacy_body(session_id, &header, body)
+                .and_then(|bin| if bin.len() > max_len {
+                    Err(RpcNetworkError::MsgTooLarge(bin.len()))
+                } else {
+                    Ok(bin)
+                })),
+            _ => None,
+        };
+        let body = match legacy_bin {
+            Some(Ok(ref bin)) => &bin[..],
+            Some(Err(e)) => {
+                self.revoke_frame(session, &mut header);
+                release_smr(smr);
+                return Err(e);
+            },
+            None => body,
+        };
+        header.len = body.len() as u32;
+        trace!("send_on_session: session {}: {:?}", session_id, header);
+
+        let credited = !header.kind.is_control();
+        // counted before posting, the completion may come first 
+        session.inc_inflight();
+        let result = match smr {
+            // the one copy of the frame, moved into the posted send 
+            // to be posted again should it be flushed 
+            None => {
+                let mut frame = vec![0u8; frame_len];
+                frame[layout.body_range(body.len())].copy_from_slice(body);
+                layout.write_header(&header, &mut frame);
+                RpcNetworkCore::post_send_inline(
+                    session_id, frame, credited, header.credit)
+            },
+            Some((class_id, mr_index)) => {
+                let frame = unsafe { RpcNetworkCore::get_send_mr(class_id, mr_index) };
+                frame[layout.body_range(body.len())].copy_from_slice(body);
+                layout.write_header(&header, frame);
+                RpcNetworkCore::post_send_mr(
+                    session_id, class_id, mr_index, frame_len, 
+                    credited, header.credit)
+            },
+        };
+        if let Err(e) = result {
+            session.dec_inflight();
+            self.revoke_frame(session, &mut header);
+            release_smr(smr);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    // The body of a version 1 frame: a serialized message holding the 
+    // fields of the header, a raw payload becoming its data. 
+    fn legacy_body(
+        session_id: u32, 
+        header: &RpcFrameHeader, 
+        body: &[u8]
+    ) -> Result<Vec<u8>, RpcNetworkError>
+    {
+        let mut msg = match header.kind {
+            RpcFrameKind::Msg => {
+                let reader = flexbuffers::Reader::get_root(body)
+                    .map_err(|e| RpcNetworkError::Codec(e.to_string()))?;
+                RpcOnceMsg::deserialize(reader)
+                    .map_err(|e| RpcNetworkError::Codec(e.to_string()))?
+            },
+            RpcFrameKind::Raw => {
+                let mut msg = RpcOnceMsg::default();
+                msg.req_type = header.req_type;
+                msg.src_id = header.src_id;
+                msg.msg_id = header.msg_id;
+                msg.payload.set_data(body.to_vec());
+                msg
+            },
+            RpcFrameKind::Credit => {
+                let mut msg = RpcOnceMsg::default();
+                msg.req_type = SRPC_REQ_TYPE_CREDIT;
+                msg
+            },
+            _ => return Err(RpcNetworkError::Unsupported(session_id, 1)),
+        };
+        msg.credit = header.credit;
+
+        let mut serializer = 
+            flexbuffers::FlexbufferSerializer::new();
+        msg.serialize(&mut serializer)
+            .map_err(|e| RpcNetworkError::Codec(e.to_string()))?;
+        Ok(serializer.take_buffer())
+    }
+
+    // Leases a registered send buffer able to carry `len` bytes to the 
+    // peer, for the payload to be written in place and sent with 
+    // send_buf without any copy. 
+    pub fn lease_send_buf(&self, peer_id: u32, len: usize) -> Result<RpcSendBuf, RpcNetworkError>
+    {
+        let frame_len = len + SRPC_FRAME_HEADER_LEN as usize;
+        let session_id = self.select_session(peer_id, frame_len)?;
+        let (mr_size, layout) = match self.session_map.read().unwrap()
+            .get(&session_id)
+        {
+            Some(session) => (session.get_mr_size(), session.get_frame_layout()),
+            None => return Err(RpcNetworkError::NoSession(session_id)),
+        };
+
+        let (class_id, mr_index) = RpcNetworkCore::acquire_send_mr(mr_size)?;
+        Ok(RpcSendBuf::new(session_id, peer_id, class_id, mr_index, mr_size, layout))
+    }
+
+    // Sends the first `len` bytes of a leased buffer as a raw frame. 
+    // The buffer is handed back along with the error if it did not 
+    // leave, so that the send may be retried. 
+    pub fn send_buf(
+        &self, 
+        mut buf: RpcSendBuf, 
+        req_type: u8, 
+        msg_id: u64, 
+        len: usize
+    ) -> Result<(), (RpcSendBuf, RpcNetworkError)>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let session_id = buf.get_session_id();
+        if len > buf.capacity() {
+            return Err((buf, RpcNetworkError::MsgTooLarge(len)));
+        }
+
+        let session_map = self.session_map.read().unwrap();
+        let session = match session_map.get(&session_id) {
+            Some(session) => session,
+            None => return Err((buf, RpcNetworkError::NoSession(session_id))),
+        };
+
+        let mut header = RpcFrameHeader::new(RpcFrameKind::Raw);
+        header.req_type = req_type;
+        header.src_id = conf.loc_id;
+        header.msg_id = msg_id;
+        header.len = len as u32;
+
+        // version 1 frames are serialized messages, the payload 
+        // is copied into one and the buffer given back 
+        let layout = buf.get_layout();
+        if layout == RpcFrameLayout::Legacy {
+            drop(session_map);
+            let body = buf.as_slice()[..len].to_vec();
+            return self.send_on_session(session_id, header, &body)
+                .map_err(|e| (buf, e));
+        }
+
+        if let Err(e) = self.admit_frame(session, &mut header) {
+            return Err((buf, e));
+        }
+        layout.write_header(&header, buf.as_frame_mut());
+
+        let (class_id, mr_index) = buf.get_mr();
+        let frame_len = layout.frame_len(session.get_mr_size() as usize, len);
+        session.inc_inflight();
+        if let Err(e) = RpcNetworkCore::post_send_mr(
+            session_id, class_id, mr_index, frame_len, 
+            !header.kind.is_control(), header.credit) 
+        {
+            session.dec_inflight();
+            self.revoke_frame(session, &mut header);
+            return Err((buf, e));
+        }
+        buf.mark_sent();
+
+        Ok(())
+    }
+
+    // Key of a region the peer exposed when connecting, regions it 
+    // exposed later are to be shared through RPC payloads. 
+    pub fn get_remote_key(&self, peer_id: u32, region_id: u32) -> Option<RpcRemoteKey>
+    {
+        let session_map = self.session_map.read().unwrap();
+        session_map.values()
+            .filter(|session| session.get_peer_id() == peer_id)
+            .filter_map(|session| session.get_peer_meta())
+            .find_map(|meta| meta.get_region(region_id).cloned())
+    }
+
+    // Reads from a region of the peer into a local registered region, 
+    // see RpcNetworkCore::remote_read. 
+    pub async fn remote_read(
+        &self, 
+        peer_id: u32, 
+        region: RpcMemRegion, 
+        local_offset: usize, 
+        remote: &RpcRemoteKey, 
+        remote_offset: u64, 
+        len: usize
+    ) -> RpcRemoteOpResult
+    {
+        let session_id = match self.get_session_id_by_peer_id(peer_id, 0) {
+            Some(session_id) => session_id,
+            None => return Err((Some(region), RpcNetworkError::NoPeer(peer_id))),
+        };
+        RpcNetworkCore::remote_read(session_id, region, local_offset, 
+            remote, remote_offset, len).await
+    }
+
+    // Writes a local registered region into a region of the peer, 
+    // see RpcNetworkCore::remote_write. 
+    pub async fn remote_write(
+        &self, 
+        peer_id: u32, 
+        region: RpcMemRegion, 
+        local_offset: usize, 
+        remote: &RpcRemoteKey, 
+        remote_offset: u64, 
+        len: usize
+    ) -> RpcRemoteOpResult
+    {
+        let session_id = match self.get_session_id_by_peer_id(peer_id, 0) {
+            Some(session_id) => session_id,
+            None => return Err((Some(region), RpcNetworkError::NoPeer(peer_id))),
+        };
+        RpcNetworkCore::remote_write(session_id, region, local_offset, 
+            remote, remote_offset, len).await
+    }
+
+    // Performs an atomic operation on the word at `offset` of the peer 
+    // region, by the adapter or by the dispatcher of the peer as 
+    // configured, returns the previous value of the word. 
+    pub async fn remote_atomic(
+        &self, 
+        session_id: u32, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        op: RpcAtomicOp
+    ) -> Result<u64, RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        match conf.atomic_mode {
+            RpcAtomicMode::Rdma => 
+                RpcNetworkCore::remote_atomic(session_id, remote, offset, op).await,
+            RpcAtomicMode::Emulated => 
+                self.emulate_atomic(session_id, remote, offset, op).await,
+        }
+    }
+
+    // Sends the atomic operation to the dispatcher of the peer, 
+    // then waits for the outcome. 
+    async fn emulate_atomic(
+        &self, 
+        session_id: u32, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        op: RpcAtomicOp
+    ) -> Result<u64, RpcNetworkError>
+    {
+        let conf = RPC_CONF.get().unwrap();
+        let addr = remote.addr_of(offset, 8)?;
+        if addr % 8 != 0 {
+            return Err(RpcNetworkError::Misaligned(addr));
+        }
+        let req = RpcAtomicReq {
+            op: op,
+            region_id: remote.region_id,
+            rkey: remote.rkey,
+            addr: addr,
+        };
+        let mut body = [0u8; SRPC_ATOMIC_REQ_LEN as usize];
+        req.write_to(&mut body);
+
+        let id = self.atomic_cnt.fetch_add(
+            1, 
+            std::sync::atomic::Ordering::SeqCst
+        );
+        let (done, result) = tokio::sync::oneshot::channel();
+        self.atomic_pending.lock().unwrap().insert(id, (session_id, done));
+
+        let mut header = RpcFrameHeader::new(RpcFrameKind::Atomic);
+        header.src_id = conf.loc_id;
+        header.msg_id = id;
+        let sent = RpcNetworkCore::retry_on_vacancy(
+            || self.send_on_session(session_id, header.clone(), &body)
+        ).await;
+        if let Err(e) = sent {
+            self.atomic_pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+
+        result.await
+            .unwrap_or(Err(RpcNetworkError::NoSession(session_id)))
+    }
+
+    // Calls `f` with the session, returns None if there is no such session. 
+    pub fn with_session<R, F>(&self, session_id: u32, f: F) -> Option<R>
+    where F: FnOnce(&RpcSession) -> R
+    {
+        self.session_map.read().unwrap()
+            .get(&session_id)
+            .map(f)
     }
 
     fn check_recv_resp(&self)
@@ -239,19 +1791,89 @@ impl RpcDispatcher
         self.check_send_req();
         self.check_recv_resp();
         self.check_send_resp();
+        self.check_frag_expiry();
+        self.check_failed_sessions();
+    }
+
+    // Tears down the failed sessions whose sends in flight all 
+    // completed, which gives back their queue pairs, buffers and slot. 
+    fn check_failed_sessions(&self)
+    {
+        let session_ids: Vec<u32> = self.session_map.read().unwrap()
+            .iter()
+            .filter(|(_, session)| matches!(session.get_status(), RpcSessionStatus::Failed(_)) 
+                && session.get_inflight() == 0)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+        for session_id in session_ids {
+            self.close_session(session_id);
+            info!("session {} torn down after failing", session_id);
+        }
+    }
+
+    // Runs the loop whenever messages arrive, are queued or may be 
+    // sent again, sleeping in between instead of spinning. Pending 
+    // batches and messages partly received wake it up once they are due. 
+    pub async fn run_loop(&self)
+    {
+        loop {
+            self.run_loop_once();
+            let wait = [self.get_batch_wait(), self.get_frag_wait()]
+                .into_iter()
+                .flatten()
+                .min();
+            match wait {
+                Some(wait) => {
+                    let _ = tokio::time::timeout(
+                        wait, 
+                        self.work_notify.notified()
+                    ).await;
+                },
+                None => self.work_notify.notified().await,
+            }
+        }
     }
 }
 
 // Utility functions for RpcDispatcher 
 impl RpcDispatcher {
-    fn get_session_id_by_peer_id(&self, peer_id: u32) -> Option<u32>
+    // Picks one of the sessions connected to the peer 
+    // according to the configured selection policy. 
+    fn get_session_id_by_peer_id(&self, peer_id: u32, frame_len: usize) -> Option<u32>
     {
+        let conf = RPC_CONF.get().unwrap();
+
         let peer_map = self.peer_map.read().unwrap();
-        let session_id = peer_map.get(&peer_id);
-        match session_id
-        {
-            Some(id) => Some(*id),
-            None => None,
+        let pool = peer_map.get(&peer_id)?;
+        let session_map = self.session_map.read().unwrap();
+        pool.select(conf.qp_select, frame_len, |session_id| {
+            match session_map.get(&session_id)
+            {
+                Some(session) => session.get_inflight(),
+                None => u64::MAX,
+            }
+        })
+    }
+
+    // Like get_session_id_by_peer_id, failing with NoPeer when no session 
+    // to the peer is up and with MsgTooLarge when none holds the frame. 
+    fn select_session(&self, peer_id: u32, frame_len: usize) -> Result<u32, RpcNetworkError>
+    {
+        if let Some(session_id) = self.get_session_id_by_peer_id(peer_id, frame_len) {
+            return Ok(session_id);
+        }
+        match self.peer_map.read().unwrap().get(&peer_id) {
+            Some(pool) if !pool.is_empty() => Err(RpcNetworkError::MsgTooLarge(frame_len)),
+            _ => Err(RpcNetworkError::NoPeer(peer_id)),
         }
     }
+
+    // Picks one of the sessions with the largest memory regions. 
+    fn get_largest_session_id_by_peer_id(&self, peer_id: u32) -> Option<u32>
+    {
+        let mr_size = self.peer_map.read().unwrap()
+            .get(&peer_id)?
+            .get_max_mr_size();
+        self.get_session_id_by_peer_id(peer_id, mr_size as usize)
+    }
 }
\ No newline at end of file
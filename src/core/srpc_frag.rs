@@ -0,0 +1,217 @@
+use crate::msg::srpc_frame::{RpcFrameHeader, RpcFrameKind, RpcFragHeader, SRPC_FRAG_HEADER_LEN};
+
+// How messages too large for the memory regions of any session
+// to the peer are carried.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RpcLargeMsgMode {
+    // Split into fragment frames reassembled by the receiver.
+    Fragment,
+    // Written straight into the landing region the receiver
+    // advertised, signalled by an immediate value carrying the msg_id.
+    // Messages not fitting the landing region are fragmented.
+    WriteImm,
+}
+
+impl std::str::FromStr for RpcLargeMsgMode {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fragment" => Ok(RpcLargeMsgMode::Fragment),
+            "write-imm" => Ok(RpcLargeMsgMode::WriteImm),
+            _ => Err(format!("unknown large message protocol: {}", s)),
+        }
+    }
+}
+
+// A serialized message being sent in fragments on one session.
+// Fragments go out in order; the message stays here until the last
+// one has left, so that sending resumes where it stopped.
+#[derive(Debug)]
+pub struct RpcFragOut {
+    session_id: u32,
+    // tells the fragments apart from those of other messages
+    xfer_id: u32,
+    header: RpcFrameHeader,
+    bin: std::vec::Vec<u8>,
+    sent: usize,
+}
+
+impl RpcFragOut {
+    // `header` is the one the message would have had in one frame,
+    // `xfer_id` one the session gave no other message in fragments lately.
+    pub fn new(
+        session_id: u32,
+        xfer_id: u32,
+        header: RpcFrameHeader,
+        bin: std::vec::Vec<u8>
+    ) -> RpcFragOut {
+        RpcFragOut {
+            session_id: session_id,
+            xfer_id: xfer_id,
+            header: header,
+            bin: bin,
+            sent: 0,
+        }
+    }
+
+    pub fn get_session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sent >= self.bin.len()
+    }
+
+    // Header and body of the next fragment, whose body holds at most
+    // `max_len` bytes.
+    pub fn next_frag(&self, max_len: usize) -> (RpcFrameHeader, std::vec::Vec<u8>) {
+        let room = max_len.saturating_sub(SRPC_FRAG_HEADER_LEN as usize);
+        let end = std::cmp::min(self.sent + std::cmp::max(room, 1), self.bin.len());
+
+        let mut header = self.header.clone();
+        header.kind = RpcFrameKind::Frag;
+        let frag_header = RpcFragHeader {
+            total: self.bin.len() as u32,
+            offset: self.sent as u32,
+            xfer_id: self.xfer_id,
+        };
+        let mut body = vec![0; SRPC_FRAG_HEADER_LEN as usize + end - self.sent];
+        frag_header.write_to(&mut body);
+        body[SRPC_FRAG_HEADER_LEN as usize..].copy_from_slice(&self.bin[self.sent..end]);
+        (header, body)
+    }
+
+    // Records the fragment returned by next_frag as sent.
+    pub fn advance(&mut self, body_len: usize) {
+        self.sent += body_len - SRPC_FRAG_HEADER_LEN as usize;
+    }
+}
+
+// Fragments of a message received so far. Fragments of a session
+// are striped across its rails and arrive in any order, each is copied
+// in place; a fragment received again is ignored.
+#[derive(Debug)]
+pub struct RpcFragIn {
+    buf: std::vec::Vec<u8>,
+    // offset -> end of the fragments received
+    pieces: std::collections::BTreeMap<usize, usize>,
+    received: usize,
+    // When the last fragment arrived.
+    since: std::time::Instant,
+}
+
+impl RpcFragIn {
+    // The caller checks `total` against the largest message it accepts,
+    // the buffer being allocated up front.
+    pub fn new(total: u32) -> RpcFragIn {
+        RpcFragIn {
+            buf: vec![0; total as usize],
+            pieces: std::collections::BTreeMap::new(),
+            received: 0,
+            since: std::time::Instant::now(),
+        }
+    }
+
+    // Time left before the message has waited `timeout` for its next
+    // fragment, zero once it is to be given up.
+    pub fn time_to_expire(&self, timeout: std::time::Duration) -> std::time::Duration {
+        timeout.saturating_sub(self.since.elapsed())
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.buf.len()
+    }
+
+    // Copies a piece in place, returns whether the message is complete.
+    // Pieces overlapping others than the one they repeat are refused.
+    pub fn push(&mut self, header: &RpcFragHeader, piece: &[u8]) -> Result<bool, std::string::String> {
+        if header.total as usize != self.buf.len() {
+            return Err(format!("fragment of a {} bytes message, expected {}",
+                header.total, self.buf.len()));
+        }
+        let offset = header.offset as usize;
+        let end = offset + piece.len();
+        if end > self.buf.len() {
+            return Err(format!("fragment ends at {} beyond {}", end, self.buf.len()));
+        }
+        if self.pieces.get(&offset) == Some(&end) {
+            return Ok(false);
+        }
+        let before = self.pieces.range(..=offset).next_back();
+        let after = self.pieces.range(offset..).next();
+        if before.map_or(false, |(_, before_end)| *before_end > offset)
+            || after.map_or(false, |(after_offset, _)| *after_offset < end)
+        {
+            return Err(format!("fragment from {} to {} overlaps another", offset, end));
+        }
+
+        self.buf[offset..end].copy_from_slice(piece);
+        self.pieces.insert(offset, end);
+        self.received += piece.len();
+        self.since = std::time::Instant::now();
+        Ok(self.received == self.buf.len())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frag(total: u32, offset: u32) -> RpcFragHeader {
+        RpcFragHeader {
+            total: total,
+            offset: offset,
+            xfer_id: 1,
+        }
+    }
+
+    #[test]
+    fn fragments_carry_their_transfer() {
+        let bin: Vec<u8> = (0..10).collect();
+        let mut out = RpcFragOut::new(3, 7, RpcFrameHeader::new(RpcFrameKind::Msg), bin.clone());
+        let mut msg = RpcFragIn::new(10);
+        while !out.is_done() {
+            let (header, body) = out.next_frag(SRPC_FRAG_HEADER_LEN as usize + 4);
+            assert_eq!(header.kind, RpcFrameKind::Frag);
+            let (frag_header, piece) = RpcFragHeader::read_from(&body).unwrap();
+            assert_eq!(frag_header.xfer_id, 7);
+            assert_eq!(frag_header.total, 10);
+            let complete = msg.push(&frag_header, piece).unwrap();
+            out.advance(body.len());
+            assert_eq!(complete, out.is_done());
+        }
+        assert_eq!(msg.as_bytes(), &bin[..]);
+    }
+
+    #[test]
+    fn fragments_complete_in_any_order() {
+        let mut msg = RpcFragIn::new(6);
+        assert_eq!(msg.push(&frag(6, 4), b"ef"), Ok(false));
+        assert_eq!(msg.push(&frag(6, 0), b"ab"), Ok(false));
+        assert_eq!(msg.push(&frag(6, 2), b"cd"), Ok(true));
+        assert_eq!(msg.as_bytes(), b"abcdef");
+    }
+
+    #[test]
+    fn repeated_fragments_are_ignored() {
+        let mut msg = RpcFragIn::new(4);
+        assert_eq!(msg.push(&frag(4, 0), b"ab"), Ok(false));
+        assert_eq!(msg.push(&frag(4, 0), b"ab"), Ok(false));
+        assert_eq!(msg.push(&frag(4, 2), b"cd"), Ok(true));
+    }
+
+    #[test]
+    fn overlapping_or_foreign_fragments_are_refused() {
+        let mut msg = RpcFragIn::new(6);
+        assert_eq!(msg.push(&frag(6, 2), b"cd"), Ok(false));
+        assert!(msg.push(&frag(6, 1), b"xx").is_err());
+        assert!(msg.push(&frag(6, 3), b"xx").is_err());
+        assert!(msg.push(&frag(6, 5), b"xx").is_err());
+        assert!(msg.push(&frag(8, 0), b"ab").is_err());
+    }
+}
@@ -3,12 +3,65 @@ use tracing::{error};
 #[allow(unused_imports)]
 use crate::msg::srpc_msg::{RpcMsgPayload};
 use crate::{core::srpc_dispatcher::RpcDispatcher, msg::srpc_msg::{RpcMsgHandle, RpcOnceMsg}}; 
+use crate::core::network::srpc_peer_meta::RpcPeerMeta;
+use crate::core::network::srpc_qp_attr::RpcQpAttr;
+use crate::core::srpc_credit::RpcCredit;
+use crate::core::network::srpc_remote_mr::RpcRemoteKey;
+use crate::core::network::srpc_net_error::{RpcNetworkError, RpcWcError};
+use crate::msg::srpc_atomic::RpcAtomicOp;
+use crate::msg::srpc_frame::{RpcFrameKind, RpcFrameLayout};
 
 use once_cell::sync::OnceCell; 
 pub static SESSION_COUNTER: OnceCell<std::sync::Arc<
     std::sync::atomic::AtomicU32>> = OnceCell::new(); 
 
-#[derive(PartialEq, Debug)]
+// Room for one session within the configured limit, given back 
+// when dropped, whether the session failed to establish, failed 
+// later on or was closed. 
+#[derive(Debug)]
+pub struct RpcSessionSlot {
+    slots: std::sync::Arc<std::sync::atomic::AtomicU32>,
+}
+
+impl RpcSessionSlot {
+    // Takes a slot unless `max_slots` are taken already, 0 for no limit. 
+    pub fn reserve(
+        slots: &std::sync::Arc<std::sync::atomic::AtomicU32>, 
+        max_slots: u32
+    ) -> Result<RpcSessionSlot, std::string::String> {
+        let result = slots.fetch_update(
+            std::sync::atomic::Ordering::SeqCst, 
+            std::sync::atomic::Ordering::SeqCst, 
+            |n| {
+                if max_slots != 0 && n >= max_slots {
+                    None
+                } else {
+                    Some(n + 1)
+                }
+            }
+        );
+
+        match result {
+            Ok(_) => Ok(RpcSessionSlot {
+                slots: slots.clone(),
+            }),
+            Err(n) => Err(format!(
+                "session limit reached: {} of {}", n, max_slots)),
+        }
+    }
+}
+
+impl Drop for RpcSessionSlot {
+    fn drop(&mut self) {
+        let _ = self.slots.fetch_update(
+            std::sync::atomic::Ordering::SeqCst, 
+            std::sync::atomic::Ordering::SeqCst, 
+            |n| n.checked_sub(1)
+        );
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum RpcSessionStatus {
     // The session is not connected to any server.
     Disconnected,
@@ -18,15 +71,31 @@ pub enum RpcSessionStatus {
     Connected,
     // The session is connected to a server and running logic.
     Running, 
+    // A work request of the session failed, its queue pair is in the
+    // error state and takes no more messages.
+    Failed(RpcWcError),
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct RpcSession {
     status: RpcSessionStatus,
+    session_id: u32, 
     // The server address.
     peer_id: u32, 
     peer_uri: std::string::String, 
+    // Identity and capabilities reported by the peer.
+    peer_meta: Option<RpcPeerMeta>, 
+    // Sends posted but not completed yet.
+    inflight: std::sync::atomic::AtomicU64, 
+    // Credits granted by the peer and owed to it.
+    credit: RpcCredit, 
+    // The landing region of the peer holds a message not released yet.
+    landing_busy: std::sync::atomic::AtomicBool, 
+    // Messages sent in fragments so far, numbering their transfers.
+    xfer_cnt: std::sync::atomic::AtomicU32, 
+    // Room the session takes within the session limit, until torn down.
+    slot: Option<RpcSessionSlot>, 
     dispatcher: std::sync::Arc<RpcDispatcher>,
 }
 
@@ -34,14 +103,22 @@ pub struct RpcSession {
 #[allow(unused_variables)]
 impl RpcSession {
     pub fn new(
+        session_id: u32, 
         peer_id: u32, 
         peer_uri: std::string::String, 
         dispatcher: std::sync::Arc<RpcDispatcher>
     ) -> RpcSession {
         RpcSession {
             status: RpcSessionStatus::Disconnected,
+            session_id: session_id, 
             peer_id: peer_id,
             peer_uri: peer_uri, 
+            peer_meta: None, 
+            inflight: std::sync::atomic::AtomicU64::new(0), 
+            credit: RpcCredit::unlimited(), 
+            landing_busy: std::sync::atomic::AtomicBool::new(false), 
+            xfer_cnt: std::sync::atomic::AtomicU32::new(0), 
+            slot: None, 
             dispatcher: dispatcher,
         }
     }
@@ -54,6 +131,176 @@ impl RpcSession {
         )
     }
 
+    pub fn set_status(&mut self, status: RpcSessionStatus) {
+        self.status = status;
+    }
+
+    pub fn get_status(&self) -> &RpcSessionStatus {
+        &self.status
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.session_id
+    }
+
+    pub fn get_peer_id(&self) -> u32 {
+        self.peer_id
+    }
+
+    pub fn get_peer_uri(&self) -> &str {
+        &self.peer_uri
+    }
+
+    pub fn set_peer_meta(&mut self, peer_meta: RpcPeerMeta) {
+        self.peer_meta = Some(peer_meta);
+    }
+
+    pub fn get_peer_meta(&self) -> Option<&RpcPeerMeta> {
+        self.peer_meta.as_ref()
+    }
+
+    // Protocol version agreed with the peer during the handshake.
+    pub fn get_proto_version(&self) -> Option<u32> {
+        self.peer_meta.as_ref().map(|meta| meta.proto_version)
+    }
+
+    // How frames are laid out on the session.
+    pub fn get_frame_layout(&self) -> RpcFrameLayout {
+        match self.get_proto_version() {
+            Some(version) => RpcFrameLayout::of_version(version),
+            None => RpcFrameLayout::Header,
+        }
+    }
+
+    // Whether the protocol version of the session carries the kind of frame.
+    pub fn speaks(&self, kind: RpcFrameKind) -> bool {
+        match self.get_proto_version() {
+            Some(version) => kind.since_version() <= version,
+            None => false,
+        }
+    }
+
+    // Largest frame body the memory regions of the session carry.
+    pub fn get_max_msg_size(&self) -> u32 {
+        match self.peer_meta {
+            Some(ref meta) => meta.max_msg_size,
+            None => 0,
+        }
+    }
+
+    // Queue pair attributes negotiated with the peer, the inline size
+    // being the local one.
+    pub fn get_qp_attr(&self) -> Option<RpcQpAttr> {
+        self.peer_meta.as_ref().map(|meta| meta.qp_attr)
+    }
+
+    // Size of the memory regions the session is bound to.
+    pub fn get_mr_size(&self) -> u32 {
+        match self.peer_meta {
+            Some(ref meta) => meta.mr_size,
+            None => 0,
+        }
+    }
+
+    pub fn inc_inflight(&self) {
+        self.inflight.fetch_add(
+            1, 
+            std::sync::atomic::Ordering::Relaxed
+        );
+    }
+
+    pub fn dec_inflight(&self) {
+        let _ = self.inflight.fetch_update(
+            std::sync::atomic::Ordering::Relaxed, 
+            std::sync::atomic::Ordering::Relaxed, 
+            |n| n.checked_sub(1)
+        );
+    }
+
+    pub fn get_inflight(&self) -> u64 {
+        self.inflight.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn set_slot(&mut self, slot: RpcSessionSlot) {
+        self.slot = Some(slot);
+    }
+
+    pub fn set_credit(&mut self, credit: RpcCredit) {
+        self.credit = credit;
+    }
+
+    pub fn get_credit(&self) -> &RpcCredit {
+        &self.credit
+    }
+
+    // Region the peer takes large messages in, if it offered one.
+    // Id of the next message the session sends in fragments, which 
+    // tells its fragments apart from those of the messages around it. 
+    pub fn next_xfer_id(&self) -> u32 {
+        self.xfer_cnt.fetch_add(
+            1, 
+            std::sync::atomic::Ordering::Relaxed
+        )
+    }
+
+    pub fn get_landing(&self) -> Option<&RpcRemoteKey> {
+        self.peer_meta.as_ref().and_then(|meta| meta.landing.as_ref())
+    }
+
+    // Claims the landing region of the peer for one message,
+    // fails while it holds the previous one.
+    pub fn try_take_landing(&self) -> bool {
+        self.landing_busy.compare_exchange(
+            false, 
+            true, 
+            std::sync::atomic::Ordering::AcqRel, 
+            std::sync::atomic::Ordering::Acquire
+        ).is_ok()
+    }
+
+    pub fn release_landing(&self) {
+        self.landing_busy.store(false, std::sync::atomic::Ordering::Release);
+    }
+
+    // Adds `add` to the word at `offset` of the peer region, 
+    // resolves to its previous value. 
+    // The future does not borrow the session, so that it may be 
+    // awaited once the session map is unlocked. 
+    pub fn fetch_add(
+        &self, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        add: u64
+    ) -> impl std::future::Future<Output = Result<u64, RpcNetworkError>> {
+        self.remote_atomic(remote, offset, RpcAtomicOp::FetchAdd(add))
+    }
+
+    // Stores `swap` in the word at `offset` of the peer region if it 
+    // equals `compare`, resolves to its previous value either way. 
+    pub fn compare_swap(
+        &self, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        compare: u64, 
+        swap: u64
+    ) -> impl std::future::Future<Output = Result<u64, RpcNetworkError>> {
+        self.remote_atomic(remote, offset, RpcAtomicOp::CompareSwap(compare, swap))
+    }
+
+    fn remote_atomic(
+        &self, 
+        remote: &RpcRemoteKey, 
+        offset: u64, 
+        op: RpcAtomicOp
+    ) -> impl std::future::Future<Output = Result<u64, RpcNetworkError>> {
+        let session_id = self.session_id;
+        let dispatcher = self.dispatcher.clone();
+        let remote = remote.clone();
+        async move {
+            dispatcher.remote_atomic(session_id, &remote, offset, op).await
+        }
+    }
+
     pub fn push_request(&mut self, msg: RpcOnceMsg) -> bool {
         if self.status != RpcSessionStatus::Connected {
             error!("The session is not connected to any server.");
@@ -61,6 +308,8 @@ impl RpcSession {
         }
 
         let mut msg_handle = RpcMsgHandle::default();
+        msg_handle.peer_id = self.peer_id;
+        msg_handle.peer_uri = self.peer_uri.clone();
         msg_handle.set_msg(msg);
 
         // report to dispatcher 
@@ -0,0 +1,168 @@
+// Policy to pick one of the sessions connected to the same peer.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum RpcSessionSelect {
+    // Cycle through the sessions in order.
+    RoundRobin,
+    // Pick the session with the fewest in-flight sends.
+    LeastLoaded,
+}
+
+impl std::str::FromStr for RpcSessionSelect {
+    type Err = std::string::String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rr" | "round-robin" => Ok(RpcSessionSelect::RoundRobin),
+            "ll" | "least-loaded" => Ok(RpcSessionSelect::LeastLoaded),
+            _ => Err(format!("unknown session selection policy: {}", s)),
+        }
+    }
+}
+
+// Sessions (one queue pair each) connected to the same peer.
+#[derive(Debug)]
+pub struct RpcSessionPool {
+    sessions: std::vec::Vec<(u32, u32)>, // (session_id, mr_size)
+    next: std::sync::atomic::AtomicUsize, // round-robin cursor
+}
+
+impl RpcSessionPool {
+    pub fn new() -> RpcSessionPool {
+        RpcSessionPool {
+            sessions: std::vec::Vec::new(),
+            next: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    pub fn insert(&mut self, session_id: u32, mr_size: u32) {
+        if !self.sessions.iter().any(|(id, _)| *id == session_id) {
+            self.sessions.push((session_id, mr_size));
+        }
+    }
+
+    pub fn remove(&mut self, session_id: u32) {
+        self.sessions.retain(|(id, _)| *id != session_id);
+    }
+
+    pub fn get_sessions(&self) -> std::vec::Vec<u32> {
+        self.sessions.iter()
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // Largest frame any of the sessions is able to carry.
+    pub fn get_max_mr_size(&self) -> u32 {
+        self.sessions.iter()
+            .map(|(_, mr_size)| *mr_size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    // Picks a session according to the policy among the ones
+    // with the smallest memory regions holding `frame_len` bytes,
+    // `load_of` reports the in-flight sends of a session.
+    pub fn select<F>(
+        &self,
+        policy: RpcSessionSelect,
+        frame_len: usize,
+        load_of: F
+    ) -> Option<u32>
+    where F: Fn(u32) -> u64
+    {
+        let mr_size = self.sessions.iter()
+            .map(|(_, mr_size)| *mr_size)
+            .filter(|mr_size| *mr_size as usize >= frame_len)
+            .min()?;
+        let candidates: std::vec::Vec<u32> = self.sessions.iter()
+            .filter(|(_, size)| *size == mr_size)
+            .map(|(id, _)| *id)
+            .collect();
+
+        match policy {
+            RpcSessionSelect::RoundRobin => {
+                let cursor = self.next.fetch_add(
+                    1,
+                    std::sync::atomic::Ordering::Relaxed
+                );
+                Some(candidates[cursor % candidates.len()])
+            },
+            RpcSessionSelect::LeastLoaded => {
+                candidates.iter()
+                    .min_by_key(|id| load_of(**id))
+                    .cloned()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(sessions: &[(u32, u32)]) -> RpcSessionPool {
+        let mut pool = RpcSessionPool::new();
+        for (session_id, mr_size) in sessions {
+            pool.insert(*session_id, *mr_size);
+        }
+        pool
+    }
+
+    #[test]
+    fn round_robin_cycles_through_the_sessions() {
+        let pool = pool(&[(1, 256), (2, 256), (3, 256)]);
+        let picked: Vec<u32> = (0..6)
+            .filter_map(|_| pool.select(RpcSessionSelect::RoundRobin, 100, |_| 0))
+            .collect();
+        assert_eq!(picked, vec![1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn least_loaded_picks_the_fewest_inflight() {
+        let pool = pool(&[(1, 256), (2, 256), (3, 256)]);
+        let load = |session_id: u32| match session_id {
+            1 => 5,
+            2 => 1,
+            _ => 3,
+        };
+        assert_eq!(pool.select(RpcSessionSelect::LeastLoaded, 100, load), Some(2));
+        // ties go to the session inserted first
+        assert_eq!(pool.select(RpcSessionSelect::LeastLoaded, 100, |_| 7), Some(1));
+    }
+
+    #[test]
+    fn frames_take_the_smallest_regions_holding_them() {
+        let pool = pool(&[(1, 4096), (2, 256), (3, 1024), (4, 256)]);
+        for policy in [RpcSessionSelect::RoundRobin, RpcSessionSelect::LeastLoaded] {
+            assert!(matches!(pool.select(policy, 256, |_| 0), Some(2) | Some(4)));
+            assert_eq!(pool.select(policy, 257, |_| 0), Some(3));
+            assert_eq!(pool.select(policy, 4096, |_| 0), Some(1));
+            assert_eq!(pool.select(policy, 4097, |_| 0), None);
+        }
+        assert_eq!(pool.get_max_mr_size(), 4096);
+    }
+
+    #[test]
+    fn removed_sessions_are_not_picked() {
+        let mut pool = pool(&[(1, 256), (2, 256)]);
+        pool.insert(1, 256);
+        assert_eq!(pool.get_sessions(), vec![1, 2]);
+        pool.remove(1);
+        for _ in 0..3 {
+            assert_eq!(pool.select(RpcSessionSelect::RoundRobin, 1, |_| 0), Some(2));
+        }
+        pool.remove(2);
+        assert!(pool.is_empty());
+        assert_eq!(pool.select(RpcSessionSelect::LeastLoaded, 1, |_| 0), None);
+    }
+
+    #[test]
+    fn policies_parse_by_short_and_long_name() {
+        assert_eq!("rr".parse::<RpcSessionSelect>(), Ok(RpcSessionSelect::RoundRobin));
+        assert_eq!("least-loaded".parse::<RpcSessionSelect>(), Ok(RpcSessionSelect::LeastLoaded));
+        assert!("random".parse::<RpcSessionSelect>().is_err());
+    }
+}
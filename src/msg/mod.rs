@@ -1 +1,3 @@
-pub mod srpc_msg;
\ No newline at end of file
+pub mod srpc_msg;
+pub mod srpc_frame;
+pub mod srpc_atomic;
\ No newline at end of file
@@ -0,0 +1,128 @@
+// Bytes of the body of an atomic request frame.
+pub const SRPC_ATOMIC_REQ_LEN: u32 = 33;
+// Bytes of the body of an atomic response frame.
+pub const SRPC_ATOMIC_RESP_LEN: u32 = 9;
+
+// Operation on an 8-byte word of a peer region.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RpcAtomicOp
+{
+    // Adds the operand, yields the previous value.
+    FetchAdd(u64),
+    // Stores the second operand if the word equals the first one,
+    // yields the previous value either way.
+    CompareSwap(u64, u64),
+}
+
+impl RpcAtomicOp
+{
+    // Performs the operation on a local word, returns the previous value.
+    pub fn apply(&self, word: &std::sync::atomic::AtomicU64) -> u64
+    {
+        match self
+        {
+            RpcAtomicOp::FetchAdd(add) => word.fetch_add(
+                *add,
+                std::sync::atomic::Ordering::SeqCst
+            ),
+            RpcAtomicOp::CompareSwap(compare, swap) => match word.compare_exchange(
+                *compare,
+                *swap,
+                std::sync::atomic::Ordering::SeqCst,
+                std::sync::atomic::Ordering::SeqCst
+            )
+            {
+                Ok(prev) => prev,
+                Err(prev) => prev,
+            },
+        }
+    }
+}
+
+// Atomic operation carried to the peer when the transport does not
+// perform it, written in the body of an atomic request frame.
+// Layout, big endian: op(1) region_id(4) rkey(4) addr(8) operand(8) swap(8)
+#[derive(Debug, PartialEq, Clone)]
+pub struct RpcAtomicReq
+{
+    pub op: RpcAtomicOp,
+    pub region_id: u32,
+    pub rkey: u32,
+    pub addr: u64, // of the word in the peer region
+}
+
+impl RpcAtomicReq
+{
+    pub fn write_to(&self, body: &mut [u8])
+    {
+        let (op, operand, swap) = match self.op
+        {
+            RpcAtomicOp::FetchAdd(add) => (1u8, add, 0),
+            RpcAtomicOp::CompareSwap(compare, swap) => (2u8, compare, swap),
+        };
+        body[0] = op;
+        body[1..5].copy_from_slice(&self.region_id.to_be_bytes());
+        body[5..9].copy_from_slice(&self.rkey.to_be_bytes());
+        body[9..17].copy_from_slice(&self.addr.to_be_bytes());
+        body[17..25].copy_from_slice(&operand.to_be_bytes());
+        body[25..33].copy_from_slice(&swap.to_be_bytes());
+    }
+
+    pub fn read_from(body: &[u8]) -> Result<RpcAtomicReq, std::string::String>
+    {
+        if body.len() < SRPC_ATOMIC_REQ_LEN as usize
+        {
+            return Err(format!("atomic request of {} bytes is too short", body.len()));
+        }
+
+        let operand = u64::from_be_bytes(body[17..25].try_into().unwrap());
+        let swap = u64::from_be_bytes(body[25..33].try_into().unwrap());
+        let op = match body[0]
+        {
+            1 => RpcAtomicOp::FetchAdd(operand),
+            2 => RpcAtomicOp::CompareSwap(operand, swap),
+            op => return Err(format!("unknown atomic operation {}", op)),
+        };
+
+        Ok(RpcAtomicReq
+        {
+            op: op,
+            region_id: u32::from_be_bytes(body[1..5].try_into().unwrap()),
+            rkey: u32::from_be_bytes(body[5..9].try_into().unwrap()),
+            addr: u64::from_be_bytes(body[9..17].try_into().unwrap()),
+        })
+    }
+}
+
+// Outcome of an atomic request, the previous value of the word unless
+// the peer refused the request.
+// Layout, big endian: status(1) value(8)
+#[derive(Debug, PartialEq, Clone)]
+pub struct RpcAtomicResp
+{
+    pub refused: bool,
+    pub value: u64,
+}
+
+impl RpcAtomicResp
+{
+    pub fn write_to(&self, body: &mut [u8])
+    {
+        body[0] = self.refused as u8;
+        body[1..9].copy_from_slice(&self.value.to_be_bytes());
+    }
+
+    pub fn read_from(body: &[u8]) -> Result<RpcAtomicResp, std::string::String>
+    {
+        if body.len() < SRPC_ATOMIC_RESP_LEN as usize
+        {
+            return Err(format!("atomic response of {} bytes is too short", body.len()));
+        }
+
+        Ok(RpcAtomicResp
+        {
+            refused: body[0] != 0,
+            value: u64::from_be_bytes(body[1..9].try_into().unwrap()),
+        })
+    }
+}
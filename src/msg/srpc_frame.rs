@@ -0,0 +1,470 @@
+// Bytes at the front of each frame taken by the frame header.
+pub const SRPC_FRAME_HEADER_LEN: u32 = 22;
+// Bytes at the front of a fragment body taken by the fragment header.
+pub const SRPC_FRAG_HEADER_LEN: u32 = 12;
+// Bytes at the tail of a memory region taken by the length
+// of a version 1 frame.
+pub const SRPC_LEGACY_TRAILER_LEN: u32 = 2;
+
+// What the body of a frame holds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RpcFrameKind
+{
+    // A serialized RpcOnceMsg.
+    Msg = 1,
+    // Payload written by the application as is.
+    Raw = 2,
+    // Nothing, the frame only returns credits.
+    Credit = 3,
+    // Frames to the same session laid out back to back.
+    Batch = 4,
+    // A piece of a serialized RpcOnceMsg too large for one frame.
+    Frag = 5,
+    // Nothing, the frame hands the landing region back to the writer.
+    Release = 6,
+    // An atomic operation for the receiver to perform.
+    Atomic = 7,
+    // The outcome of an atomic operation.
+    AtomicResp = 8,
+}
+
+impl RpcFrameKind
+{
+    pub fn from_u8(kind: u8) -> Option<RpcFrameKind>
+    {
+        match kind
+        {
+            1 => Some(RpcFrameKind::Msg),
+            2 => Some(RpcFrameKind::Raw),
+            3 => Some(RpcFrameKind::Credit),
+            4 => Some(RpcFrameKind::Batch),
+            5 => Some(RpcFrameKind::Frag),
+            6 => Some(RpcFrameKind::Release),
+            7 => Some(RpcFrameKind::Atomic),
+            8 => Some(RpcFrameKind::AtomicResp),
+            _ => None,
+        }
+    }
+
+    // First protocol version carrying the kind. Version 1 frames are
+    // serialized messages, raw payloads and credits travel as such;
+    // version 4 added batches, fragments, landing and atomic frames.
+    pub fn since_version(&self) -> u32
+    {
+        match self
+        {
+            RpcFrameKind::Msg | RpcFrameKind::Raw | RpcFrameKind::Credit => 1,
+            _ => 4,
+        }
+    }
+
+    // Control frames carry no data, take no credit and land in
+    // the receive buffers kept out of the credits.
+    pub fn is_control(&self) -> bool
+    {
+        match self
+        {
+            RpcFrameKind::Credit | RpcFrameKind::Release => true,
+            _ => false,
+        }
+    }
+}
+
+// Fixed-size description of a frame, written at the front of the
+// memory region and followed by the body, so that only the bytes in
+// use are sent and the application is able to write the body in place.
+// Layout, big endian: kind(1) req_type(1) src_id(4) msg_id(8) credit(4) len(4)
+#[derive(Debug, PartialEq, Clone)]
+pub struct RpcFrameHeader
+{
+    pub kind: RpcFrameKind,
+    pub req_type: u8,
+    pub src_id: u32,
+    pub msg_id: u64,
+    pub credit: u32, // credits returned to the receiver
+    pub len: u32, // bytes of the body
+}
+
+impl RpcFrameHeader
+{
+    pub fn new(kind: RpcFrameKind) -> RpcFrameHeader
+    {
+        RpcFrameHeader
+        {
+            kind: kind,
+            req_type: 0,
+            src_id: 0,
+            msg_id: 0,
+            credit: 0,
+            len: 0,
+        }
+    }
+
+    // Bytes of the frame on the wire, header included.
+    pub fn frame_len(&self) -> usize
+    {
+        SRPC_FRAME_HEADER_LEN as usize + self.len as usize
+    }
+
+    // Writes the header at the front of the frame.
+    pub fn write_to(&self, frame: &mut [u8])
+    {
+        let header = &mut frame[..SRPC_FRAME_HEADER_LEN as usize];
+        header[0] = self.kind as u8;
+        header[1] = self.req_type;
+        header[2..6].copy_from_slice(&self.src_id.to_be_bytes());
+        header[6..14].copy_from_slice(&self.msg_id.to_be_bytes());
+        header[14..18].copy_from_slice(&self.credit.to_be_bytes());
+        header[18..22].copy_from_slice(&self.len.to_be_bytes());
+    }
+
+    // Reads the header at the front of the frame, `frame` being the 
+    // bytes received. Fails if the frame is malformed or truncated.
+    pub fn read_from(frame: &[u8]) -> Result<RpcFrameHeader, std::string::String>
+    {
+        if frame.len() < SRPC_FRAME_HEADER_LEN as usize
+        {
+            return Err(format!("frame of {} bytes is too short", frame.len()));
+        }
+
+        let header = &frame[..SRPC_FRAME_HEADER_LEN as usize];
+        let kind = RpcFrameKind::from_u8(header[0])
+            .ok_or(format!("unknown frame kind {}", header[0]))?;
+        let len = u32::from_be_bytes(header[18..22].try_into().unwrap());
+        let body_len = frame.len() - SRPC_FRAME_HEADER_LEN as usize;
+        if len as usize > body_len
+        {
+            return Err(format!("frame body of {} bytes exceeds {}", len, body_len));
+        }
+
+        Ok(RpcFrameHeader
+        {
+            kind: kind,
+            req_type: header[1],
+            src_id: u32::from_be_bytes(header[2..6].try_into().unwrap()),
+            msg_id: u64::from_be_bytes(header[6..14].try_into().unwrap()),
+            credit: u32::from_be_bytes(header[14..18].try_into().unwrap()),
+            len: len,
+        })
+    }
+
+    // The body of a frame whose header was read.
+    pub fn body_of<'a>(&self, frame: &'a [u8]) -> &'a [u8]
+    {
+        &frame[SRPC_FRAME_HEADER_LEN as usize..self.frame_len()]
+    }
+
+    // Writes the header at the tail of the frame, as version 2 does.
+    // Layout, big endian: len(4) credit(4) msg_id(8) src_id(4) req_type(1) kind(1)
+    pub fn write_trailer_to(&self, frame: &mut [u8])
+    {
+        let start = frame.len() - SRPC_FRAME_HEADER_LEN as usize;
+        let trailer = &mut frame[start..];
+        trailer[0..4].copy_from_slice(&self.len.to_be_bytes());
+        trailer[4..8].copy_from_slice(&self.credit.to_be_bytes());
+        trailer[8..16].copy_from_slice(&self.msg_id.to_be_bytes());
+        trailer[16..20].copy_from_slice(&self.src_id.to_be_bytes());
+        trailer[20] = self.req_type;
+        trailer[21] = self.kind as u8;
+    }
+
+    // Reads the header at the tail of a version 2 frame,
+    // fails if the frame is malformed.
+    pub fn read_trailer_from(frame: &[u8]) -> Result<RpcFrameHeader, std::string::String>
+    {
+        if frame.len() < SRPC_FRAME_HEADER_LEN as usize
+        {
+            return Err(format!("frame of {} bytes is too short", frame.len()));
+        }
+
+        let start = frame.len() - SRPC_FRAME_HEADER_LEN as usize;
+        let trailer = &frame[start..];
+        let kind = RpcFrameKind::from_u8(trailer[21])
+            .ok_or(format!("unknown frame kind {}", trailer[21]))?;
+        let len = u32::from_be_bytes(trailer[0..4].try_into().unwrap());
+        if len as usize > start
+        {
+            return Err(format!("frame body of {} bytes exceeds {}", len, start));
+        }
+
+        Ok(RpcFrameHeader
+        {
+            kind: kind,
+            req_type: trailer[20],
+            src_id: u32::from_be_bytes(trailer[16..20].try_into().unwrap()),
+            msg_id: u64::from_be_bytes(trailer[8..16].try_into().unwrap()),
+            credit: u32::from_be_bytes(trailer[4..8].try_into().unwrap()),
+            len: len,
+        })
+    }
+}
+
+// How frames are laid out in the memory regions of a session,
+// which depends on the protocol version negotiated.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RpcFrameLayout
+{
+    // Version 1: a serialized RpcOnceMsg carrying the credits itself,
+    // padded to the region with its length in the last 2 bytes.
+    Legacy,
+    // Version 2: the body padded to the region,
+    // with the header in the last bytes.
+    Trailer,
+    // Version 3 on: the header followed by the body,
+    // only the bytes in use are sent.
+    Header,
+}
+
+impl RpcFrameLayout
+{
+    pub fn of_version(version: u32) -> RpcFrameLayout
+    {
+        match version
+        {
+            1 => RpcFrameLayout::Legacy,
+            2 => RpcFrameLayout::Trailer,
+            _ => RpcFrameLayout::Header,
+        }
+    }
+
+    // Largest body fitting in a memory region of `mr_size` bytes.
+    pub fn max_body_len(&self, mr_size: u32) -> u32
+    {
+        match self
+        {
+            RpcFrameLayout::Legacy =>
+            {
+                // bounded by the width of the length
+                let max_len = mr_size.saturating_sub(SRPC_LEGACY_TRAILER_LEN);
+                std::cmp::min(max_len, u16::MAX as u32)
+            },
+            _ => mr_size.saturating_sub(SRPC_FRAME_HEADER_LEN),
+        }
+    }
+
+    // Where a body of `body_len` bytes lies in the memory region.
+    pub fn body_range(&self, body_len: usize) -> std::ops::Range<usize>
+    {
+        match self
+        {
+            RpcFrameLayout::Header =>
+            {
+                let start = SRPC_FRAME_HEADER_LEN as usize;
+                start..start + body_len
+            },
+            _ => 0..body_len,
+        }
+    }
+
+    // Bytes to send for a body of `body_len` bytes,
+    // padded frames take the whole region.
+    pub fn frame_len(&self, mr_size: usize, body_len: usize) -> usize
+    {
+        match self
+        {
+            RpcFrameLayout::Header => SRPC_FRAME_HEADER_LEN as usize + body_len,
+            _ => mr_size,
+        }
+    }
+
+    // Writes the header of a frame whose body is in place, `frame`
+    // being the whole memory region. A version 1 frame only keeps the
+    // length, the other fields are part of the serialized message.
+    pub fn write_header(&self, header: &RpcFrameHeader, frame: &mut [u8])
+    {
+        match self
+        {
+            RpcFrameLayout::Legacy =>
+            {
+                let start = frame.len() - SRPC_LEGACY_TRAILER_LEN as usize;
+                frame[start..].copy_from_slice(&(header.len as u16).to_be_bytes());
+            },
+            RpcFrameLayout::Trailer => header.write_trailer_to(frame),
+            RpcFrameLayout::Header => header.write_to(frame),
+        }
+    }
+
+    // Reads the header of a received frame, returns it along with the
+    // body. The header of a version 1 frame only tells the length,
+    // the other fields are read by deserializing the message.
+    pub fn read<'a>(&self, frame: &'a [u8]) -> Result<(RpcFrameHeader, &'a [u8]), std::string::String>
+    {
+        match self
+        {
+            RpcFrameLayout::Legacy =>
+            {
+                if frame.len() < SRPC_LEGACY_TRAILER_LEN as usize
+                {
+                    return Err(format!("frame of {} bytes is too short", frame.len()));
+                }
+                let start = frame.len() - SRPC_LEGACY_TRAILER_LEN as usize;
+                let len = u16::from_be_bytes([frame[start], frame[start + 1]]) as usize;
+                if len > start
+                {
+                    return Err(format!("frame body of {} bytes exceeds {}", len, start));
+                }
+
+                let mut header = RpcFrameHeader::new(RpcFrameKind::Msg);
+                header.len = len as u32;
+                Ok((header, &frame[..len]))
+            },
+            RpcFrameLayout::Trailer =>
+            {
+                let header = RpcFrameHeader::read_trailer_from(frame)?;
+                let len = header.len as usize;
+                Ok((header, &frame[..len]))
+            },
+            RpcFrameLayout::Header =>
+            {
+                let header = RpcFrameHeader::read_from(frame)?;
+                let body = header.body_of(frame);
+                Ok((header, body))
+            },
+        }
+    }
+}
+
+// Where the piece carried by a fragment frame goes, written at the
+// front of its body. Layout, big endian: total(4) offset(4) xfer_id(4)
+#[derive(Debug, PartialEq, Clone)]
+pub struct RpcFragHeader
+{
+    pub total: u32, // bytes of the whole message
+    pub offset: u32, // of the piece in the message
+    pub xfer_id: u32, // of the message among those the session sent in fragments
+}
+
+impl RpcFragHeader
+{
+    pub fn write_to(&self, body: &mut [u8])
+    {
+        body[0..4].copy_from_slice(&self.total.to_be_bytes());
+        body[4..8].copy_from_slice(&self.offset.to_be_bytes());
+        body[8..12].copy_from_slice(&self.xfer_id.to_be_bytes());
+    }
+
+    // Reads the header of a fragment body, returns it along with
+    // the piece it carries.
+    pub fn read_from(body: &[u8]) -> Result<(RpcFragHeader, &[u8]), std::string::String>
+    {
+        if body.len() < SRPC_FRAG_HEADER_LEN as usize
+        {
+            return Err(format!("fragment of {} bytes is too short", body.len()));
+        }
+
+        let header = RpcFragHeader
+        {
+            total: u32::from_be_bytes(body[0..4].try_into().unwrap()),
+            offset: u32::from_be_bytes(body[4..8].try_into().unwrap()),
+            xfer_id: u32::from_be_bytes(body[8..12].try_into().unwrap()),
+        };
+        Ok((header, &body[SRPC_FRAG_HEADER_LEN as usize..]))
+    }
+}
+
+// A received raw frame lent to its handler, the body stays in the
+// registered memory region and is only valid during the call.
+#[derive(Debug)]
+pub struct RpcRecvView<'a>
+{
+    pub session_id: u32,
+    pub peer_id: u32,
+    pub req_type: u8,
+    pub src_id: u32,
+    pub msg_id: u64,
+    pub data: &'a [u8],
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const MR_SIZE: usize = 64;
+
+    fn header(kind: RpcFrameKind, len: u32) -> RpcFrameHeader
+    {
+        RpcFrameHeader
+        {
+            kind: kind,
+            req_type: 7,
+            src_id: 3,
+            msg_id: 1 << 40,
+            credit: 5,
+            len: len,
+        }
+    }
+
+    // Lays the frame out in a region, then reads back
+    // the bytes that would have been received.
+    fn round_trip(layout: RpcFrameLayout, header: &RpcFrameHeader, body: &[u8]) -> (RpcFrameHeader, Vec<u8>)
+    {
+        let mut frame = vec![0xaau8; MR_SIZE];
+        frame[layout.body_range(body.len())].copy_from_slice(body);
+        layout.write_header(header, &mut frame);
+        let frame_len = layout.frame_len(MR_SIZE, body.len());
+
+        let (header, body) = layout.read(&frame[..frame_len]).unwrap();
+        (header, body.to_vec())
+    }
+
+    #[test]
+    fn versions_map_to_layouts()
+    {
+        assert_eq!(RpcFrameLayout::of_version(1), RpcFrameLayout::Legacy);
+        assert_eq!(RpcFrameLayout::of_version(2), RpcFrameLayout::Trailer);
+        assert_eq!(RpcFrameLayout::of_version(3), RpcFrameLayout::Header);
+        assert_eq!(RpcFrameLayout::of_version(4), RpcFrameLayout::Header);
+    }
+
+    #[test]
+    fn batches_need_version_4()
+    {
+        assert_eq!(RpcFrameKind::Raw.since_version(), 1);
+        assert_eq!(RpcFrameKind::Credit.since_version(), 1);
+        assert_eq!(RpcFrameKind::Batch.since_version(), 4);
+        assert_eq!(RpcFrameKind::AtomicResp.since_version(), 4);
+    }
+
+    #[test]
+    fn header_layout_sends_only_the_bytes_in_use()
+    {
+        let sent = header(RpcFrameKind::Raw, 4);
+        assert_eq!(RpcFrameLayout::Header.frame_len(MR_SIZE, 4), 26);
+
+        let (received, body) = round_trip(RpcFrameLayout::Header, &sent, b"abcd");
+        assert_eq!(received, sent);
+        assert_eq!(body, b"abcd");
+    }
+
+    #[test]
+    fn trailer_layout_pads_to_the_region()
+    {
+        let sent = header(RpcFrameKind::Msg, 4);
+        assert_eq!(RpcFrameLayout::Trailer.frame_len(MR_SIZE, 4), MR_SIZE);
+
+        let (received, body) = round_trip(RpcFrameLayout::Trailer, &sent, b"abcd");
+        assert_eq!(received, sent);
+        assert_eq!(body, b"abcd");
+    }
+
+    #[test]
+    fn legacy_layout_keeps_only_the_length()
+    {
+        let sent = header(RpcFrameKind::Msg, 4);
+        let (received, body) = round_trip(RpcFrameLayout::Legacy, &sent, b"abcd");
+        assert_eq!(received, RpcFrameHeader { len: 4, ..RpcFrameHeader::new(RpcFrameKind::Msg) });
+        assert_eq!(body, b"abcd");
+        assert_eq!(RpcFrameLayout::Legacy.max_body_len(1 << 20), u16::MAX as u32);
+    }
+
+    #[test]
+    fn truncated_frames_are_refused()
+    {
+        let mut frame = vec![0u8; MR_SIZE];
+        header(RpcFrameKind::Msg, MR_SIZE as u32).write_trailer_to(&mut frame);
+        assert!(RpcFrameLayout::Trailer.read(&frame).is_err());
+        assert!(RpcFrameLayout::Header.read(&frame[..10]).is_err());
+        assert!(RpcFrameLayout::Legacy.read(&[0xff, 0xff]).is_err());
+    }
+}
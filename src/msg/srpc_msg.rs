@@ -78,6 +78,8 @@ pub struct RpcOnceMsg
     pub src_id: u32, // optional 
     pub msg_id: u64, // bound to the client node 
     pub payload: RpcMsgPayload,
+    #[serde(default)]
+    pub credit: u32, // credits returned to the receiver, protocol version 1 
 }
 
 impl RpcOnceMsg
@@ -90,6 +92,7 @@ impl RpcOnceMsg
             src_id: 0,
             msg_id: 0,
             payload: RpcMsgPayload::default(),
+            credit: 0,
         }
     }
 }
@@ -2,6 +2,7 @@
 use secrpc::core::srpc_core::RpcCore;
 
 use secrpc::{msg::srpc_msg::{RpcMsgHandle, RpcOnceMsg}, core::srpc_core::RPC_DISPATCHER, conf::conf::RpcConf};
+use secrpc::core::network::srpc_device::list_devices;
 use tracing::{info, Level, trace};
 use tracing_subscriber::{FmtSubscriber};
 
@@ -29,6 +30,17 @@ async fn main() {
 
     RpcConf::print_conf();
 
+    if RpcConf::get_conf().list_devices {
+        match list_devices() {
+            Ok(devices) => devices.iter().for_each(|device| print!("{}", device)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(exitcode::UNAVAILABLE);
+            }
+        }
+        std::process::exit(exitcode::OK);
+    }
+
     let rpc_core = RpcCore::new();
     assert!(RPC_DISPATCHER.get().is_some());
     let _reg_result = 
@@ -38,32 +50,34 @@ async fn main() {
         );
     let _result = rpc_core.start();
 
+    // a node given no peer only serves the ones connecting to it 
     let conf = RpcConf::get_conf();
-    let rmt_grpc_uri = conf.rmt_grpc_uri
-        .get(0).unwrap(); 
-    let peer_id = 1;
-
-    let _session_id = 
-        rpc_core.dispatcher.connect_to(peer_id, rmt_grpc_uri).await;
+    for (rmt_grpc_uri, peer_id) in conf.rmt_grpc_uri.iter().zip(conf.rmt_ids.iter()) {
+        let peer_id = *peer_id;
+        if let Err(e) = 
+            rpc_core.dispatcher.connect_pool_to(peer_id, rmt_grpc_uri).await 
+        {
+            eprintln!("failed to connect to peer {} at {}: {}", peer_id, rmt_grpc_uri, e);
+            std::process::exit(exitcode::UNAVAILABLE);
+        }
 
-    // sleep for 1 second(s)
-    // for better trace log (optional) 
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        // sleep for 1 second(s)
+        // for better trace log (optional) 
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
-    let mut req = RpcMsgHandle::default();
-    let mut msg = RpcOnceMsg::default();
-    msg.req_type = 1;
-    let raw_str = "hello"; 
-    msg.payload.msg_data = raw_str.as_bytes().to_vec();
-    req.set_msg(msg);
-    req.peer_id = peer_id;
+        let mut req = RpcMsgHandle::default();
+        let mut msg = RpcOnceMsg::default();
+        msg.req_type = 1;
+        let raw_str = "hello"; 
+        msg.payload.msg_data = raw_str.as_bytes().to_vec();
+        req.set_msg(msg);
+        req.peer_id = peer_id;
 
-    // push request 
-    rpc_core.dispatcher.push_req(req.clone());  
-
-    loop {
-        // run event loop
-        rpc_core.dispatcher.run_loop_once();
+        // push request 
+        rpc_core.dispatcher.push_req(req.clone());  
     }
 
+    // run event loop
+    rpc_core.dispatcher.run_loop().await;
+
 }